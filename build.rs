@@ -1,5 +1,49 @@
 extern crate configure_me_codegen;
 
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//Everything under here is built into the binary
+const WEBROOT: &str = "webroot";
+
 fn main() -> Result<(), configure_me_codegen::Error> {
+    embed_webroot().expect("Unable to embed webroot");
     configure_me_codegen::build_script_auto()
-}
\ No newline at end of file
+}
+
+//Write out the list of webroot files for src/assets.rs to include, each named by its path under webroot
+fn embed_webroot() -> io::Result<()> {
+    let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default());
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").unwrap_or_default());
+
+    let mut files = Vec::new();
+    list_files(Path::new(WEBROOT), &mut files)?;
+    files.sort();
+
+    let mut code = String::from("static EMBEDDED_FILES: &[(&str, &[u8])] = &[\n");
+    for file in files {
+        let name = file.strip_prefix(WEBROOT).unwrap_or(&file).to_string_lossy().replace('\\', "/");
+        code.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", name, manifest_dir.join(&file)));
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+    code.push_str("];\n");
+
+    fs::write(out_dir.join("webroot.rs"), code)
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    //Picks up files being added or removed
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
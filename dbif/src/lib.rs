@@ -1,10 +1,58 @@
-use rusqlite::{params, Connection};
+use log::{debug, error, info};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
 use std::error::Error;
 use std::fmt;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::os::unix::fs::PermissionsExt;
 
+//Names the poller's checkpoints are stored under
+const CHECKPOINT_INVOICE_ADD_INDEX: &str = "invoice_add_index";
+const CHECKPOINT_INVOICE_SETTLE_INDEX: &str = "invoice_settle_index";
+const CHECKPOINT_PAYMENT_INDEX: &str = "payment_index";
+
+//What to do when a boost being stored is already there.  The poller leaves it be, while reindexing refreshes
+//everything parsed from LND but keeps what's been done with it since, like replying
+const BOOST_KEEP_EXISTING: &str = "DO NOTHING";
+const BOOST_REFRESH_EXISTING: &str = "DO UPDATE SET
+    time = excluded.time,
+    value_msat = excluded.value_msat,
+    value_msat_total = excluded.value_msat_total,
+    action = excluded.action,
+    sender = excluded.sender,
+    app = excluded.app,
+    message = excluded.message,
+    podcast = excluded.podcast,
+    episode = excluded.episode,
+    tlv = excluded.tlv,
+    remote_podcast = COALESCE(excluded.remote_podcast, remote_podcast),
+    remote_episode = COALESCE(excluded.remote_episode, remote_episode),
+    source = excluded.source,
+    destination = excluded.destination";
+
+const PAYMENT_KEEP_EXISTING: &str = "DO UPDATE SET
+    reply_to_idx = COALESCE(reply_to_idx, excluded.reply_to_idx)";
+const PAYMENT_REFRESH_EXISTING: &str = "DO UPDATE SET
+    time = excluded.time,
+    value_msat = excluded.value_msat,
+    value_msat_total = excluded.value_msat_total,
+    action = excluded.action,
+    sender = excluded.sender,
+    app = excluded.app,
+    message = excluded.message,
+    podcast = excluded.podcast,
+    episode = excluded.episode,
+    tlv = excluded.tlv,
+    remote_podcast = COALESCE(excluded.remote_podcast, remote_podcast),
+    remote_episode = COALESCE(excluded.remote_episode, remote_episode),
+    payment_hash = excluded.payment_hash,
+    payment_pubkey = excluded.payment_pubkey,
+    payment_custom_key = excluded.payment_custom_key,
+    payment_custom_value = excluded.payment_custom_value,
+    payment_fee_msat = excluded.payment_fee_msat,
+    reply_to_idx = COALESCE(reply_to_idx, excluded.reply_to_idx),
+    source = excluded.source";
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NodeInfoRecord {
     pub lnd_alias: String,
@@ -12,6 +60,29 @@ pub struct NodeInfoRecord {
     pub node_version: String,
 }
 
+//How far the poller has got through LND's invoices and payments
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Checkpoints {
+    pub invoice_add_index: u64,
+    pub invoice_settle_index: u64,
+    pub payment_index: u64,
+}
+
+//Totals over everything stored, for reporting
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StatsRecord {
+    pub boosts: u64,
+    pub boost_msat: i64,
+    pub streams: u64,
+    pub stream_msat: i64,
+    pub sent_boosts: u64,
+    pub sent_msat: i64,
+    pub sent_fee_msat: i64,
+    pub first_boost_time: Option<i64>,
+    pub last_boost_time: Option<i64>,
+    pub wallet_balance: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BoostRecord {
     pub index: u64,
@@ -28,6 +99,8 @@ pub struct BoostRecord {
     pub remote_podcast: Option<String>,
     pub remote_episode: Option<String>,
     pub reply_sent: bool,
+    pub source: String,
+    pub destination: Option<String>,
     pub payment_info: Option<PaymentRecord>,
 }
 
@@ -46,6 +119,16 @@ impl BoostRecord {
     pub fn parse_tlv(&self) -> Result<Value, Box<dyn Error>> {
         return Ok(serde_json::from_str(self.tlv.as_str())?);
     }
+
+    //The name of the action code
+    pub fn action_name(&self) -> &'static str {
+        match self.action {
+            1 => "stream",
+            2 => "boost",
+            4 => "auto",
+            _ => "unknown",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -58,6 +141,94 @@ pub struct PaymentRecord {
     pub reply_to_idx: Option<u64>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LnurlInvoiceRecord {
+    pub payment_hash: String,
+    pub add_index: u64,
+    pub name: String,
+    pub value_msat: i64,
+    pub comment: String,
+    pub payer_data: Option<String>,
+    pub created: i64,
+    pub expires: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DestinationRecord {
+    pub name: String,
+    pub custom_key: Option<u64>,
+    pub custom_value: Option<String>,
+    pub podcast_guid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserRecord {
+    pub id: u64,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: String,
+    pub destinations: Vec<String>,
+    pub disabled: bool,
+    pub created: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct JwtKeyRecord {
+    pub id: u64,
+    pub secret: String,
+    pub created: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionRecord {
+    pub id: String,
+    pub user_id: u64,
+    pub username: String,
+    pub user_agent: String,
+    pub created: i64,
+    pub last_seen: i64,
+    pub revoked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiTokenRecord {
+    pub id: u64,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scope: String,
+    pub created: i64,
+    pub last_used: Option<i64>,
+    pub revoked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginAttemptRecord {
+    pub index: u64,
+    pub time: i64,
+    pub remote_ip: String,
+    pub username: String,
+    pub success: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TotpRecord {
+    pub user_id: u64,
+    pub secret: String,
+    pub enabled: bool,
+    pub last_step: i64,
+    pub created: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoveryCodeRecord {
+    pub index: u64,
+    pub user_id: u64,
+    pub code_hash: String,
+}
+
 #[derive(Debug)]
 struct HydraError(String);
 impl fmt::Display for HydraError {
@@ -75,10 +246,10 @@ fn connect_to_database(init: bool, filepath: &String) -> Result<Connection, Box<
             match set_database_file_permissions(filepath.as_str()) {
                 Ok(_) => {},
                 Err(e) => {
-                    eprintln!("{:#?}", e);
+                    error!("{:?}", e);
                 }
             }
-            println!("Using database file: [{}]", filepath.as_str());
+            info!("Using database file: [{}]", filepath.as_str());
         }
         Ok(conn)
     } else {
@@ -96,7 +267,7 @@ fn set_database_file_permissions(filepath: &str) -> Result<bool, Box<dyn Error>>
                 Ok(metadata) => {
                     let mut perms = metadata.permissions();
                     perms.set_mode(0o666);
-                    println!("Set file permission to: [666] on database file: [{}]", filepath);
+                    debug!("Set file permission to: [666] on database file: [{}]", filepath);
                     Ok(true)
                 },
                 Err(e) => {
@@ -111,6 +282,28 @@ fn set_database_file_permissions(filepath: &str) -> Result<bool, Box<dyn Error>>
 }
 
 
+//Builds the sql condition and named parameters limiting a query to the given destinations
+fn destination_filter(destinations: Option<&[String]>) -> (String, Vec<(String, String)>) {
+    let list = match destinations {
+        Some(list) => list,
+        None => return ("".to_string(), Vec::new()),
+    };
+
+    if list.is_empty() {
+        return ("AND 0".to_string(), Vec::new());
+    }
+
+    let params: Vec<(String, String)> = list.iter()
+        .enumerate()
+        .map(|(i, dest)| (format!(":destination{}", i), dest.clone()))
+        .collect();
+
+    let names: Vec<&str> = params.iter().map(|(name, _)| name.as_str()).collect();
+
+    (format!("AND destination IN ({})", names.join(", ")), params)
+}
+
+
 //Create or update a new database file if needed
 pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
     let conn = connect_to_database(true, filepath)?;
@@ -135,10 +328,10 @@ pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
         [],
     ) {
         Ok(_) => {
-            println!("Boosts table is ready.");
+            debug!("Boosts table is ready.");
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError(format!("Failed to create database boosts table: [{}].", filepath).into())))
         }
     }
@@ -146,21 +339,35 @@ pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
     //Add additional columns to existing installs
     match conn.execute("ALTER TABLE boosts ADD COLUMN remote_podcast text", []) {
         Ok(_) => {
-            println!("Boosts remote podcast column added.");
+            info!("Boosts remote podcast column added.");
         }
         Err(_) => {}
     }
 
     match conn.execute("ALTER TABLE boosts ADD COLUMN remote_episode text", []) {
         Ok(_) => {
-            println!("Boosts remote episode column added.");
+            info!("Boosts remote episode column added.");
         }
         Err(_) => {}
     }
 
     match conn.execute("ALTER TABLE boosts ADD COLUMN reply_sent integer", []) {
         Ok(_) => {
-            println!("Boosts reply sent column added.");
+            info!("Boosts reply sent column added.");
+        }
+        Err(_) => {}
+    }
+
+    match conn.execute("ALTER TABLE boosts ADD COLUMN source text", []) {
+        Ok(_) => {
+            info!("Boosts source column added.");
+        }
+        Err(_) => {}
+    }
+
+    match conn.execute("ALTER TABLE boosts ADD COLUMN destination text", []) {
+        Ok(_) => {
+            info!("Boosts destination column added.");
         }
         Err(_) => {}
     }
@@ -208,10 +415,10 @@ pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
         [],
     ) {
         Ok(_) => {
-            println!("Node info table is ready.");
+            debug!("Node info table is ready.");
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError(format!("Failed to create database node_info table: [{}].", filepath).into())))
         }
     }
@@ -242,14 +449,303 @@ pub fn create_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
         [],
     ) {
         Ok(_) => {
-            println!("Sent boosts table is ready.");
+            debug!("Sent boosts table is ready.");
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError(format!("Failed to create database sent_boosts table: [{}].", filepath).into())))
         }
     }
 
+    match conn.execute("ALTER TABLE sent_boosts ADD COLUMN source text", []) {
+        Ok(_) => {
+            info!("Sent boosts source column added.");
+        }
+        Err(_) => {}
+    }
+
+    //Create the destinations table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS destinations (
+             name text primary key,
+             custom_key integer,
+             custom_value text,
+             podcast_guid text
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Destinations table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database destinations table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the users table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+             idx integer primary key autoincrement,
+             username text unique,
+             password_hash text,
+             role text,
+             destinations text,
+             disabled integer default 0,
+             created integer
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Users table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database users table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the jwt signing keys table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS jwt_keys (
+             idx integer primary key autoincrement,
+             secret text,
+             created integer
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("JWT keys table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database jwt_keys table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the login sessions table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+             id text primary key,
+             user_id integer,
+             username text,
+             user_agent text,
+             created integer,
+             last_seen integer,
+             revoked integer default 0
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Sessions table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database sessions table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the api tokens table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS api_tokens (
+             idx integer primary key autoincrement,
+             name text,
+             token_hash text unique,
+             scope text,
+             created integer,
+             last_used integer,
+             revoked integer default 0
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("API tokens table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database api_tokens table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the login attempts (audit log) table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS login_attempts (
+             idx integer primary key autoincrement,
+             time integer,
+             remote_ip text,
+             username text,
+             success integer,
+             reason text
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Login attempts table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database login_attempts table: [{}].", filepath).into())))
+        }
+    }
+
+    if conn.execute("CREATE INDEX IF NOT EXISTS login_attempts_remote_ip ON login_attempts (remote_ip, time)", []).is_ok() {
+        debug!("Login attempts index is ready.");
+    }
+
+    //Create the two-factor (totp) secrets table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS totp (
+             user_id integer primary key,
+             secret text,
+             enabled integer default 0,
+             last_step integer default 0,
+             created integer
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("TOTP table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database totp table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the two-factor recovery codes table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS recovery_codes (
+             idx integer primary key autoincrement,
+             user_id integer,
+             code_hash text,
+             used integer default 0
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Recovery codes table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database recovery_codes table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the lnurl invoices table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS lnurl_invoices (
+             payment_hash text primary key,
+             add_index integer,
+             name text,
+             value_msat integer,
+             comment text,
+             payer_data text,
+             created integer,
+             expires integer,
+             settled integer default 0
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("LNURL invoices table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database lnurl_invoices table: [{}].", filepath).into())))
+        }
+    }
+
+    //Create the poller checkpoints table
+    match conn.execute(
+        "CREATE TABLE IF NOT EXISTS checkpoints (
+             name text primary key,
+             value integer not null
+         )",
+        [],
+    ) {
+        Ok(_) => {
+            debug!("Checkpoints table is ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to create database checkpoints table: [{}].", filepath).into())))
+        }
+    }
+
+    //Existing installs start from the newest stored boost and payment, like the poller used to
+    match conn.execute_batch(&format!(
+        "INSERT OR IGNORE INTO checkpoints (name, value) SELECT '{}', COALESCE(MAX(idx), 0) FROM boosts;
+         INSERT OR IGNORE INTO checkpoints (name, value) VALUES ('{}', 0);
+         INSERT OR IGNORE INTO checkpoints (name, value) SELECT '{}', COALESCE(MAX(idx), 0) FROM sent_boosts;",
+        CHECKPOINT_INVOICE_ADD_INDEX, CHECKPOINT_INVOICE_SETTLE_INDEX, CHECKPOINT_PAYMENT_INDEX,
+    )) {
+        Ok(_) => {
+            debug!("Checkpoints are ready.");
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(Box::new(HydraError(format!("Failed to set starting checkpoints: [{}].", filepath).into())))
+        }
+    }
+
+    Ok(true)
+}
+
+//Make sure the database can still be written to by taking and releasing the write lock
+pub fn check_database_writable(filepath: &String) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")?;
+    Ok(true)
+}
+
+//Tidy up the database on the way out, making sure nothing is left open
+pub fn close_database(filepath: &String) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    conn.execute_batch("PRAGMA optimize;")?;
+    conn.close().map_err(|(_, e)| e)?;
+    Ok(true)
+}
+
+//Get/Set how far the poller got through LND's invoices and payments
+pub fn get_checkpoints_from_db(filepath: &String) -> Result<Checkpoints, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut checkpoints = Checkpoints::default();
+
+    let mut stmt = conn.prepare("SELECT name, value FROM checkpoints")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))?;
+
+    for row in rows {
+        let (name, value) = row?;
+        match name.as_str() {
+            CHECKPOINT_INVOICE_ADD_INDEX => checkpoints.invoice_add_index = value,
+            CHECKPOINT_INVOICE_SETTLE_INDEX => checkpoints.invoice_settle_index = value,
+            CHECKPOINT_PAYMENT_INDEX => checkpoints.payment_index = value,
+            _ => {}
+        }
+    }
+
+    Ok(checkpoints)
+}
+
+pub fn set_checkpoints_in_db(filepath: &String, checkpoints: &Checkpoints) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    set_checkpoint(&tx, CHECKPOINT_INVOICE_ADD_INDEX, checkpoints.invoice_add_index)?;
+    set_checkpoint(&tx, CHECKPOINT_INVOICE_SETTLE_INDEX, checkpoints.invoice_settle_index)?;
+    set_checkpoint(&tx, CHECKPOINT_PAYMENT_INDEX, checkpoints.payment_index)?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+fn set_checkpoint(conn: &Connection, name: &str, value: u64) -> Result<bool, Box<dyn Error>> {
+    conn.execute(
+        "INSERT INTO checkpoints (name, value) VALUES (?1, ?2) \
+         ON CONFLICT(name) DO UPDATE SET value = ?2",
+        params![name, value],
+    )?;
+
     Ok(true)
 }
 
@@ -313,7 +809,7 @@ pub fn add_node_info_to_db(filepath: &String, info: NodeInfoRecord) -> Result<bo
             Ok(true)
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError("Failed to add node info".into())))
         }
     }
@@ -322,9 +818,58 @@ pub fn add_node_info_to_db(filepath: &String, info: NodeInfoRecord) -> Result<bo
 //Add an invoice to the database
 pub fn add_invoice_to_db(filepath: &String, boost: BoostRecord) -> Result<bool, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
+    insert_boost(&conn, &boost, BOOST_KEEP_EXISTING)
+}
+
+//Add a batch of boosts the poller found, moving its invoice checkpoints past them in the same transaction.
+//Boosts that are already stored are left alone
+pub fn add_invoices_to_db(filepath: &String, boosts: &[BoostRecord], add_index: u64, settle_index: u64) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    for boost in boosts {
+        insert_boost(&tx, boost, BOOST_KEEP_EXISTING)?;
+    }
+
+    set_checkpoint(&tx, CHECKPOINT_INVOICE_ADD_INDEX, add_index)?;
+    set_checkpoint(&tx, CHECKPOINT_INVOICE_SETTLE_INDEX, settle_index)?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+//Store boosts parsed again from LND's history, updating the ones already there
+pub fn reindex_invoices_in_db(filepath: &String, boosts: &[BoostRecord]) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    for boost in boosts {
+        insert_boost(&tx, boost, BOOST_REFRESH_EXISTING)?;
+    }
+
+    tx.commit()?;
+    Ok(true)
+}
 
-    match conn.execute("INSERT INTO boosts (idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv, remote_podcast, remote_episode, reply_sent) \
-                                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+//Store boosts brought in from an export, leaving the ones already there alone.  Returns how many were new
+pub fn import_boosts_to_db(filepath: &String, boosts: &[BoostRecord]) -> Result<u64, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+    let before: u64 = tx.query_row("SELECT COUNT(*) FROM boosts", [], |row| row.get(0))?;
+
+    for boost in boosts {
+        insert_boost(&tx, boost, BOOST_KEEP_EXISTING)?;
+    }
+
+    let after: u64 = tx.query_row("SELECT COUNT(*) FROM boosts", [], |row| row.get(0))?;
+    tx.commit()?;
+    Ok(after - before)
+}
+
+fn insert_boost(conn: &Connection, boost: &BoostRecord, on_conflict: &str) -> Result<bool, Box<dyn Error>> {
+    match conn.execute(&format!("INSERT INTO boosts (idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv, remote_podcast, remote_episode, reply_sent, source, destination) \
+                                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16) \
+                                        ON CONFLICT(idx) {}", on_conflict),
                        params![boost.index,
                                        boost.time,
                                        boost.value_msat,
@@ -338,13 +883,15 @@ pub fn add_invoice_to_db(filepath: &String, boost: BoostRecord) -> Result<bool,
                                        boost.tlv,
                                        boost.remote_podcast,
                                        boost.remote_episode,
-                                       boost.reply_sent]
+                                       boost.reply_sent,
+                                       boost.source,
+                                       boost.destination]
     ) {
         Ok(_) => {
             Ok(true)
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError(format!("Failed to add boost: [{}].", boost.index).into())))
         }
     }
@@ -358,7 +905,7 @@ pub fn mark_boost_as_replied(filepath: &String, index: u64) -> Result<bool, Box<
 }
 
 //Get all of the boosts from the database
-pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
+pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool, destinations: Option<&[String]>) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
     let mut boosts: Vec<BoostRecord> = Vec::new();
 
@@ -367,6 +914,9 @@ pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bo
         ltgt = "<=";
     }
 
+    //Only include boosts sent to these destinations if given
+    let (dest_filter, dest_params) = destination_filter(destinations);
+
     //Query for boosts and automated boosts
     let sqltxt = format!("SELECT idx, \
                                        time, \
@@ -381,16 +931,24 @@ pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bo
                                        tlv, \
                                        remote_podcast, \
                                        remote_episode, \
-                                       reply_sent \
+                                       reply_sent, \
+                                       source, \
+                                       destination \
                                  FROM boosts \
                                  WHERE action IN (2, 4) \
                                    AND idx {} :index \
+                                   {} \
                                  ORDER BY idx DESC \
-                                 LIMIT :max", ltgt);
+                                 LIMIT :max", ltgt, dest_filter);
 
     //Prepare and execute the query
     let mut stmt = conn.prepare(sqltxt.as_str())?;
-    let rows = stmt.query_map(&[(":index", index.to_string().as_str()), (":max", max.to_string().as_str())], |row| {
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![(":index", &index), (":max", &max)];
+    for (name, value) in &dest_params {
+        params.push((name.as_str(), value));
+    }
+
+    let rows = stmt.query_map(&params[..], |row| {
         Ok(BoostRecord {
             index: row.get(0)?,
             time: row.get(1)?,
@@ -406,13 +964,15 @@ pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bo
             remote_podcast: row.get(11).ok(),
             remote_episode: row.get(12).ok(),
             reply_sent: row.get(13).unwrap_or(false),
+            source: row.get(14).unwrap_or_else(|_| "keysend".to_string()),
+            destination: row.get(15).ok(),
             payment_info: None,
         })
-    }).unwrap();
+    })?;
 
     //Parse the results
     for row in rows {
-        let boost: BoostRecord = row.unwrap();
+        let boost: BoostRecord = row?;
 
         //Some things like text output don't need to be html entity escaped
         //so only do it if asked for
@@ -446,7 +1006,7 @@ pub fn get_boosts_from_db(filepath: &String, index: u64, max: u64, direction: bo
 
 
 //Get all of the boosts from the database
-pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
+pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool, destinations: Option<&[String]>) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
     let mut boosts: Vec<BoostRecord> = Vec::new();
 
@@ -456,6 +1016,9 @@ pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: b
         ltgt = "<=";
     }
 
+    //Only include streams sent to these destinations if given
+    let (dest_filter, dest_params) = destination_filter(destinations);
+
     //Build the query
     let sqltxt = format!("SELECT idx, \
                                        time, \
@@ -470,16 +1033,24 @@ pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: b
                                        tlv, \
                                        remote_podcast, \
                                        remote_episode, \
-                                       reply_sent \
+                                       reply_sent, \
+                                       source, \
+                                       destination \
                                  FROM boosts \
                                  WHERE action = 1 \
                                    AND idx {} :index \
+                                   {} \
                                  ORDER BY idx DESC \
-                                 LIMIT :max", ltgt);
+                                 LIMIT :max", ltgt, dest_filter);
 
     //Prepare and execute the query
     let mut stmt = conn.prepare(sqltxt.as_str())?;
-    let rows = stmt.query_map(&[(":index", index.to_string().as_str()), (":max", max.to_string().as_str())], |row| {
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![(":index", &index), (":max", &max)];
+    for (name, value) in &dest_params {
+        params.push((name.as_str(), value));
+    }
+
+    let rows = stmt.query_map(&params[..], |row| {
         Ok(BoostRecord {
             index: row.get(0)?,
             time: row.get(1)?,
@@ -495,13 +1066,15 @@ pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: b
             remote_podcast: row.get(11).ok(),
             remote_episode: row.get(12).ok(),
             reply_sent: row.get(13).unwrap_or(false),
+            source: row.get(14).unwrap_or_else(|_| "keysend".to_string()),
+            destination: row.get(15).ok(),
             payment_info: None,
         })
-    }).unwrap();
+    })?;
 
     //Parse the results
     for row in rows {
-        let boost: BoostRecord = row.unwrap();
+        let boost: BoostRecord = row?;
 
         //Some things like text output don't need to be html entity escaped
         //so only do it if asked for
@@ -536,41 +1109,12 @@ pub fn get_streams_from_db(filepath: &String, index: u64, max: u64, direction: b
 //Get the last boost index number from the database
 pub fn get_last_boost_index_from_db(filepath: &String) -> Result<u64, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
-    let mut boosts: Vec<BoostRecord> = Vec::new();
-    let max = 1;
 
-    //Prepare and execute the query
-    let mut stmt = conn.prepare("SELECT idx, time, value_msat, value_msat_total, action, sender, app, message, podcast, episode, tlv, remote_podcast, remote_episode, reply_sent \
-                                 FROM boosts \
-                                 ORDER BY idx DESC LIMIT :max")?;
-    let rows = stmt.query_map(&[(":max", max.to_string().as_str())], |row| {
-        Ok(BoostRecord {
-            index: row.get(0)?,
-            time: row.get(1)?,
-            value_msat: row.get(2)?,
-            value_msat_total: row.get(3)?,
-            action: row.get(4)?,
-            sender: row.get(5)?,
-            app: row.get(6)?,
-            message: row.get(7)?,
-            podcast: row.get(8)?,
-            episode: row.get(9)?,
-            tlv: row.get(10)?,
-            remote_podcast: row.get(11).ok(),
-            remote_episode: row.get(12).ok(),
-            reply_sent: row.get(13).unwrap_or(false),
-            payment_info: None,
-        })
-    }).unwrap();
-
-    //Parse the results
-    for row in rows {
-        let boost: BoostRecord = row.unwrap();
-        boosts.push(boost);
-    }
+    let mut stmt = conn.prepare("SELECT MAX(idx) FROM boosts")?;
+    let index = stmt.query_row([], |row| row.get(0))?;
 
-    if boosts.len() > 0 {
-        return Ok(boosts[0].index)
+    if let Some(idx) = index {
+        return Ok(idx);
     }
 
     Ok(0)
@@ -589,7 +1133,7 @@ pub fn add_wallet_balance_to_db(filepath: &String, balance: i64) -> Result<bool,
             Ok(true)
         }
         Err(e) => {
-            eprintln!("{}", e);
+            error!("{}", e);
             return Err(Box::new(HydraError(format!("Failed to update wallet balance in database: [{}].", balance).into())))
         }
     }
@@ -612,14 +1156,57 @@ pub fn get_wallet_balance_from_db(filepath: &String) -> Result<i64, Box<dyn Erro
     Ok(info[0])
 }
 
-//Get all of the sent boosts from the database
-pub fn get_payments_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
+//Count up everything received and sent.  Limited to some destinations, only the replies to their boosts count as
+//sent and the node's balance is left out
+pub fn get_stats_from_db(filepath: &String, destinations: Option<&[String]>) -> Result<StatsRecord, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
-    let mut boosts: Vec<BoostRecord> = Vec::new();
+    let mut stats = StatsRecord::default();
 
-    let mut ltgt = ">=";
-    if direction {
-        ltgt = "<=";
+    let (dest_filter, dest_params) = destination_filter(destinations);
+    let params: Vec<(&str, &dyn ToSql)> = dest_params.iter()
+        .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+        .collect();
+
+    (stats.boosts, stats.boost_msat, stats.first_boost_time, stats.last_boost_time) = conn.query_row(
+        &format!("SELECT COUNT(*), COALESCE(SUM(value_msat), 0), MIN(time), MAX(time) FROM boosts WHERE action IN (2, 4) {}", dest_filter),
+        &params[..],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+
+    (stats.streams, stats.stream_msat) = conn.query_row(
+        &format!("SELECT COUNT(*), COALESCE(SUM(value_msat), 0) FROM boosts WHERE action = 1 {}", dest_filter),
+        &params[..],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let sent_filter = match destinations {
+        Some(_) => format!("WHERE reply_to_idx IN (SELECT idx FROM boosts WHERE 1 {})", dest_filter),
+        None => "".to_string(),
+    };
+
+    (stats.sent_boosts, stats.sent_msat, stats.sent_fee_msat) = conn.query_row(
+        &format!("SELECT COUNT(*), COALESCE(SUM(value_msat), 0), COALESCE(SUM(payment_fee_msat), 0) FROM sent_boosts {}", sent_filter),
+        &params[..],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+
+    if destinations.is_none() {
+        stats.wallet_balance = conn.query_row("SELECT wallet_balance FROM node_info WHERE idx = 1", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+    }
+
+    Ok(stats)
+}
+
+//Get all of the sent boosts from the database
+pub fn get_payments_from_db(filepath: &String, index: u64, max: u64, direction: bool, escape_html: bool) -> Result<Vec<BoostRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut boosts: Vec<BoostRecord> = Vec::new();
+
+    let mut ltgt = ">=";
+    if direction {
+        ltgt = "<=";
     }
 
     //Build the query
@@ -643,7 +1230,8 @@ pub fn get_payments_from_db(filepath: &String, index: u64, max: u64, direction:
             payment_custom_key,
             payment_custom_value,
             payment_fee_msat,
-            reply_to_idx
+            reply_to_idx,
+            source
         FROM
             sent_boosts
         WHERE
@@ -674,6 +1262,8 @@ pub fn get_payments_from_db(filepath: &String, index: u64, max: u64, direction:
             remote_podcast: row.get(11).ok(),
             remote_episode: row.get(12).ok(),
             reply_sent: false,
+            source: row.get(19).unwrap_or_else(|_| "keysend".to_string()),
+            destination: None,
             payment_info: Some(PaymentRecord {
                 payment_hash: row.get(13)?,
                 pubkey: row.get(14)?,
@@ -683,11 +1273,11 @@ pub fn get_payments_from_db(filepath: &String, index: u64, max: u64, direction:
                 reply_to_idx: row.get(18)?,
             }),
         })
-    }).unwrap();
+    })?;
 
     //Parse the results
     for row in rows {
-        let boost: BoostRecord = row.unwrap();
+        let boost: BoostRecord = row?;
 
         //Some things like text output don't need to be html entity escaped
         //so only do it if asked for
@@ -743,7 +1333,53 @@ pub fn get_last_payment_index_from_db(filepath: &String) -> Result<u64, Box<dyn
 //Add a payment (sent boost) to the database
 pub fn add_payment_to_db(filepath: &String, boost: &BoostRecord) -> Result<bool, Box<dyn Error>> {
     let conn = connect_to_database(false, filepath)?;
+    insert_payment(&conn, boost, PAYMENT_KEEP_EXISTING)
+}
+
+//Add a batch of sent boosts the poller found, moving its payment checkpoint past them in the same transaction
+pub fn add_payments_to_db(filepath: &String, boosts: &[BoostRecord], payment_index: u64) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    for boost in boosts {
+        insert_payment(&tx, boost, PAYMENT_KEEP_EXISTING)?;
+    }
 
+    set_checkpoint(&tx, CHECKPOINT_PAYMENT_INDEX, payment_index)?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+//Store sent boosts parsed again from LND's history, updating the ones already there
+pub fn reindex_payments_in_db(filepath: &String, boosts: &[BoostRecord]) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    for boost in boosts {
+        insert_payment(&tx, boost, PAYMENT_REFRESH_EXISTING)?;
+    }
+
+    tx.commit()?;
+    Ok(true)
+}
+
+//Store sent boosts brought in from an export, leaving the ones already there alone.  Returns how many were new
+pub fn import_payments_to_db(filepath: &String, boosts: &[BoostRecord]) -> Result<u64, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+    let before: u64 = tx.query_row("SELECT COUNT(*) FROM sent_boosts", [], |row| row.get(0))?;
+
+    for boost in boosts {
+        insert_payment(&tx, boost, PAYMENT_KEEP_EXISTING)?;
+    }
+
+    let after: u64 = tx.query_row("SELECT COUNT(*) FROM sent_boosts", [], |row| row.get(0))?;
+    tx.commit()?;
+    Ok(after - before)
+}
+
+fn insert_payment(conn: &Connection, boost: &BoostRecord, on_conflict: &str) -> Result<bool, Box<dyn Error>> {
     let payment_info = match &boost.payment_info {
         Some(info) => info,
         None => {
@@ -752,7 +1388,7 @@ pub fn add_payment_to_db(filepath: &String, boost: &BoostRecord) -> Result<bool,
     };
 
     conn.execute(
-        "INSERT INTO sent_boosts (
+        &format!("INSERT INTO sent_boosts (
             idx,
             time,
             value_msat,
@@ -771,13 +1407,13 @@ pub fn add_payment_to_db(filepath: &String, boost: &BoostRecord) -> Result<bool,
             payment_custom_key,
             payment_custom_value,
             payment_fee_msat,
-            reply_to_idx
+            reply_to_idx,
+            source
         )
         VALUES
-            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
-        ON CONFLICT(idx) DO UPDATE SET
-            reply_to_idx = COALESCE(reply_to_idx, excluded.reply_to_idx)
-        ",
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)
+        ON CONFLICT(idx) {}
+        ", on_conflict),
         params![
             boost.index,
             boost.time,
@@ -798,12 +1434,912 @@ pub fn add_payment_to_db(filepath: &String, boost: &BoostRecord) -> Result<bool,
             payment_info.custom_value,
             payment_info.fee_msat,
             payment_info.reply_to_idx,
+            boost.source,
         ]
     )?;
 
+    //On the same connection, since it may be in the middle of a transaction
     if let Some(reply_to_idx) = payment_info.reply_to_idx {
-        mark_boost_as_replied(filepath, reply_to_idx)?;
+        conn.execute("UPDATE boosts SET reply_sent = 1 WHERE idx = ?1", params![reply_to_idx])?;
+    }
+
+    Ok(true)
+}
+
+//Add an invoice handed out through the lnurl-pay endpoint so it can be matched up once paid
+pub fn add_lnurl_invoice_to_db(filepath: &String, invoice: &LnurlInvoiceRecord) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO lnurl_invoices (
+            payment_hash,
+            add_index,
+            name,
+            value_msat,
+            comment,
+            payer_data,
+            created,
+            expires
+        )
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ",
+        params![
+            invoice.payment_hash,
+            invoice.add_index,
+            invoice.name,
+            invoice.value_msat,
+            invoice.comment,
+            invoice.payer_data,
+            invoice.created,
+            invoice.expires,
+        ]
+    )?;
+
+    Ok(true)
+}
+
+//Get the lnurl-pay invoices that haven't been paid yet and haven't expired before the given time
+pub fn get_pending_lnurl_invoices_from_db(filepath: &String, expires_after: i64) -> Result<Vec<LnurlInvoiceRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut invoices: Vec<LnurlInvoiceRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            payment_hash,
+            add_index,
+            name,
+            value_msat,
+            comment,
+            payer_data,
+            created,
+            expires
+        FROM
+            lnurl_invoices
+        WHERE
+            settled = 0
+            AND expires > :expires_after
+        ORDER BY
+            add_index ASC
+        "
+    )?;
+
+    let rows = stmt.query_map(&[(":expires_after", &expires_after)], |row| {
+        Ok(LnurlInvoiceRecord {
+            payment_hash: row.get(0)?,
+            add_index: row.get(1)?,
+            name: row.get(2)?,
+            value_msat: row.get(3)?,
+            comment: row.get(4)?,
+            payer_data: row.get(5)?,
+            created: row.get(6)?,
+            expires: row.get(7)?,
+        })
+    })?;
+
+    for row in rows {
+        invoices.push(row?);
+    }
+
+    Ok(invoices)
+}
+
+//Count the lnurl-pay invoices for a name that haven't been paid yet and haven't expired before the given time
+pub fn count_pending_lnurl_invoices_in_db(filepath: &String, name: &str, expires_after: i64) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let count: u64 = conn.query_row(
+        "SELECT COUNT(*) FROM lnurl_invoices WHERE name = ?1 AND settled = 0 AND expires > ?2",
+        params![name, expires_after],
+        |row| row.get(0),
+    )?;
+
+    Ok(count)
+}
+
+//Store the boost a paid lnurl-pay invoice brought in and set the invoice as settled in the same transaction, so
+//the poller keeps checking it until the boost is stored
+pub fn add_lnurl_boost_to_db(filepath: &String, boost: &BoostRecord, payment_hash: &str) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    insert_boost(&tx, boost, BOOST_KEEP_EXISTING)?;
+    tx.execute("UPDATE lnurl_invoices SET settled = 1 WHERE payment_hash = ?1", params![payment_hash])?;
+
+    tx.commit()?;
+    Ok(true)
+}
+
+//Get all of the destinations boosts can be routed to
+pub fn get_destinations_from_db(filepath: &String) -> Result<Vec<DestinationRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut destinations: Vec<DestinationRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            name,
+            custom_key,
+            custom_value,
+            podcast_guid
+        FROM
+            destinations
+        ORDER BY
+            name ASC
+        "
+    )?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok(DestinationRecord {
+            name: row.get(0)?,
+            custom_key: row.get(1)?,
+            custom_value: row.get(2)?,
+            podcast_guid: row.get(3)?,
+        })
+    })?;
+
+    for row in rows {
+        destinations.push(row?);
+    }
+
+    Ok(destinations)
+}
+
+//Get a single destination by name
+pub fn get_destination_from_db(filepath: &String, name: &str) -> Result<Option<DestinationRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let destination = conn.query_row(
+        "SELECT
+            name,
+            custom_key,
+            custom_value,
+            podcast_guid
+        FROM
+            destinations
+        WHERE
+            name = ?1
+        ",
+        params![name],
+        |row| {
+            Ok(DestinationRecord {
+                name: row.get(0)?,
+                custom_key: row.get(1)?,
+                custom_value: row.get(2)?,
+                podcast_guid: row.get(3)?,
+            })
+        },
+    ).optional()?;
+
+    Ok(destination)
+}
+
+//Add or update a destination
+pub fn add_destination_to_db(filepath: &String, destination: &DestinationRecord) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO destinations (
+            name,
+            custom_key,
+            custom_value,
+            podcast_guid
+        )
+        VALUES
+            (?1, ?2, ?3, ?4)
+        ON CONFLICT(name) DO UPDATE SET
+            custom_key = excluded.custom_key,
+            custom_value = excluded.custom_value,
+            podcast_guid = excluded.podcast_guid
+        ",
+        params![
+            destination.name,
+            destination.custom_key,
+            destination.custom_value,
+            destination.podcast_guid,
+        ]
+    )?;
+
+    Ok(true)
+}
+
+//Remove a destination.  Boosts already tagged with it keep their tag
+pub fn delete_destination_from_db(filepath: &String, name: &str) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let deleted = conn.execute("DELETE FROM destinations WHERE name = ?1", params![name])?;
+    Ok(deleted > 0)
+}
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<UserRecord> {
+    let destinations: Option<String> = row.get(4)?;
+
+    Ok(UserRecord {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        password_hash: row.get(2)?,
+        role: row.get(3)?,
+        destinations: destinations.unwrap_or_default()
+            .split(',')
+            .filter(|dest| !dest.is_empty())
+            .map(str::to_string)
+            .collect(),
+        disabled: row.get(5)?,
+        created: row.get(6)?,
+    })
+}
+
+//Add a user, returning their id
+pub fn add_user_to_db(filepath: &String, user: &UserRecord) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO users (
+            username,
+            password_hash,
+            role,
+            destinations,
+            disabled,
+            created
+        )
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6)
+        ",
+        params![
+            user.username,
+            user.password_hash,
+            user.role,
+            user.destinations.join(","),
+            user.disabled,
+            user.created,
+        ]
+    )?;
+
+    Ok(conn.last_insert_rowid() as u64)
+}
+
+//Get all of the users
+pub fn get_users_from_db(filepath: &String) -> Result<Vec<UserRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut users: Vec<UserRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            idx,
+            username,
+            password_hash,
+            role,
+            destinations,
+            disabled,
+            created
+        FROM
+            users
+        ORDER BY
+            idx ASC
+        "
+    )?;
+
+    let rows = stmt.query_map([], user_from_row)?;
+
+    for row in rows {
+        users.push(row?);
+    }
+
+    Ok(users)
+}
+
+//Get a single user by id
+pub fn get_user_from_db(filepath: &String, id: u64) -> Result<Option<UserRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let user = conn.query_row(
+        "SELECT idx, username, password_hash, role, destinations, disabled, created FROM users WHERE idx = ?1",
+        params![id],
+        user_from_row,
+    ).optional()?;
+
+    Ok(user)
+}
+
+//Get a single user by username
+pub fn get_user_by_username_from_db(filepath: &String, username: &str) -> Result<Option<UserRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let user = conn.query_row(
+        "SELECT idx, username, password_hash, role, destinations, disabled, created FROM users WHERE username = ?1",
+        params![username],
+        user_from_row,
+    ).optional()?;
+
+    Ok(user)
+}
+
+//Count the users, so we know whether logins are required
+pub fn get_user_count_from_db(filepath: &String) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let count = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+    Ok(count)
+}
+
+//Enable or disable a user.  Disabling also ends all of their sessions
+pub fn set_user_disabled_in_db(filepath: &String, id: u64, disabled: bool) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE users SET disabled = ?1 WHERE idx = ?2", params![disabled, id])?;
+
+    if disabled {
+        conn.execute("UPDATE sessions SET revoked = 1 WHERE user_id = ?1", params![id])?;
+    }
+
+    Ok(updated > 0)
+}
+
+//Get the jwt signing keys, newest first
+pub fn get_jwt_keys_from_db(filepath: &String) -> Result<Vec<JwtKeyRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut keys: Vec<JwtKeyRecord> = Vec::new();
+
+    let mut stmt = conn.prepare("SELECT idx, secret, created FROM jwt_keys ORDER BY idx DESC")?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok(JwtKeyRecord {
+            id: row.get(0)?,
+            secret: row.get(1)?,
+            created: row.get(2)?,
+        })
+    })?;
+
+    for row in rows {
+        keys.push(row?);
+    }
+
+    Ok(keys)
+}
+
+//Get a single jwt signing key by id
+pub fn get_jwt_key_from_db(filepath: &String, id: u64) -> Result<Option<JwtKeyRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let key = conn.query_row("SELECT idx, secret, created FROM jwt_keys WHERE idx = ?1", params![id], |row| {
+        Ok(JwtKeyRecord {
+            id: row.get(0)?,
+            secret: row.get(1)?,
+            created: row.get(2)?,
+        })
+    }).optional()?;
+
+    Ok(key)
+}
+
+//Add a new jwt signing key, returning its id
+pub fn add_jwt_key_to_db(filepath: &String, secret: &str, created: i64) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    conn.execute("INSERT INTO jwt_keys (secret, created) VALUES (?1, ?2)", params![secret, created])?;
+    Ok(conn.last_insert_rowid() as u64)
+}
+
+//Remove the jwt signing keys created before the given time
+pub fn delete_jwt_keys_before_from_db(filepath: &String, created: i64) -> Result<usize, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let deleted = conn.execute("DELETE FROM jwt_keys WHERE created < ?1", params![created])?;
+    Ok(deleted)
+}
+
+fn session_from_row(row: &rusqlite::Row) -> rusqlite::Result<SessionRecord> {
+    Ok(SessionRecord {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        username: row.get(2)?,
+        user_agent: row.get(3)?,
+        created: row.get(4)?,
+        last_seen: row.get(5)?,
+        revoked: row.get(6)?,
+    })
+}
+
+//Add a login session
+pub fn add_session_to_db(filepath: &String, session: &SessionRecord) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO sessions (
+            id,
+            user_id,
+            username,
+            user_agent,
+            created,
+            last_seen,
+            revoked
+        )
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ",
+        params![
+            session.id,
+            session.user_id,
+            session.username,
+            session.user_agent,
+            session.created,
+            session.last_seen,
+            session.revoked,
+        ]
+    )?;
+
+    Ok(true)
+}
+
+//Get a single login session by id
+pub fn get_session_from_db(filepath: &String, id: &str) -> Result<Option<SessionRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let session = conn.query_row(
+        "SELECT id, user_id, username, user_agent, created, last_seen, revoked FROM sessions WHERE id = ?1",
+        params![id],
+        session_from_row,
+    ).optional()?;
+
+    Ok(session)
+}
+
+//Get the sessions that haven't been revoked and were seen since the given time
+pub fn get_active_sessions_from_db(filepath: &String, seen_since: i64) -> Result<Vec<SessionRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut sessions: Vec<SessionRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            id,
+            user_id,
+            username,
+            user_agent,
+            created,
+            last_seen,
+            revoked
+        FROM
+            sessions
+        WHERE
+            revoked = 0
+            AND last_seen >= ?1
+        ORDER BY
+            last_seen DESC
+        "
+    )?;
+
+    let rows = stmt.query_map(params![seen_since], session_from_row)?;
+
+    for row in rows {
+        sessions.push(row?);
     }
 
+    Ok(sessions)
+}
+
+//Record that a session was just used
+pub fn touch_session_in_db(filepath: &String, id: &str, last_seen: i64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE sessions SET last_seen = ?1 WHERE id = ?2", params![last_seen, id])?;
+    Ok(updated > 0)
+}
+
+//Revoke a session so its token can't be used any more
+pub fn revoke_session_in_db(filepath: &String, id: &str) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE sessions SET revoked = 1 WHERE id = ?1 AND revoked = 0", params![id])?;
+    Ok(updated > 0)
+}
+
+//Remove sessions that were revoked or not seen since the given time
+pub fn delete_stale_sessions_from_db(filepath: &String, seen_since: i64) -> Result<usize, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let deleted = conn.execute("DELETE FROM sessions WHERE revoked = 1 OR last_seen < ?1", params![seen_since])?;
+    Ok(deleted)
+}
+
+fn api_token_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiTokenRecord> {
+    Ok(ApiTokenRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        token_hash: row.get(2)?,
+        scope: row.get(3)?,
+        created: row.get(4)?,
+        last_used: row.get(5)?,
+        revoked: row.get(6)?,
+    })
+}
+
+//Add an api token, returning its id
+pub fn add_api_token_to_db(filepath: &String, token: &ApiTokenRecord) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO api_tokens (
+            name,
+            token_hash,
+            scope,
+            created,
+            last_used,
+            revoked
+        )
+        VALUES
+            (?1, ?2, ?3, ?4, ?5, ?6)
+        ",
+        params![
+            token.name,
+            token.token_hash,
+            token.scope,
+            token.created,
+            token.last_used,
+            token.revoked,
+        ]
+    )?;
+
+    Ok(conn.last_insert_rowid() as u64)
+}
+
+//Get all of the api tokens
+pub fn get_api_tokens_from_db(filepath: &String) -> Result<Vec<ApiTokenRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut tokens: Vec<ApiTokenRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            idx,
+            name,
+            token_hash,
+            scope,
+            created,
+            last_used,
+            revoked
+        FROM
+            api_tokens
+        ORDER BY
+            idx ASC
+        "
+    )?;
+
+    let rows = stmt.query_map([], api_token_from_row)?;
+
+    for row in rows {
+        tokens.push(row?);
+    }
+
+    Ok(tokens)
+}
+
+//Get a single api token by the hash of its value
+pub fn get_api_token_by_hash_from_db(filepath: &String, token_hash: &str) -> Result<Option<ApiTokenRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let token = conn.query_row(
+        "SELECT idx, name, token_hash, scope, created, last_used, revoked FROM api_tokens WHERE token_hash = ?1",
+        params![token_hash],
+        api_token_from_row,
+    ).optional()?;
+
+    Ok(token)
+}
+
+//Record that an api token was just used
+pub fn touch_api_token_in_db(filepath: &String, id: u64, last_used: i64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE api_tokens SET last_used = ?1 WHERE idx = ?2", params![last_used, id])?;
+    Ok(updated > 0)
+}
+
+//Revoke an api token so it can't be used any more
+pub fn revoke_api_token_in_db(filepath: &String, id: u64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE api_tokens SET revoked = 1 WHERE idx = ?1 AND revoked = 0", params![id])?;
+    Ok(updated > 0)
+}
+
+//Record a login attempt in the audit log
+pub fn add_login_attempt_to_db(filepath: &String, attempt: &LoginAttemptRecord) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT INTO login_attempts (
+            time,
+            remote_ip,
+            username,
+            success,
+            reason
+        )
+        VALUES
+            (?1, ?2, ?3, ?4, ?5)
+        ",
+        params![
+            attempt.time,
+            attempt.remote_ip,
+            attempt.username,
+            attempt.success,
+            attempt.reason,
+        ]
+    )?;
+
     Ok(true)
-}
\ No newline at end of file
+}
+
+//Remove login attempts from before the given time
+pub fn delete_login_attempts_before_from_db(filepath: &String, time: i64) -> Result<usize, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let deleted = conn.execute("DELETE FROM login_attempts WHERE time < ?1", params![time])?;
+    Ok(deleted)
+}
+
+//Count the failed logins from an address since the given time.  A failure is only cleared by a later successful
+//login from the same address as the same user, so logging in as one account doesn't reset guesses at another
+pub fn get_failed_login_count_from_db(filepath: &String, remote_ip: &str, since: i64) -> Result<u64, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let count = conn.query_row(
+        "SELECT COUNT(*) FROM login_attempts AS failed
+         WHERE failed.remote_ip = ?1
+           AND failed.success = 0
+           AND failed.time >= ?2
+           AND NOT EXISTS (
+               SELECT 1 FROM login_attempts AS succeeded
+               WHERE succeeded.remote_ip = failed.remote_ip
+                 AND succeeded.username = failed.username
+                 AND succeeded.success = 1
+                 AND succeeded.idx > failed.idx
+           )",
+        params![remote_ip, since],
+        |row| row.get(0),
+    )?;
+
+    Ok(count)
+}
+
+//Get the most recent login attempts, newest first
+pub fn get_login_attempts_from_db(filepath: &String, max: u64) -> Result<Vec<LoginAttemptRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut attempts: Vec<LoginAttemptRecord> = Vec::new();
+
+    let mut stmt = conn.prepare(
+        "SELECT
+            idx,
+            time,
+            remote_ip,
+            username,
+            success,
+            reason
+        FROM
+            login_attempts
+        ORDER BY
+            idx DESC
+        LIMIT
+            ?1
+        "
+    )?;
+
+    let rows = stmt.query_map(params![max], |row| {
+        Ok(LoginAttemptRecord {
+            index: row.get(0)?,
+            time: row.get(1)?,
+            remote_ip: row.get(2)?,
+            username: row.get(3)?,
+            success: row.get(4)?,
+            reason: row.get(5)?,
+        })
+    })?;
+
+    for row in rows {
+        attempts.push(row?);
+    }
+
+    Ok(attempts)
+}
+
+//Get the two-factor secret of a user, whether or not it has been confirmed yet
+pub fn get_totp_from_db(filepath: &String, user_id: u64) -> Result<Option<TotpRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    let totp = conn.query_row(
+        "SELECT user_id, secret, enabled, last_step, created FROM totp WHERE user_id = ?1",
+        params![user_id],
+        |row| {
+            Ok(TotpRecord {
+                user_id: row.get(0)?,
+                secret: row.get(1)?,
+                enabled: row.get(2)?,
+                last_step: row.get(3)?,
+                created: row.get(4)?,
+            })
+        },
+    ).optional()?;
+
+    Ok(totp)
+}
+
+//Add or replace the two-factor secret of a user
+pub fn set_totp_in_db(filepath: &String, totp: &TotpRecord) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO totp (user_id, secret, enabled, last_step, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![totp.user_id, totp.secret, totp.enabled, totp.last_step, totp.created]
+    )?;
+
+    Ok(true)
+}
+
+//Turn on two-factor for a user once they have confirmed a code, remembering the time step it was for
+pub fn enable_totp_in_db(filepath: &String, user_id: u64, last_step: i64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE totp SET enabled = 1, last_step = ?1 WHERE user_id = ?2", params![last_step, user_id])?;
+    Ok(updated > 0)
+}
+
+//Remember the last time step a code was used for, so codes can't be replayed
+pub fn set_totp_last_step_in_db(filepath: &String, user_id: u64, last_step: i64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE totp SET last_step = ?1 WHERE user_id = ?2", params![last_step, user_id])?;
+    Ok(updated > 0)
+}
+
+//Turn off two-factor for a user, removing their recovery codes too
+pub fn delete_totp_from_db(filepath: &String, user_id: u64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let deleted = conn.execute("DELETE FROM totp WHERE user_id = ?1", params![user_id])?;
+    conn.execute("DELETE FROM recovery_codes WHERE user_id = ?1", params![user_id])?;
+    Ok(deleted > 0)
+}
+
+//Replace the recovery codes of a user
+pub fn set_recovery_codes_in_db(filepath: &String, user_id: u64, code_hashes: &[String]) -> Result<bool, Box<dyn Error>> {
+    let mut conn = connect_to_database(false, filepath)?;
+    let tx = conn.transaction()?;
+
+    tx.execute("DELETE FROM recovery_codes WHERE user_id = ?1", params![user_id])?;
+
+    for code_hash in code_hashes {
+        tx.execute("INSERT INTO recovery_codes (user_id, code_hash, used) VALUES (?1, ?2, 0)", params![user_id, code_hash])?;
+    }
+
+    tx.commit()?;
+
+    Ok(true)
+}
+
+//Get the recovery codes of a user that haven't been used yet
+pub fn get_unused_recovery_codes_from_db(filepath: &String, user_id: u64) -> Result<Vec<RecoveryCodeRecord>, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let mut codes: Vec<RecoveryCodeRecord> = Vec::new();
+
+    let mut stmt = conn.prepare("SELECT idx, user_id, code_hash FROM recovery_codes WHERE user_id = ?1 AND used = 0")?;
+
+    let rows = stmt.query_map(params![user_id], |row| {
+        Ok(RecoveryCodeRecord {
+            index: row.get(0)?,
+            user_id: row.get(1)?,
+            code_hash: row.get(2)?,
+        })
+    })?;
+
+    for row in rows {
+        codes.push(row?);
+    }
+
+    Ok(codes)
+}
+
+//Use up a recovery code
+pub fn mark_recovery_code_as_used(filepath: &String, index: u64) -> Result<bool, Box<dyn Error>> {
+    let conn = connect_to_database(false, filepath)?;
+    let updated = conn.execute("UPDATE recovery_codes SET used = 1 WHERE idx = ?1 AND used = 0", params![index])?;
+    Ok(updated > 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_database(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("helipad-dbif-test-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let path = path.to_string_lossy().to_string();
+        create_database(&path).unwrap();
+        path
+    }
+
+    fn test_boost(index: u64, destination: Option<&str>) -> BoostRecord {
+        BoostRecord {
+            index,
+            time: 1700000000 + index as i64,
+            value_msat: 1000,
+            value_msat_total: 1000,
+            action: 2,
+            sender: "sender".to_string(),
+            app: "app".to_string(),
+            message: "message".to_string(),
+            podcast: "podcast".to_string(),
+            episode: "episode".to_string(),
+            tlv: "{}".to_string(),
+            remote_podcast: None,
+            remote_episode: None,
+            reply_sent: false,
+            source: "keysend".to_string(),
+            destination: destination.map(str::to_string),
+            payment_info: None,
+        }
+    }
+
+    fn boost_indexes(filepath: &String, destinations: Option<&[String]>) -> Vec<u64> {
+        get_boosts_from_db(filepath, 0, 100, false, false, destinations)
+            .unwrap()
+            .iter()
+            .map(|boost| boost.index)
+            .collect()
+    }
+
+    #[test]
+    fn destination_filter_builds_named_params() {
+        assert_eq!(destination_filter(None), ("".to_string(), Vec::new()));
+
+        let destinations = vec!["alice".to_string(), "bob".to_string()];
+        assert_eq!(destination_filter(Some(&destinations)), (
+            "AND destination IN (:destination0, :destination1)".to_string(),
+            vec![
+                (":destination0".to_string(), "alice".to_string()),
+                (":destination1".to_string(), "bob".to_string()),
+            ],
+        ));
+    }
+
+    #[test]
+    fn destination_filter_with_no_destinations_matches_nothing() {
+        assert_eq!(destination_filter(Some(&[])), ("AND 0".to_string(), Vec::new()));
+
+        let filepath = test_database("destination-filter");
+        add_invoices_to_db(&filepath, &[test_boost(1, Some("alice")), test_boost(2, Some("bob")), test_boost(3, None)], 3, 3).unwrap();
+
+        assert_eq!(boost_indexes(&filepath, None), vec![3, 2, 1]);
+        assert_eq!(boost_indexes(&filepath, Some(&["bob".to_string()])), vec![2]);
+        assert_eq!(boost_indexes(&filepath, Some(&[])), Vec::<u64>::new());
+
+        let _ = std::fs::remove_file(&filepath);
+    }
+
+    #[test]
+    fn invoice_batches_move_checkpoints_with_their_boosts() {
+        let filepath = test_database("checkpoints");
+
+        add_invoices_to_db(&filepath, &[test_boost(1, None), test_boost(2, None)], 2, 1).unwrap();
+
+        let saved = get_checkpoints_from_db(&filepath).unwrap();
+        assert_eq!((saved.invoice_add_index, saved.invoice_settle_index), (2, 1));
+
+        //Sqlite can't store an index this big, so the batch fails part way through and none of it is kept
+        assert!(add_invoices_to_db(&filepath, &[test_boost(3, None), test_boost(u64::MAX, None)], 4, 3).is_err());
+
+        assert_eq!(get_checkpoints_from_db(&filepath).unwrap(), saved);
+        assert_eq!(boost_indexes(&filepath, None), vec![2, 1]);
+
+        let _ = std::fs::remove_file(&filepath);
+    }
+
+    #[test]
+    fn reindexing_refreshes_boosts_but_keeps_replies() {
+        let filepath = test_database("reindex");
+
+        add_invoices_to_db(&filepath, &[test_boost(1, None)], 1, 1).unwrap();
+        mark_boost_as_replied(&filepath, 1).unwrap();
+
+        let reparsed = BoostRecord {
+            message: "parsed again".to_string(),
+            destination: Some("alice".to_string()),
+            ..test_boost(1, None)
+        };
+        reindex_invoices_in_db(&filepath, &[reparsed, test_boost(2, None)]).unwrap();
+
+        let boosts = get_boosts_from_db(&filepath, 0, 100, false, false, None).unwrap();
+        assert_eq!(boosts.len(), 2);
+
+        let boost = boosts.iter().find(|boost| boost.index == 1).unwrap();
+        assert_eq!(boost.message, "parsed again");
+        assert_eq!(boost.destination.as_deref(), Some("alice"));
+        assert!(boost.reply_sent);
+
+        let _ = std::fs::remove_file(&filepath);
+    }
+}
@@ -0,0 +1,416 @@
+use crate::auth::AuthUser;
+use crate::error::HelipadError;
+use crate::handler;
+use crate::reindex;
+use crate::{Context, Response};
+use hyper::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+//How many items a page has when the client doesn't say, and the most it can ask for
+pub const DEFAULT_PAGE_SIZE: u64 = 100;
+pub const MAX_PAGE_SIZE: u64 = 1000;
+
+//Pages of older items start from the newest one when no index is given.  Sqlite integers are signed
+const NEWEST_INDEX: u64 = i64::MAX as u64;
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+//Responses
+#[derive(Serialize)]
+pub struct NodeInfo {
+    pub alias: String,
+    pub pubkey: String,
+    pub version: String,
+}
+
+#[derive(Serialize)]
+pub struct Balance {
+    pub balance: i64,
+}
+
+#[derive(Serialize)]
+pub struct Index {
+    pub index: u64,
+}
+
+//A received or sent boost.  Unlike v1 the text fields aren't html escaped and the tlv is given as JSON
+#[derive(Serialize)]
+pub struct Boost {
+    pub index: u64,
+    pub time: i64,
+    pub value_msat: i64,
+    pub value_msat_total: i64,
+    pub action: &'static str,
+    pub sender: String,
+    pub app: String,
+    pub message: String,
+    pub podcast: String,
+    pub episode: String,
+    pub remote_podcast: Option<String>,
+    pub remote_episode: Option<String>,
+    pub reply_sent: bool,
+    pub source: String,
+    pub destination: Option<String>,
+    pub tlv: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment: Option<Payment>,
+}
+
+//How a sent boost was paid
+#[derive(Serialize)]
+pub struct Payment {
+    pub payment_hash: String,
+    pub pubkey: String,
+    pub custom_key: Option<u64>,
+    pub custom_value: Option<String>,
+    pub fee_msat: i64,
+    pub reply_to_index: Option<u64>,
+}
+
+//Every list comes back in one of these.  Paged lists also say how to get the next page
+#[derive(Serialize)]
+pub struct List<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<Pagination>,
+}
+
+#[derive(Serialize)]
+pub struct Pagination {
+    pub index: u64,
+    pub count: u64,
+    pub old: bool,
+    pub next_index: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct MarkedReplied {
+    pub index: u64,
+    pub reply_sent: bool,
+}
+
+#[derive(Serialize)]
+pub struct DeletedDestination {
+    pub name: String,
+    pub deleted: bool,
+}
+
+//Requests
+#[derive(Deserialize)]
+pub struct ReplyRequest {
+    pub index: u64,
+    pub sats: u64,
+    pub sender: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MarkRepliedRequest {
+    pub index: u64,
+}
+
+#[derive(Deserialize)]
+pub struct DestinationRequest {
+    pub name: String,
+    pub custom_key: Option<u64>,
+    pub custom_value: Option<String>,
+    pub podcast_guid: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteDestinationRequest {
+    pub name: String,
+}
+
+//Which page of a boost listing was asked for
+struct PageRequest {
+    index: u64,
+    count: u64,
+    old: bool,
+    destination: Option<String>,
+}
+
+impl PageRequest {
+    fn from_query(ctx: &Context) -> Result<PageRequest, HelipadError> {
+        let params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
+            url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
+        }).unwrap_or_default();
+
+        let old = match params.get("old").map(String::as_str) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(_) => return Err(HelipadError::BadRequest("'old' must be true or false.".to_string())),
+        };
+
+        let index = match params.get("index") {
+            Some(index) => index.parse::<u64>()
+                .map_err(|_| HelipadError::BadRequest("'index' must be an unsigned integer.".to_string()))?,
+            None if old => NEWEST_INDEX,
+            None => 0,
+        };
+
+        let count = match params.get("count") {
+            Some(count) => match count.parse::<u64>() {
+                Ok(count) if count > 0 && count <= MAX_PAGE_SIZE => count,
+                _ => return Err(HelipadError::BadRequest(format!("'count' must be between 1 and {}.", MAX_PAGE_SIZE))),
+            },
+            None => DEFAULT_PAGE_SIZE,
+        };
+
+        Ok(PageRequest {
+            index,
+            count,
+            old,
+            destination: params.get("destination").cloned(),
+        })
+    }
+
+    //Wrap a page of boosts up with where the next one starts.  Going back in time that's just below the oldest
+    //one given, as long as the page was full.  Going forward it's just above the newest, to poll for new ones
+    fn page(&self, boosts: Vec<dbif::BoostRecord>) -> List<Boost> {
+        let next_index = if self.old {
+            match boosts.iter().map(|boost| boost.index).min() {
+                Some(oldest) if boosts.len() as u64 == self.count && oldest > 0 => Some(oldest - 1),
+                _ => None,
+            }
+        } else {
+            Some(boosts.iter().map(|boost| boost.index + 1).max().unwrap_or(self.index))
+        };
+
+        List {
+            items: boosts.into_iter().map(Boost::from).collect(),
+            pagination: Some(Pagination {
+                index: self.index,
+                count: self.count,
+                old: self.old,
+                next_index,
+            }),
+        }
+    }
+}
+
+impl From<dbif::BoostRecord> for Boost {
+    fn from(boost: dbif::BoostRecord) -> Boost {
+        let tlv = boost.parse_tlv().unwrap_or(Value::Null);
+        let action = boost.action_name();
+
+        let payment = boost.payment_info.map(|info| Payment {
+            payment_hash: info.payment_hash,
+            pubkey: info.pubkey,
+            custom_key: Some(info.custom_key).filter(|key| *key != 0),
+            custom_value: Some(info.custom_value).filter(|value| !value.is_empty()),
+            fee_msat: info.fee_msat,
+            reply_to_index: info.reply_to_idx,
+        });
+
+        Boost {
+            index: boost.index,
+            time: boost.time,
+            value_msat: boost.value_msat,
+            value_msat_total: boost.value_msat_total,
+            action,
+            sender: boost.sender,
+            app: boost.app,
+            message: boost.message,
+            podcast: boost.podcast,
+            episode: boost.episode,
+            remote_podcast: boost.remote_podcast,
+            remote_episode: boost.remote_episode,
+            reply_sent: boost.reply_sent,
+            source: boost.source,
+            destination: boost.destination,
+            tlv,
+            payment,
+        }
+    }
+}
+
+
+//Helper functions -------------------------------------------------------------------------------------------
+fn json_response<T: Serialize>(value: &T) -> Result<Response, HelipadError> {
+    let json_doc = serde_json::to_string_pretty(value)
+        .map_err(|e| HelipadError::internal("Error encoding response", e))?;
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json_doc.into())
+        .unwrap())
+}
+
+async fn json_body<T: serde::de::DeserializeOwned>(ctx: &mut Context) -> Result<T, HelipadError> {
+    ctx.body_json::<T>().await
+        .map_err(|e| HelipadError::BadRequest(format!("Invalid JSON body: {}", e)))
+}
+
+fn require(ctx: &Context, check: fn(&AuthUser) -> bool) -> Result<(), HelipadError> {
+    if handler::user_can(ctx, check) {
+        Ok(())
+    } else {
+        Err(HelipadError::Forbidden("Access forbidden".to_string()))
+    }
+}
+
+
+//Handlers ---------------------------------------------------------------------------------------------------
+pub async fn node_info(ctx: Context) -> Result<Response, HelipadError> {
+    let info = dbif::get_node_info_from_db(&ctx.helipad_config.database_file_path)
+        .map_err(|e| HelipadError::database("Error getting node info", e))?;
+
+    json_response(&NodeInfo {
+        alias: info.lnd_alias,
+        pubkey: info.node_pubkey,
+        version: info.node_version,
+    })
+}
+
+pub async fn balance(ctx: Context) -> Result<Response, HelipadError> {
+    let balance = dbif::get_wallet_balance_from_db(&ctx.helipad_config.database_file_path)
+        .map_err(|e| HelipadError::database("Error getting balance", e))?;
+
+    json_response(&Balance { balance })
+}
+
+pub async fn index(ctx: Context) -> Result<Response, HelipadError> {
+    let index = dbif::get_last_boost_index_from_db(&ctx.helipad_config.database_file_path)
+        .map_err(|e| HelipadError::database("Error getting current db index", e))?;
+
+    json_response(&Index { index })
+}
+
+pub async fn sent_index(ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_view_sent)?;
+
+    let index = dbif::get_last_payment_index_from_db(&ctx.helipad_config.database_file_path)
+        .map_err(|e| HelipadError::database("Error getting current db index", e))?;
+
+    json_response(&Index { index })
+}
+
+pub async fn boosts(ctx: Context) -> Result<Response, HelipadError> {
+    let page = PageRequest::from_query(&ctx)?;
+    let destinations = handler::visible_destinations(&ctx, page.destination.as_ref());
+
+    let boosts = dbif::get_boosts_from_db(&ctx.helipad_config.database_file_path, page.index, page.count, page.old, false, destinations.as_deref())
+        .map_err(|e| HelipadError::database("Error getting boosts", e))?;
+
+    json_response(&page.page(boosts))
+}
+
+pub async fn streams(ctx: Context) -> Result<Response, HelipadError> {
+    let page = PageRequest::from_query(&ctx)?;
+    let destinations = handler::visible_destinations(&ctx, page.destination.as_ref());
+
+    let streams = dbif::get_streams_from_db(&ctx.helipad_config.database_file_path, page.index, page.count, page.old, false, destinations.as_deref())
+        .map_err(|e| HelipadError::database("Error getting streams", e))?;
+
+    json_response(&page.page(streams))
+}
+
+pub async fn sent(ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_view_sent)?;
+
+    let page = PageRequest::from_query(&ctx)?;
+
+    let sent = dbif::get_payments_from_db(&ctx.helipad_config.database_file_path, page.index, page.count, page.old, false)
+        .map_err(|e| HelipadError::database("Error getting sent boosts", e))?;
+
+    json_response(&page.page(sent))
+}
+
+pub async fn reply(mut ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_send_payments)?;
+
+    let request: ReplyRequest = json_body(&mut ctx).await?;
+
+    if request.sats == 0 {
+        return Err(HelipadError::BadRequest("'sats' must be more than 0.".to_string()));
+    }
+
+    let sender = request.sender.unwrap_or_else(|| "Anonymous".to_string());
+    let message = request.message.unwrap_or_default();
+
+    let sent = handler::send_reply(&ctx.helipad_config, &ctx.state.version, request.index, request.sats, &sender, &message).await?;
+
+    json_response(&Boost::from(sent))
+}
+
+pub async fn mark_replied(mut ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_mark_replied)?;
+
+    let request: MarkRepliedRequest = json_body(&mut ctx).await?;
+    let destinations = handler::visible_destinations(&ctx, None);
+
+    handler::mark_replied(&ctx.helipad_config.database_file_path, request.index, destinations)?;
+
+    json_response(&MarkedReplied {
+        index: request.index,
+        reply_sent: true,
+    })
+}
+
+//List the destinations, or add/update one when posted to
+pub async fn destinations(mut ctx: Context) -> Result<Response, HelipadError> {
+    let database_file_path = ctx.helipad_config.database_file_path.clone();
+
+    if ctx.req.method() == hyper::Method::POST {
+        require(&ctx, AuthUser::can_manage)?;
+
+        let request: DestinationRequest = json_body(&mut ctx).await?;
+
+        let destination = dbif::DestinationRecord {
+            name: request.name,
+            custom_key: request.custom_key,
+            custom_value: request.custom_value.filter(|value| !value.is_empty()),
+            podcast_guid: request.podcast_guid.filter(|guid| !guid.is_empty()),
+        };
+
+        handler::save_destination(&database_file_path, &destination)?;
+
+        return json_response(&destination);
+    }
+
+    let destinations = handler::list_destinations(&database_file_path, handler::visible_destinations(&ctx, None))?;
+
+    json_response(&List {
+        items: destinations,
+        pagination: None,
+    })
+}
+
+pub async fn delete_destination(mut ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_manage)?;
+
+    let request: DeleteDestinationRequest = json_body(&mut ctx).await?;
+
+    let deleted = dbif::delete_destination_from_db(&ctx.helipad_config.database_file_path, &request.name)
+        .map_err(|e| HelipadError::database("Error deleting destination", e))?;
+
+    if !deleted {
+        return Err(HelipadError::NotFound(format!("Unknown destination: {}", request.name)));
+    }
+
+    json_response(&DeletedDestination {
+        name: request.name,
+        deleted,
+    })
+}
+
+//Start reparsing LND's whole history in the background, or see how the last one went
+pub async fn reindex(ctx: Context) -> Result<Response, HelipadError> {
+    require(&ctx, AuthUser::can_manage)?;
+
+    if ctx.req.method() == hyper::Method::POST {
+        let progress = reindex::start()?;
+        tokio::spawn(reindex::run(ctx.helipad_config.as_ref().clone(), ctx.state.shutdown.clone()));
+
+        return json_response(&progress);
+    }
+
+    match reindex::progress() {
+        Some(progress) => json_response(&progress),
+        None => Err(HelipadError::NotFound("No reindex has been run.".to_string())),
+    }
+}
@@ -0,0 +1,220 @@
+use crate::error::HelipadError;
+use crate::middleware::{self, Encoding};
+use data_encoding::HEXLOWER;
+use handlebars::Handlebars;
+use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+
+//Every file under webroot, built in by build.rs
+include!(concat!(env!("OUT_DIR"), "/webroot.rs"));
+
+//The html files in here are the pages, which are Handlebars templates
+const TEMPLATE_DIR: &str = "html/";
+
+//How much of the content hash goes in an ETag, in bytes
+const ETAG_HASH_LENGTH: usize = 16;
+
+//Swapped out whole when the files are loaded again
+static WEBROOT: RwLock<Option<Arc<Webroot>>> = RwLock::new(None);
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+pub struct Asset {
+    pub content_type: &'static str,
+    pub etag: String,
+    body: Bytes,
+    //Compressed the first time a client asks for them
+    brotli: OnceLock<Option<Bytes>>,
+    gzip: OnceLock<Option<Bytes>>,
+}
+
+struct Webroot {
+    files: HashMap<String, Arc<Asset>>,
+    templates: Handlebars<'static>,
+}
+
+impl Asset {
+    fn new(path: &str, body: Bytes) -> Asset {
+        let hash = Sha256::digest(&body);
+
+        Asset {
+            content_type: content_type(path),
+            etag: format!("W/\"{}\"", HEXLOWER.encode(&hash[..ETAG_HASH_LENGTH])),
+            body,
+            brotli: OnceLock::new(),
+            gzip: OnceLock::new(),
+        }
+    }
+
+    //The body in the encoding asked for, along with the encoding it ended up in.  Small or already compressed
+    //files are sent as they are
+    pub fn body(&self, encoding: Option<Encoding>) -> (Bytes, Option<Encoding>) {
+        let encoding = match encoding {
+            Some(encoding) if middleware::compressible(self.content_type) && self.body.len() >= middleware::COMPRESSION_MIN_SIZE => encoding,
+            _ => return (self.body.clone(), None),
+        };
+
+        let cached = match encoding {
+            Encoding::Brotli => &self.brotli,
+            Encoding::Gzip => &self.gzip,
+        };
+
+        let compressed = cached.get_or_init(|| match middleware::compress(&self.body, encoding) {
+            Ok(compressed) => Some(Bytes::from(compressed)),
+            Err(e) => {
+                error!("Error compressing asset: {}", e);
+                None
+            }
+        });
+
+        match compressed {
+            Some(compressed) => (compressed.clone(), Some(encoding)),
+            None => (self.body.clone(), None),
+        }
+    }
+
+    //Whether an If-None-Match header means the client already has this file.  Every encoding shares the ETag
+    pub fn is_cached(&self, if_none_match: &str) -> bool {
+        let etag = self.etag.trim_start_matches("W/");
+
+        if_none_match.split(',')
+            .map(|candidate| candidate.trim())
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Use the built in webroot files, with any under override_dir taking the place of the ones at the same path.
+//The pages are compiled here, so a broken one is found before anything is served
+pub fn load(override_dir: Option<&str>) -> Result<(), String> {
+    let mut files: HashMap<String, Bytes> = EMBEDDED_FILES.iter()
+        .map(|(name, body)| (name.to_string(), Bytes::from_static(body)))
+        .collect();
+
+    if let Some(dir) = override_dir {
+        let mut overrides = Vec::new();
+        list_files(Path::new(dir), Path::new(dir), &mut overrides)
+            .map_err(|e| format!("Error reading webroot_dir {}: {}", dir, e))?;
+
+        info!("Using {} files from webroot_dir {} over the built in ones.", overrides.len(), dir);
+        files.extend(overrides);
+    }
+
+    let mut templates = Handlebars::new();
+    for (name, body) in files.iter() {
+        if let Some(page) = name.strip_prefix(TEMPLATE_DIR).and_then(|page| page.strip_suffix(".html")) {
+            let source = std::str::from_utf8(body).map_err(|e| format!("Page {} isn't utf-8: {}", name, e))?;
+            templates.register_template_string(page, source).map_err(|e| format!("Error in page {}: {}", name, e))?;
+        }
+    }
+
+    let webroot = Webroot {
+        files: files.into_iter().map(|(name, body)| {
+            let asset = Asset::new(&name, body);
+            (name, Arc::new(asset))
+        }).collect(),
+        templates,
+    };
+
+    let mut current = WEBROOT.write().map_err(|_| "Unable to swap in the webroot files".to_string())?;
+    *current = Some(Arc::new(webroot));
+
+    Ok(())
+}
+
+fn list_files(root: &Path, dir: &Path, files: &mut Vec<(String, Bytes)>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(root, &path, files)?;
+            continue;
+        }
+
+        let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        files.push((name, Bytes::from(std::fs::read(&path)?)));
+    }
+
+    Ok(())
+}
+
+fn current() -> Option<Arc<Webroot>> {
+    WEBROOT.read().ok().and_then(|webroot| webroot.clone())
+}
+
+//A file by its path under webroot, like style/default.css
+pub fn get(path: &str) -> Option<Arc<Asset>> {
+    current().and_then(|webroot| webroot.files.get(path).cloned())
+}
+
+//Render one of the pages in webroot/html, named without the .html
+pub fn render(page: &str, params: &serde_json::Value) -> Result<String, HelipadError> {
+    let webroot = current()
+        .ok_or_else(|| HelipadError::internal(&format!("Error rendering {} page", page), "the webroot files aren't loaded"))?;
+
+    webroot.templates.render(page, params)
+        .map_err(|e| HelipadError::template(&format!("Error rendering {} page", page), e))
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit('.').next().unwrap_or("") {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etags_match_weak_or_strong() {
+        let asset = Asset::new("script/app.js", Bytes::from_static(b"console.log('boost');"));
+        let strong = asset.etag.trim_start_matches("W/").to_string();
+
+        assert!(asset.etag.starts_with("W/\""));
+        assert!(asset.is_cached(&asset.etag));
+        assert!(asset.is_cached(&strong));
+        assert!(asset.is_cached(&format!("\"something-else\", {}", strong)));
+        assert!(asset.is_cached("*"));
+
+        assert!(!asset.is_cached("W/\"something-else\""));
+        assert!(!asset.is_cached(""));
+    }
+
+    #[test]
+    fn small_assets_are_sent_uncompressed() {
+        let asset = Asset::new("style/app.css", Bytes::from_static(b"body {}"));
+
+        assert_eq!(asset.content_type, "text/css");
+        assert_eq!(asset.body(Some(Encoding::Gzip)), (Bytes::from_static(b"body {}"), None));
+    }
+
+    #[test]
+    fn large_assets_are_compressed_once() {
+        let css = "body { color: black; }\n".repeat(200);
+        let asset = Asset::new("style/app.css", Bytes::from(css.clone()));
+
+        let (compressed, encoding) = asset.body(Some(Encoding::Gzip));
+        assert_eq!(encoding, Some(Encoding::Gzip));
+
+        let mut decompressed = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&compressed[..]), &mut decompressed).unwrap();
+        assert_eq!(decompressed, css);
+
+        //The second ask gets the same compressed copy
+        assert_eq!(asset.body(Some(Encoding::Gzip)).0.as_ptr(), compressed.as_ptr());
+    }
+}
@@ -0,0 +1,487 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use chrono::Utc;
+use data_encoding::{BASE32_NOPAD, BASE64, HEXLOWER};
+use hmac::{Hmac, Mac};
+use qrcode::QrCode;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+//The password from the config file logs in as this built-in admin user
+pub const CONFIG_ADMIN_ID: u64 = 0;
+pub const CONFIG_ADMIN_USERNAME: &str = "admin";
+
+//Jwt signing keys are replaced this often.  Tokens only live for an hour, so the old key is dropped
+//once a second rotation period has passed
+pub const JWT_KEY_ROTATION_DAYS: i64 = 30;
+
+//Sessions end after this long without a request, matching the lifetime of the jwt cookie
+pub const SESSION_IDLE_TIMEOUT_HOURS: i64 = 1;
+
+//Logins from an address are refused for a while after this many failures in a row
+pub const MAX_LOGIN_FAILURES: u64 = 5;
+pub const LOGIN_LOCKOUT_MINUTES: i64 = 15;
+
+//Login attempts are kept in the audit log for this long
+pub const LOGIN_ATTEMPT_RETENTION_DAYS: i64 = 90;
+
+//Two-factor codes are 6 digits and change every 30 seconds (the RFC 6238 defaults authenticator apps expect)
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_STEP_SECONDS: i64 = 30;
+pub const TOTP_ISSUER: &str = "Helipad";
+
+//Codes from one step either side of now are accepted, to allow for clock drift
+pub const TOTP_ALLOWED_DRIFT: i64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+//Prefix of the api tokens we hand out, so they are easy to recognise
+pub const API_TOKEN_PREFIX: &str = "hp_";
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,    //Can do everything, including sending payments and managing users
+    Producer, //Can read and mark as replied the boosts of their own shows
+    Viewer,   //Can only read boosts
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Producer => "producer",
+            Role::Viewer => "viewer",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = AuthError;
+
+    fn from_str(role: &str) -> Result<Role, AuthError> {
+        match role {
+            "admin" => Ok(Role::Admin),
+            "producer" => Ok(Role::Producer),
+            "viewer" => Ok(Role::Viewer),
+            _ => Err(AuthError(format!("Unknown role: {}", role))),
+        }
+    }
+}
+
+//What an api token is allowed to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Read,     //Only GET requests to the api
+    Payments, //Can also send replies and mark boosts as replied
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Payments => "payments",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = AuthError;
+
+    fn from_str(scope: &str) -> Result<TokenScope, AuthError> {
+        match scope {
+            "read" => Ok(TokenScope::Read),
+            "payments" => Ok(TokenScope::Payments),
+            _ => Err(AuthError(format!("Unknown token scope: {}", scope))),
+        }
+    }
+}
+
+//The user a request is being made as
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub id: u64,
+    pub username: String,
+    pub role: Role,
+    pub destinations: Vec<String>,
+    pub session_id: String,
+    pub token_scope: Option<TokenScope>,
+    //When the login cookie the request came with runs out, as a unix timestamp
+    pub cookie_expires: Option<i64>,
+}
+
+impl AuthUser {
+    pub fn config_admin() -> AuthUser {
+        AuthUser {
+            id: CONFIG_ADMIN_ID,
+            username: CONFIG_ADMIN_USERNAME.to_string(),
+            role: Role::Admin,
+            destinations: Vec::new(),
+            session_id: "".to_string(),
+            token_scope: None,
+            cookie_expires: None,
+        }
+    }
+
+    //Disabled users and users with an unknown role can't be logged in as
+    pub fn from_record(record: &dbif::UserRecord) -> Option<AuthUser> {
+        if record.disabled {
+            return None;
+        }
+
+        Some(AuthUser {
+            id: record.id,
+            username: record.username.clone(),
+            role: record.role.parse().ok()?,
+            destinations: record.destinations.clone(),
+            session_id: "".to_string(),
+            token_scope: None,
+            cookie_expires: None,
+        })
+    }
+
+    //Revoked tokens and tokens with an unknown scope can't be used
+    pub fn from_api_token(record: &dbif::ApiTokenRecord) -> Option<AuthUser> {
+        if record.revoked {
+            return None;
+        }
+
+        let scope: TokenScope = record.scope.parse().ok()?;
+
+        Some(AuthUser {
+            id: CONFIG_ADMIN_ID,
+            username: format!("token:{}", record.name),
+            role: match scope {
+                TokenScope::Read => Role::Viewer,
+                TokenScope::Payments => Role::Admin,
+            },
+            destinations: Vec::new(),
+            session_id: "".to_string(),
+            token_scope: Some(scope),
+            cookie_expires: None,
+        })
+    }
+
+    pub fn can_send_payments(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    pub fn can_manage(&self) -> bool {
+        self.role == Role::Admin && self.token_scope.is_none()
+    }
+
+    pub fn can_mark_replied(&self) -> bool {
+        self.role == Role::Admin || self.role == Role::Producer
+    }
+
+    pub fn can_view_sent(&self) -> bool {
+        self.role != Role::Producer
+    }
+
+    //The destinations this user is limited to, or None if they can see everything
+    pub fn visible_destinations(&self) -> Option<&[String]> {
+        match self.role {
+            Role::Producer => Some(&self.destinations),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthError(String);
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Auth error: {}", self.0)
+    }
+}
+
+impl Error for AuthError {}
+
+
+//Password hashing -------------------------------------------------------------------------------------------
+pub fn hash_password(password: &str) -> Result<String, Box<dyn Error>> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(Box::new(AuthError(format!("Unable to hash password: {}", e)))),
+    }
+}
+
+//Whether a configured password is already an argon2 hash rather than the password itself
+pub fn is_password_hash(password: &str) -> bool {
+    password.starts_with("$argon2") && PasswordHash::new(password).is_ok()
+}
+
+//Argon2 verification compares in constant time
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+//Check a password against a hash no one has the password to.  Used when there's no such user, so that takes as
+//long to answer as a wrong password and doesn't give away which usernames exist
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let hash = DUMMY_HASH.get_or_init(|| hash_password(&random_string(32)).unwrap_or_default());
+    verify_password(password, hash);
+}
+
+
+//Signing keys and sessions ----------------------------------------------------------------------------------
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+//The key new tokens are signed with, rotating in a fresh one when the newest is too old
+pub fn current_signing_key(filepath: &String) -> Result<dbif::JwtKeyRecord, Box<dyn Error>> {
+    let now = Utc::now().timestamp();
+    let rotation = JWT_KEY_ROTATION_DAYS * 86400;
+
+    if let Some(key) = dbif::get_jwt_keys_from_db(filepath)?.into_iter().next() {
+        if key.created > now - rotation {
+            return Ok(key);
+        }
+    }
+
+    let secret = random_string(40);
+    let id = dbif::add_jwt_key_to_db(filepath, &secret, now)?;
+    info!("Generated new JWT signing key: [{}]", id);
+
+    dbif::delete_jwt_keys_before_from_db(filepath, now - (rotation * 2))?;
+
+    Ok(dbif::JwtKeyRecord {
+        id,
+        secret,
+        created: now,
+    })
+}
+
+//Start a new login session for the user, returning its id
+pub fn start_session(filepath: &String, user: &AuthUser, user_agent: &str) -> Result<String, Box<dyn Error>> {
+    let now = Utc::now().timestamp();
+
+    let session = dbif::SessionRecord {
+        id: random_string(32),
+        user_id: user.id,
+        username: user.username.clone(),
+        user_agent: user_agent.to_string(),
+        created: now,
+        last_seen: now,
+        revoked: false,
+    };
+
+    dbif::add_session_to_db(filepath, &session)?;
+
+    //Clear out old sessions while we're here
+    dbif::delete_stale_sessions_from_db(filepath, session_cutoff())?;
+
+    Ok(session.id)
+}
+
+//Sessions last seen before this time have expired
+pub fn session_cutoff() -> i64 {
+    Utc::now().timestamp() - (SESSION_IDLE_TIMEOUT_HOURS * 3600)
+}
+
+
+//Api tokens -------------------------------------------------------------------------------------------------
+//Tokens are long and random, so a plain sha256 is enough to avoid keeping them around in the clear
+pub fn hash_api_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+//Make a new api token, returning it along with its hash for storing
+pub fn generate_api_token() -> (String, String) {
+    let token = format!("{}{}", API_TOKEN_PREFIX, random_string(40));
+    let hash = hash_api_token(&token);
+    (token, hash)
+}
+
+
+//Two-factor authentication ----------------------------------------------------------------------------------
+pub fn generate_totp_secret() -> String {
+    BASE32_NOPAD.encode(&rand::thread_rng().gen::<[u8; 20]>())
+}
+
+//The code for a time step, per RFC 4226 section 5.3
+fn totp_code(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+//Check a code against a secret, returning the time step it matched.  Steps up to and including
+//the last one used are refused so a code can't be replayed
+pub fn verify_totp(secret: &str, code: &str, last_step: i64) -> Option<i64> {
+    verify_totp_at(secret, code, last_step, Utc::now().timestamp())
+}
+
+fn verify_totp_at(secret: &str, code: &str, last_step: i64, time: i64) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current = time / TOTP_STEP_SECONDS;
+
+    (current - TOTP_ALLOWED_DRIFT..=current + TOTP_ALLOWED_DRIFT)
+        .filter(|step| *step > last_step)
+        .find(|step| totp_code(&key, *step) == code)
+}
+
+//The otpauth:// uri authenticator apps are set up from
+pub fn totp_provisioning_uri(secret: &str, username: &str) -> String {
+    let label: String = url::form_urlencoded::byte_serialize(format!("{}:{}", TOTP_ISSUER, username).as_bytes()).collect();
+
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        label, secret, TOTP_ISSUER, TOTP_DIGITS, TOTP_STEP_SECONDS
+    )
+}
+
+//A QR code of the provisioning uri, as an svg data uri that can be used straight in an <img>
+pub fn qr_code_data_uri(data: &str) -> Result<String, Box<dyn Error>> {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => return Err(Box::new(AuthError(format!("Unable to make QR code: {}", e)))),
+    };
+
+    let svg = code.render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(format!("data:image/svg+xml;base64,{}", BASE64.encode(svg.as_bytes())))
+}
+
+//Recovery codes are long and random like api tokens, so they are hashed the same way
+pub fn hash_recovery_code(code: &str) -> String {
+    let code: String = code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    hash_api_token(&code)
+}
+
+//Make a new set of recovery codes, returning them along with their hashes for storing
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = random_string(16).to_ascii_lowercase();
+            format!("{}-{}-{}-{}", &code[0..4], &code[4..8], &code[8..12], &code[12..16])
+        })
+        .collect();
+
+    let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+    (codes, hashes)
+}
+
+pub fn two_factor_enabled(filepath: &String, user_id: u64) -> Result<bool, Box<dyn Error>> {
+    Ok(dbif::get_totp_from_db(filepath, user_id)?.map(|totp| totp.enabled).unwrap_or(false))
+}
+
+//Check a two-factor code, or failing that a recovery code (which then gets used up)
+pub fn check_second_factor(filepath: &String, user_id: u64, code: &str) -> Result<bool, Box<dyn Error>> {
+    let totp = match dbif::get_totp_from_db(filepath, user_id)? {
+        Some(totp) if totp.enabled => totp,
+        _ => return Ok(false),
+    };
+
+    if let Some(step) = verify_totp(&totp.secret, code, totp.last_step) {
+        dbif::set_totp_last_step_in_db(filepath, user_id, step)?;
+        return Ok(true);
+    }
+
+    let code_hash = hash_recovery_code(code);
+    let recovery_code = dbif::get_unused_recovery_codes_from_db(filepath, user_id)?
+        .into_iter()
+        .find(|recovery_code| recovery_code.code_hash == code_hash);
+
+    if let Some(recovery_code) = recovery_code {
+        if dbif::mark_recovery_code_as_used(filepath, recovery_code.index)? {
+            info!("Recovery code used for user: [{}]", user_id);
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The SHA1 secret from RFC 6238 appendix B, "12345678901234567890", in base32
+    const RFC6238_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn password_hashes_verify() {
+        let hash = hash_password("correct horse").unwrap();
+
+        assert!(is_password_hash(&hash));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+    }
+
+    #[test]
+    fn plain_passwords_are_not_hashes() {
+        assert!(!is_password_hash("correct horse"));
+        assert!(!is_password_hash("$argon2id$not a hash"));
+        assert!(!verify_password("correct horse", "correct horse"));
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_vectors() {
+        //The last 6 digits of the 8 digit codes in the RFC
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (time, code) in vectors.iter() {
+            assert_eq!(verify_totp_at(RFC6238_SECRET, code, 0, *time), Some(time / TOTP_STEP_SECONDS), "time {}", time);
+        }
+    }
+
+    #[test]
+    fn totp_allows_drift_but_not_replay() {
+        let time = 1111111109;
+        let step = time / TOTP_STEP_SECONDS;
+
+        assert_eq!(verify_totp_at(RFC6238_SECRET, "081 804", 0, time + TOTP_STEP_SECONDS), Some(step));
+        assert_eq!(verify_totp_at(RFC6238_SECRET, "081804", 0, time + (TOTP_STEP_SECONDS * 2)), None);
+        assert_eq!(verify_totp_at(RFC6238_SECRET, "081804", step, time), None);
+        assert_eq!(verify_totp_at(RFC6238_SECRET, "081805", 0, time), None);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::auth::Role;
+use crate::error::HelipadError;
+use crate::handler;
+use dbif::BoostRecord;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{BufRead, IsTerminal, Read, Write};
+
+//Sqlite takes a signed limit, so this is as close to everything as an export can ask for
+const EXPORT_ALL: u64 = i64::MAX as u64;
+
+//Where create-user looks for the new account's password before asking for it on stdin
+const NEW_PASSWORD_ENV: &str = "HELIPAD_NEW_USER_PASSWORD";
+
+//Stands in for stdin or stdout in place of a file name
+const STDIO_PATH: &str = "-";
+
+pub const USAGE: &str = "Usage: helipad [options] [command]
+
+Commands:
+  serve                         Run the web server and LND poller (the default)
+  migrate                       Create or upgrade the database, then exit
+  reindex                       Parse every invoice and payment in LND again
+  export <boosts|streams|sent>  Write out stored boosts [--format json|csv] [--output <file>] [--destination <name>]
+  import <boosts|sent> <file>   Store boosts from a json export, keeping the ones already stored
+  stats                         Show totals of what's been received and sent [--json] [--destination <name>]
+  create-user <username>        Add a user [--role admin|producer|viewer] [--destinations <a,b>]
+  check-config                  Check the configuration, then exit
+  help                          Show this message
+
+Options like --database-dir go before the command.  Use --help to list them.";
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    //A bare port number is still taken as the port to serve on, like before there were commands
+    Serve { port: Option<u16> },
+    Migrate,
+    Reindex,
+    Export { list: List, format: ExportFormat, output: String, destination: Option<String> },
+    Import { list: List, input: String },
+    Stats { json: bool, destination: Option<String> },
+    CreateUser { username: String, role: Role, destinations: Vec<String> },
+    CheckConfig,
+    Help,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum List {
+    Boosts,
+    Streams,
+    Sent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+//What came after a command: the plain arguments, plus --name value (or --name=value) options
+struct Arguments {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Command {
+    pub fn is_serve(&self) -> bool {
+        matches!(self, Command::Serve { .. })
+    }
+}
+
+impl List {
+    fn parse(list: Option<&String>) -> Result<List, String> {
+        match list.map(|list| list.as_str()) {
+            Some("boosts") => Ok(List::Boosts),
+            Some("streams") => Ok(List::Streams),
+            Some("sent") => Ok(List::Sent),
+            Some(other) => Err(format!("[{}] is not a list.  Use boosts, streams or sent.", other)),
+            None => Err("Say which list to use: boosts, streams or sent.".to_string()),
+        }
+    }
+}
+
+impl Arguments {
+    //Fail on anything the command doesn't know about, so typos don't go unnoticed.  Flags are options that don't take a value
+    fn parse(args: Vec<String>, positional: usize, options: &[&str], flags: &[&str]) -> Result<Arguments, String> {
+        let mut arguments = Arguments {
+            positional: Vec::new(),
+            options: HashMap::new(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None if arguments.positional.len() < positional => {
+                    arguments.positional.push(arg);
+                    continue;
+                }
+                None => return Err(format!("Unexpected argument [{}].", arg)),
+            };
+
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (name, None),
+            };
+
+            if !options.contains(&name) && !flags.contains(&name) {
+                return Err(format!("Unknown option [--{}].", name));
+            }
+
+            let value = match value {
+                Some(value) => value,
+                None if flags.contains(&name) => "true".to_string(),
+                None => args.next().ok_or_else(|| format!("--{} needs a value.", name))?,
+            };
+
+            arguments.options.insert(name.to_string(), value);
+        }
+
+        Ok(arguments)
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Work out the command from what's left over once configure_me has taken its options
+pub fn parse(args: impl Iterator<Item = OsString>) -> Result<Command, String> {
+    let mut args: Vec<String> = args.map(|arg| arg.to_string_lossy().to_string()).collect();
+
+    if args.is_empty() {
+        return Ok(Command::Serve { port: None });
+    }
+
+    let command = args.remove(0);
+
+    match command.as_str() {
+        port if port.parse::<u16>().is_ok() => {
+            Arguments::parse(args, 0, &[], &[])?;
+            Ok(Command::Serve { port: port.parse().ok() })
+        }
+        "serve" => {
+            Arguments::parse(args, 0, &[], &[])?;
+            Ok(Command::Serve { port: None })
+        }
+        "migrate" => {
+            Arguments::parse(args, 0, &[], &[])?;
+            Ok(Command::Migrate)
+        }
+        "reindex" => {
+            Arguments::parse(args, 0, &[], &[])?;
+            Ok(Command::Reindex)
+        }
+        "export" => {
+            let mut arguments = Arguments::parse(args, 1, &["format", "output", "destination"], &[])?;
+
+            let format = match arguments.options.get("format").map(|format| format.as_str()) {
+                None | Some("json") => ExportFormat::Json,
+                Some("csv") => ExportFormat::Csv,
+                Some(other) => return Err(format!("[{}] is not an export format.  Use json or csv.", other)),
+            };
+
+            let list = List::parse(arguments.positional.first())?;
+            let destination = arguments.options.remove("destination");
+            if list == List::Sent && destination.is_some() {
+                return Err("Sent boosts don't have a destination.  Use --destination with boosts or streams.".to_string());
+            }
+
+            Ok(Command::Export {
+                list,
+                format,
+                output: arguments.options.remove("output").unwrap_or_else(|| STDIO_PATH.to_string()),
+                destination,
+            })
+        }
+        "import" => {
+            let arguments = Arguments::parse(args, 2, &[], &[])?;
+
+            let list = List::parse(arguments.positional.first())?;
+            if list == List::Streams {
+                return Err("Streams are imported along with boosts.  Use boosts.".to_string());
+            }
+
+            let input = arguments.positional.get(1)
+                .ok_or_else(|| "Say which file to import from, or - for stdin.".to_string())?;
+
+            Ok(Command::Import { list, input: input.to_string() })
+        }
+        "stats" => {
+            let mut arguments = Arguments::parse(args, 0, &["destination"], &["json"])?;
+            Ok(Command::Stats {
+                json: arguments.options.contains_key("json"),
+                destination: arguments.options.remove("destination"),
+            })
+        }
+        "create-user" => {
+            let arguments = Arguments::parse(args, 1, &["role", "destinations"], &[])?;
+
+            let username = arguments.positional.first()
+                .ok_or_else(|| "Say what the new user's username is.".to_string())?;
+
+            let role = match arguments.options.get("role") {
+                Some(role) => role.parse::<Role>().map_err(|_| "--role must be one of admin, producer or viewer.".to_string())?,
+                None => Role::Viewer,
+            };
+
+            let destinations = arguments.options.get("destinations")
+                .map(|list| list.split(',').map(str::trim).filter(|dest| !dest.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default();
+
+            Ok(Command::CreateUser { username: username.to_string(), role, destinations })
+        }
+        "check-config" => {
+            Arguments::parse(args, 0, &[], &[])?;
+            Ok(Command::CheckConfig)
+        }
+        "help" => Ok(Command::Help),
+        other => Err(format!("Unknown command [{}].", other)),
+    }
+}
+
+//Write every stored boost, stream or sent boost out as json (which can be imported again) or csv, or just the
+//ones sent to a destination
+pub fn export(database_file_path: &String, list: List, format: ExportFormat, output: &str, destination: Option<&str>) -> Result<(), HelipadError> {
+    let destinations = destination.map(|dest| vec![dest.to_string()]);
+
+    let boosts = match list {
+        List::Boosts => dbif::get_boosts_from_db(database_file_path, 0, EXPORT_ALL, false, false, destinations.as_deref()),
+        List::Streams => dbif::get_streams_from_db(database_file_path, 0, EXPORT_ALL, false, false, destinations.as_deref()),
+        List::Sent => dbif::get_payments_from_db(database_file_path, 0, EXPORT_ALL, false, false),
+    }.map_err(|e| HelipadError::database("Error getting boosts", e))?;
+
+    let count = boosts.len();
+
+    let exported = match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&boosts)
+            .map_err(|e| HelipadError::internal("Error encoding boosts", e))?,
+        ExportFormat::Csv => {
+            let mut csv = String::from(handler::CSV_HEADER);
+            for (count, boost) in boosts.into_iter().enumerate() {
+                csv.push_str(&handler::csv_row(count as u64 + 1, boost));
+            }
+            csv
+        }
+    };
+
+    if output == STDIO_PATH {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(exported.as_bytes())
+            .and_then(|_| stdout.write_all(b"\n"))
+            .map_err(|e| HelipadError::file("Error writing export", e))?;
+    } else {
+        std::fs::write(output, exported)
+            .map_err(|e| HelipadError::file(&format!("Error writing export to {}", output), e))?;
+        println!("Exported {} records to {}.", count, output);
+    }
+
+    Ok(())
+}
+
+//Store boosts from a json export.  Ones already in the database are left as they are
+pub fn import(database_file_path: &String, list: List, input: &str) -> Result<(), HelipadError> {
+    let mut json = String::new();
+
+    if input == STDIO_PATH {
+        std::io::stdin().read_to_string(&mut json)
+            .map_err(|e| HelipadError::file("Error reading import", e))?;
+    } else {
+        json = std::fs::read_to_string(input)
+            .map_err(|e| HelipadError::file(&format!("Error reading import from {}", input), e))?;
+    }
+
+    let boosts: Vec<BoostRecord> = serde_json::from_str(&json)
+        .map_err(|e| HelipadError::BadRequest(format!("The import isn't a json export of boosts: {}", e)))?;
+
+    let imported = match list {
+        List::Sent => dbif::import_payments_to_db(database_file_path, &boosts),
+        _ => dbif::import_boosts_to_db(database_file_path, &boosts),
+    }.map_err(|e| HelipadError::database("Error importing boosts", e))?;
+
+    println!("Imported {} of {} records.  Any that were already stored were left as they were.", imported, boosts.len());
+
+    Ok(())
+}
+
+pub fn stats(database_file_path: &String, json: bool, destination: Option<&str>) -> Result<(), HelipadError> {
+    let destinations = destination.map(|dest| vec![dest.to_string()]);
+
+    let stats = dbif::get_stats_from_db(database_file_path, destinations.as_deref())
+        .map_err(|e| HelipadError::database("Error getting stats", e))?;
+
+    if json {
+        let json = serde_json::to_string_pretty(&stats)
+            .map_err(|e| HelipadError::internal("Error encoding stats", e))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let date = |time: Option<i64>| {
+        time.and_then(|time| chrono::DateTime::from_timestamp(time, 0))
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    println!("Boosts received:  {} ({} sats)", stats.boosts, stats.boost_msat / 1000);
+    println!("Streams received: {} ({} sats)", stats.streams, stats.stream_msat / 1000);
+    println!("Boosts sent:      {} ({} sats, {} sats in fees)", stats.sent_boosts, stats.sent_msat / 1000, stats.sent_fee_msat / 1000);
+    println!("First boost:      {}", date(stats.first_boost_time));
+    println!("Last boost:       {}", date(stats.last_boost_time));
+    match stats.wallet_balance {
+        Some(balance) => println!("Channel balance:  {} sats", balance),
+        None => println!("Channel balance:  -"),
+    }
+
+    Ok(())
+}
+
+//Ask for the password without showing it when there's someone at a terminal, twice to catch typos.  Otherwise
+//it's read from the first line of stdin
+fn read_new_password(username: &str) -> Result<String, HelipadError> {
+    if !std::io::stdin().is_terminal() {
+        let mut password = String::new();
+        std::io::stdin().lock().read_line(&mut password)
+            .map_err(|e| HelipadError::file("Error reading password", e))?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password = rpassword::prompt_password(format!("Password for {}: ", username))
+        .map_err(|e| HelipadError::file("Error reading password", e))?;
+    let confirmed = rpassword::prompt_password("Same password again: ")
+        .map_err(|e| HelipadError::file("Error reading password", e))?;
+
+    if password != confirmed {
+        return Err(HelipadError::BadRequest("The passwords didn't match.".to_string()));
+    }
+
+    Ok(password)
+}
+
+pub fn create_user(database_file_path: &String, username: &str, role: Role, destinations: Vec<String>) -> Result<(), HelipadError> {
+    let password = match std::env::var(NEW_PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => read_new_password(username)?,
+    };
+
+    let id = handler::create_user(database_file_path, username, &password, role, destinations)?;
+    println!("Added {} user {} (id {}).", role.as_str(), username, id);
+
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use crate::router::Middleware;
+use crate::{Context, Response};
+use async_trait::async_trait;
+use hyper::header::{self, HeaderValue};
+use hyper::{HeaderMap, Method, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+//Paths any site may call without credentials, like lightning addresses looked up by browser wallets
+const PUBLIC_PATHS: [&str; 1] = ["/.well-known/"];
+
+//These have always answered any origin, so they still do even when no origins are configured
+const LEGACY_OPEN_PATHS: [&str; 2] = ["/api/v1/balance", "/csv"];
+
+//Headers a cross-origin request may send when the preflight doesn't ask for specific ones
+const DEFAULT_ALLOWED_HEADERS: &str = "Authorization, Content-Type";
+
+//How long browsers may cache a preflight response, in seconds
+const PREFLIGHT_MAX_AGE: u32 = 600;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct CorsError(String);
+
+impl fmt::Display for CorsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CORS error: {}", self.0)
+    }
+}
+
+impl Error for CorsError {}
+
+//Which other sites may call us from a browser, and whether they can do it with the login cookie
+#[derive(Clone, Debug)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allow_credentials: bool,
+}
+
+impl Cors {
+    //Origins and methods are comma separated lists.  An origin of "*" allows any site, but never with credentials,
+    //and no origins at all keeps everything but the public paths to our own pages
+    pub fn new(allowed_origins: &str, allowed_methods: &str, allow_credentials: bool) -> Result<Cors, CorsError> {
+        let allowed_origins: Vec<String> = split_list(allowed_origins)
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect();
+
+        let allowed_methods = split_list(allowed_methods)
+            .map(|method| Method::from_str(&method.to_uppercase()).map_err(|_| CorsError(format!("Unknown method: [{}]", method))))
+            .collect::<Result<Vec<Method>, CorsError>>()?;
+
+        if allow_credentials && allowed_origins.iter().any(|origin| origin == "*") {
+            return Err(CorsError("Credentials can only be allowed for listed origins, not \"*\"".to_string()));
+        }
+
+        Ok(Cors {
+            allowed_origins,
+            allowed_methods,
+            allow_credentials,
+        })
+    }
+
+    //The Access-Control-Allow-Origin to answer with, and whether credentials are allowed along with it
+    fn allow_origin(&self, origin: Option<&HeaderValue>, path: &str) -> Option<(HeaderValue, bool)> {
+        let origin = origin?;
+
+        if PUBLIC_PATHS.iter().any(|public| path.starts_with(public)) || LEGACY_OPEN_PATHS.contains(&path) {
+            return Some((HeaderValue::from_static("*"), false));
+        }
+
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            return Some((HeaderValue::from_static("*"), false));
+        }
+
+        let origin_str = origin.to_str().ok()?;
+        if self.allowed_origins.iter().any(|allowed| allowed == origin_str) {
+            return Some((origin.clone(), self.allow_credentials));
+        }
+
+        None
+    }
+
+    //Add the preflight headers to the router's answer for an OPTIONS request, going by the methods it allows
+    fn preflight(&self, req_headers: &HeaderMap, path: &str, resp: &mut Response) {
+        if resp.status() != StatusCode::NO_CONTENT {
+            return;
+        }
+
+        let (allow_origin, credentials) = match self.allow_origin(req_headers.get(header::ORIGIN), path) {
+            Some(allowed) => allowed,
+            None => return,
+        };
+
+        //Only offer the methods that are both configured and served here
+        let cors_methods: Vec<&str> = resp.headers()
+            .get(header::ALLOW)
+            .and_then(|allow| allow.to_str().ok())
+            .unwrap_or("")
+            .split(',')
+            .map(|method| method.trim())
+            .filter(|method| self.allowed_methods.iter().any(|allowed| allowed.as_str() == *method))
+            .collect();
+        let cors_methods = HeaderValue::from_str(&cors_methods.join(", "));
+
+        let allow_headers = req_headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .cloned()
+            .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_ALLOWED_HEADERS));
+
+        let headers = resp.headers_mut();
+        set_allow_origin(headers, allow_origin, credentials);
+        if let Ok(val) = cors_methods {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, val);
+        }
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(PREFLIGHT_MAX_AGE));
+    }
+}
+
+#[async_trait]
+impl Middleware for Cors {
+    //Add the CORS headers for the request's origin, if it's allowed
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        let method = ctx.req.method();
+
+        if method == Method::OPTIONS {
+            self.preflight(ctx.req.headers(), &ctx.path, resp);
+            return;
+        }
+
+        if !self.allowed_methods.contains(method) {
+            return;
+        }
+
+        if let Some((allow_origin, credentials)) = self.allow_origin(ctx.req.headers().get(header::ORIGIN), &ctx.path) {
+            set_allow_origin(resp.headers_mut(), allow_origin, credentials);
+        }
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(|item| item.trim()).filter(|item| !item.is_empty())
+}
+
+fn set_allow_origin(headers: &mut HeaderMap, allow_origin: HeaderValue, credentials: bool) {
+    //Answers that depend on the origin mustn't be cached and handed to other origins
+    if allow_origin != "*" {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+
+    if credentials {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(value: &'static str) -> HeaderValue {
+        HeaderValue::from_static(value)
+    }
+
+    #[test]
+    fn public_and_legacy_paths_allow_any_origin_without_credentials() {
+        let cors = Cors::new("", "GET, POST", false).unwrap();
+        let site = origin("https://wallet.example");
+
+        assert_eq!(cors.allow_origin(Some(&site), "/.well-known/lnurlp/alice"), Some((origin("*"), false)));
+        assert_eq!(cors.allow_origin(Some(&site), "/api/v1/balance"), Some((origin("*"), false)));
+        assert_eq!(cors.allow_origin(Some(&site), "/csv"), Some((origin("*"), false)));
+        assert_eq!(cors.allow_origin(Some(&site), "/api/v1/boosts"), None);
+        assert_eq!(cors.allow_origin(None, "/csv"), None);
+    }
+
+    #[test]
+    fn listed_origins_are_echoed_back() {
+        let cors = Cors::new("https://overlay.example/, https://other.example", "GET", true).unwrap();
+
+        let overlay = origin("https://overlay.example");
+        assert_eq!(cors.allow_origin(Some(&overlay), "/api/v1/boosts"), Some((overlay.clone(), true)));
+        assert_eq!(cors.allow_origin(Some(&origin("https://evil.example")), "/api/v1/boosts"), None);
+
+        //The public paths never hand out credentials, even to listed origins
+        assert_eq!(cors.allow_origin(Some(&overlay), "/csv"), Some((origin("*"), false)));
+    }
+
+    #[test]
+    fn any_origin_never_gets_credentials() {
+        assert!(Cors::new("*", "GET", true).is_err());
+        assert!(Cors::new("", "GET, NOT A METHOD", false).is_err());
+
+        let cors = Cors::new("*", "GET", false).unwrap();
+        assert_eq!(cors.allow_origin(Some(&origin("https://any.example")), "/api/v1/boosts"), Some((origin("*"), false)));
+    }
+
+    #[test]
+    fn preflight_only_offers_configured_methods_the_route_serves() {
+        let cors = Cors::new("https://overlay.example", "GET, OPTIONS", false).unwrap();
+
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(header::ORIGIN, origin("https://overlay.example"));
+
+        let mut resp: Response = hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, "GET, POST, OPTIONS")
+            .body("".into())
+            .unwrap();
+        cors.preflight(&req_headers, "/api/v1/boosts", &mut resp);
+
+        let headers = resp.headers();
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(&origin("https://overlay.example")));
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS), Some(&origin("GET, OPTIONS")));
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS), Some(&origin(DEFAULT_ALLOWED_HEADERS)));
+        assert_eq!(headers.get(header::VARY), Some(&origin("Origin")));
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::router::IntoResponse;
+use crate::Response;
+use hyper::{header, StatusCode};
+use serde_json::json;
+use std::error::Error;
+use std::fmt;
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//Everything that can go wrong answering a request.  Each kind has its own http status and a code api
+//clients can check for.  The ones that aren't the client's fault also carry the underlying cause
+#[derive(Debug)]
+pub enum HelipadError {
+    BadRequest(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Database(String, String),
+    Lightning(String, String),
+    PodcastIndex(String, String),
+    File(String, String),
+    Template(String, String),
+    Internal(String, String),
+}
+
+impl HelipadError {
+    pub fn database(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::Database(message.to_string(), cause.to_string())
+    }
+
+    pub fn lightning(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::Lightning(message.to_string(), cause.to_string())
+    }
+
+    pub fn podcastindex(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::PodcastIndex(message.to_string(), cause.to_string())
+    }
+
+    pub fn file(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::File(message.to_string(), cause.to_string())
+    }
+
+    pub fn template(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::Template(message.to_string(), cause.to_string())
+    }
+
+    pub fn internal(message: &str, cause: impl fmt::Display) -> HelipadError {
+        HelipadError::Internal(message.to_string(), cause.to_string())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HelipadError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            HelipadError::Forbidden(_) => StatusCode::FORBIDDEN,
+            HelipadError::NotFound(_) => StatusCode::NOT_FOUND,
+            HelipadError::Conflict(_) => StatusCode::CONFLICT,
+            HelipadError::Lightning(..) | HelipadError::PodcastIndex(..) => StatusCode::BAD_GATEWAY,
+            HelipadError::Database(..) | HelipadError::File(..) | HelipadError::Template(..) | HelipadError::Internal(..) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            HelipadError::BadRequest(_) => "bad_request",
+            HelipadError::Forbidden(_) => "forbidden",
+            HelipadError::NotFound(_) => "not_found",
+            HelipadError::Conflict(_) => "conflict",
+            HelipadError::Database(..) => "database_error",
+            HelipadError::Lightning(..) => "lightning_error",
+            HelipadError::PodcastIndex(..) => "podcastindex_error",
+            HelipadError::File(..) => "file_error",
+            HelipadError::Template(..) => "template_error",
+            HelipadError::Internal(..) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            HelipadError::BadRequest(message) |
+            HelipadError::Forbidden(message) |
+            HelipadError::NotFound(message) |
+            HelipadError::Conflict(message) |
+            HelipadError::Database(message, _) |
+            HelipadError::Lightning(message, _) |
+            HelipadError::PodcastIndex(message, _) |
+            HelipadError::File(message, _) |
+            HelipadError::Template(message, _) |
+            HelipadError::Internal(message, _) => message,
+        }
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        match self {
+            HelipadError::BadRequest(_) | HelipadError::Forbidden(_) | HelipadError::NotFound(_) | HelipadError::Conflict(_) => None,
+            HelipadError::Database(_, details) |
+            HelipadError::Lightning(_, details) |
+            HelipadError::PodcastIndex(_, details) |
+            HelipadError::File(_, details) |
+            HelipadError::Template(_, details) |
+            HelipadError::Internal(_, details) => Some(details),
+        }
+    }
+}
+
+impl fmt::Display for HelipadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.details() {
+            Some(details) => write!(f, "{}: {}", self.message(), details),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl Error for HelipadError {}
+
+impl From<HelipadError> for Response {
+    fn from(err: HelipadError) -> Response {
+        let status = err.status();
+
+        //Our own failures get logged along with their cause, the client's mistakes don't need to be.  The cause
+        //stays in the log since it can give away file paths or where the node is
+        if status.is_server_error() {
+            error!("{}", err);
+        }
+
+        let body = json!({
+            "error": err.message(),
+            "code": err.code(),
+            "details": null,
+        });
+
+        hyper::Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.to_string().into())
+            .unwrap()
+    }
+}
+
+impl IntoResponse for Result<Response, HelipadError> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(resp) => resp,
+            Err(err) => err.into(),
+        }
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//The code for an error response that didn't come from a HelipadError, like "not_found" for a 404
+pub fn status_code(status: StatusCode) -> String {
+    status.canonical_reason()
+        .unwrap_or("error")
+        .to_lowercase()
+        .replace(' ', "_")
+}
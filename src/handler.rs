@@ -1,5 +1,12 @@
-use crate::{Context, Request, Body, Response};
+use crate::{Context, HelipadConfig, Request, Body, Response};
+use crate::assets;
+use crate::auth::{self, AuthUser, Role, TokenScope};
+use crate::error::HelipadError;
+use crate::health;
 use crate::lightning;
+use crate::metrics;
+use crate::middleware;
+use crate::openapi;
 use crate::podcastindex;
 use crate::cookies::CookiesExt;
 use cookie::Cookie;
@@ -7,68 +14,133 @@ use hyper::{Method, StatusCode};
 use hyper::header;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fmt;
-use std::fs;
 use std::str;
 use voca_rs::*;
-use handlebars::Handlebars;
 use serde_json::json;
-use chrono::{Duration, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use dbif::BoostRecord;
+use data_encoding::HEXLOWER;
+use lnd::lnrpc::lnrpc::Invoice;
 
 use serde::{Deserialize, Serialize};
-use jsonwebtoken::{decode, encode, Algorithm, Header, DecodingKey, EncodingKey, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, DecodingKey, EncodingKey, Validation};
 
 //Constants --------------------------------------------------------------------------------------------------
-const WEBROOT_PATH_HTML: &str = "webroot/html";
-const WEBROOT_PATH_IMAGE: &str = "webroot/image";
-const WEBROOT_PATH_STYLE: &str = "webroot/style";
-const WEBROOT_PATH_SCRIPT: &str = "webroot/script";
+//Webroot files aren't versioned, so browsers keep them but check with the ETag before using them again
+const ASSET_CACHE_CONTROL: &str = "public, no-cache";
 
+const PAGE_CONTENT_TYPE: &str = "text/html; charset=utf-8";
 
-//Structs and Enums ------------------------------------------------------------------------------------------
-#[derive(Debug)]
-struct HydraError(String);
+//How long after giving their password a user has to enter their two-factor code
+const MFA_PENDING_MINUTES: i64 = 5;
 
-impl fmt::Display for HydraError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Fatal error: {}", self.0)
-    }
-}
+//Only write a session's or api token's last used time this often, rather than on every request
+const SESSION_TOUCH_INTERVAL: i64 = 60;
+
+//Login cookies last as long as an idle session, and are only signed again once less than half of that is left
+const JWT_COOKIE_LIFETIME: i64 = auth::SESSION_IDLE_TIMEOUT_HOURS * 3600;
+
+//Pages anyone can get without logging in
+const PUBLIC_PAGES: [&str; 4] = ["/login", "/api/openapi.json", "/health", "/ready"];
+
+//The api calls a token with the payments scope can make besides reading
+const TOKEN_PAYMENT_PATHS: [&str; 4] = ["/api/v1/reply", "/api/v1/mark_replied", "/api/v2/reply", "/api/v2/mark_replied"];
+
+const LNURL_MIN_SENDABLE: u64 = 1000;
+const LNURL_MAX_SENDABLE: u64 = 1000000000;
+const LNURL_COMMENT_ALLOWED: usize = 255;
+pub const LNURL_INVOICE_EXPIRY: i64 = 3600;
+
+//Most unpaid lnurl-pay invoices a name can have out at once, since anyone can ask for them
+const LNURL_MAX_PENDING_INVOICES: u64 = 50;
 
-impl Error for HydraError {}
+//Most lnurl-pay invoices one client address can ask for in LNURL_RATE_WINDOW seconds
+const LNURL_RATE_LIMIT: usize = 10;
+const LNURL_RATE_WINDOW: i64 = 60;
 
+pub const CSV_HEADER: &str = "count,index,time,value_msat,value_sat,value_msat_total,value_sat_total,action,sender,app,message,podcast,episode,remote_podcast,remote_episode,source,destination\n";
+
+
+//When each client address last asked for lnurl-pay invoices, within LNURL_RATE_WINDOW
+static LNURL_REQUESTS: std::sync::Mutex<Vec<(String, i64)>> = std::sync::Mutex::new(Vec::new());
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
 #[derive(Debug, Serialize, Deserialize)]
-struct JwtClaims {
-   // sub: String,
+pub struct JwtClaims {
+   sub: u64,
+   sid: String,
+   role: Role,
    iat: usize,
    exp: usize,
 }
 
+//Handed out between the password and two-factor steps of logging in
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaClaims {
+   sub: u64,
+   mfa_pending: bool,
+   exp: usize,
+}
+
 //Helper functions
 async fn get_post_params(req: Request<Body>) -> HashMap<String, String> {
-    let full_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
-    let body_str = str::from_utf8(&full_body).unwrap();
-    let body_params = url::form_urlencoded::parse(body_str.as_bytes());
+    let full_body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Error reading request body: {}", e);
+            return HashMap::new();
+        }
+    };
+    let body_params = url::form_urlencoded::parse(&full_body);
 
     return body_params
         .map(|(key, value)| (key.into_owned(), value.into_owned()))
         .collect();
 }
 
-fn client_error_response(message: String) -> Response {
-    text_response(message, StatusCode::BAD_REQUEST)
-}
+//Render one of the html page templates
+fn page_response(status: StatusCode, page: &str, params: &serde_json::Value) -> Result<Response, HelipadError> {
+    let doc_rendered = assets::render(page, params)?;
 
-fn server_error_response(message: String) -> Response {
-    text_response(message, StatusCode::SERVICE_UNAVAILABLE)
+    Ok(hyper::Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, PAGE_CONTENT_TYPE)
+        .body(doc_rendered.into())
+        .unwrap())
 }
 
-fn text_response(message: String, code: StatusCode) -> Response {
-    return hyper::Response::builder()
-        .status(code)
-        .body(message.into())
-        .unwrap();
+//Send a file from the webroot, where a missing one is the client asking for something that isn't there.  Clients
+//that already have it get a 304
+fn asset_response(ctx: &Context, path: &str) -> Result<Response, HelipadError> {
+    let asset = assets::get(path).ok_or_else(|| HelipadError::NotFound(format!("No such file: {}", path)))?;
+
+    let cached = ctx.req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| asset.is_cached(val))
+        .unwrap_or(false);
+
+    let builder = hyper::Response::builder()
+        .header(header::ETAG, &asset.etag)
+        .header(header::CACHE_CONTROL, ASSET_CACHE_CONTROL)
+        .header(header::VARY, "Accept-Encoding");
+
+    if cached {
+        return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+    }
+
+    let (body, encoding) = asset.body(middleware::accepted_encoding(ctx.req.headers()));
+
+    let mut builder = builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, asset.content_type);
+
+    if let Some(encoding) = encoding {
+        builder = builder.header(header::CONTENT_ENCODING, encoding.as_str());
+    }
+
+    Ok(builder.body(Body::from(body)).unwrap())
 }
 
 fn json_response<T: serde::Serialize>(value: T) -> Response {
@@ -77,18 +149,146 @@ fn json_response<T: serde::Serialize>(value: T) -> Response {
 
     return hyper::Response::builder()
         .status(StatusCode::OK)
-        .header("Access-Control-Allow-Origin", "*")
         .header("Content-Type", "application/json")
         .body(format!("{}", json_doc).into())
         .unwrap();
 }
 
-fn options_response(options: String) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::NO_CONTENT)
-        .header("Access-Control-Allow-Methods", options)
-        .body(format!("").into())
-        .unwrap();
+//LNURL responses are always JSON and can't have their contents altered since the metadata gets
+//hashed into the invoice.  The router lets any origin reach them so browser wallets work
+fn lnurl_response(value: serde_json::Value) -> Response {
+    hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(value.to_string().into())
+        .unwrap()
+}
+
+fn lnurl_error_response(reason: &str) -> Response {
+    lnurl_response(json!({
+        "status": "ERROR",
+        "reason": reason,
+    }))
+}
+
+//Unknown lightning addresses get a 404, in the same JSON shape as other lnurl errors
+fn lnurl_not_found_response() -> Response {
+    hyper::Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .body(json!({"status": "ERROR", "reason": "Unknown lightning address"}).to_string().into())
+        .unwrap()
+}
+
+//Only destinations (the keysend addresses are destinations too) can be paid through a lightning address
+fn is_known_lnurl_name(helipad_config: &HelipadConfig, name: &str) -> bool {
+    if !is_valid_lnurl_name(name) {
+        return false;
+    }
+
+    match dbif::get_destination_from_db(&helipad_config.database_file_path, name) {
+        Ok(destination) => destination.is_some(),
+        Err(e) => {
+            error!("Error getting destination: {}", e);
+            false
+        }
+    }
+}
+
+//Note an lnurl-pay invoice request from the client address, false if it's asked for too many lately
+fn lnurl_rate_allowed(remote_ip: &str) -> bool {
+    let now = Utc::now().timestamp();
+
+    let mut requests = match LNURL_REQUESTS.lock() {
+        Ok(requests) => requests,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    requests.retain(|(_, time)| now - time < LNURL_RATE_WINDOW);
+
+    if requests.iter().filter(|(ip, _)| ip == remote_ip).count() >= LNURL_RATE_LIMIT {
+        return false;
+    }
+
+    requests.push((remote_ip.to_string(), now));
+    true
+}
+
+//An X-Forwarded header, only believed from trusted proxies.  The first value is from the proxy nearest the client
+fn forwarded_header<'a>(ctx: &'a Context, name: &str) -> Option<&'a str> {
+    if !ctx.state.via_proxy {
+        return None;
+    }
+
+    let val = ctx.req.headers().get(name)?.to_str().ok()?;
+    Some(val.split(',').next().unwrap_or_default().trim())
+}
+
+//The host the client used to reach us, which has to look like one
+fn request_host(ctx: &Context) -> Option<&str> {
+    let host = match forwarded_header(ctx, "X-Forwarded-Host") {
+        Some(host) => host,
+        None => ctx.req.headers().get(header::HOST)?.to_str().ok()?,
+    };
+
+    if host.is_empty() || !host.chars().all(|c| c.is_ascii_alphanumeric() || "-.:[]".contains(c)) {
+        return None;
+    }
+
+    Some(host)
+}
+
+//The scheme and host the client used to reach us, for building absolute callback urls
+fn request_base_url(ctx: &Context) -> Option<String> {
+    let host = request_host(ctx)?;
+
+    let scheme = match (forwarded_header(ctx, "X-Forwarded-Proto"), ctx.helipad_config.tls) {
+        (Some("https"), _) | (None, true) => "https",
+        _ => "http",
+    };
+
+    Some(format!("{}://{}{}", scheme, host, ctx.helipad_config.base_path))
+}
+
+//Redirect to one of our own pages, under the base path we are served from
+fn local_redirect(helipad_config: &HelipadConfig, path: &str) -> Response {
+    redirect(&format!("{}{}", helipad_config.base_path, path))
+}
+
+//Cookies only need to be sent to our own pages
+fn cookie_path(helipad_config: &HelipadConfig) -> String {
+    if helipad_config.base_path.is_empty() {
+        return "/".to_string();
+    }
+
+    helipad_config.base_path.clone()
+}
+
+//Lightning address user names are limited to a-z0-9-_. (LUD-16)
+fn is_valid_lnurl_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_' || c == '.')
+}
+
+//The metadata is hashed into the invoice description so it has to be built the same way every time
+fn lnurl_metadata(ctx: &Context, name: &str) -> String {
+    let host = request_host(ctx).unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+
+    let alias = match dbif::get_node_info_from_db(&ctx.helipad_config.database_file_path) {
+        Ok(info) => info.lnd_alias,
+        Err(_) => "".to_string(),
+    };
+
+    let description = if alias.is_empty() {
+        format!("Boost {}", name)
+    } else {
+        format!("Boost {}", alias)
+    };
+
+    json!([
+        ["text/plain", description],
+        ["text/identifier", format!("{}@{}", name, host)],
+    ]).to_string()
 }
 
 pub fn redirect(url: &str) -> Response {
@@ -99,46 +299,210 @@ pub fn redirect(url: &str) -> Response {
         .unwrap()
 }
 
-pub fn verify_jwt_cookie(req: &Request<Body>, secret: &String) -> bool {
-    if secret.is_empty() {
-        return false;
-    }
-
+//Tokens name the signing key they were made with in their "kid" header
+pub fn verify_jwt_cookie(req: &Request<Body>, database_file_path: &String) -> Option<JwtClaims> {
     let cookies = req.cookies();
 
     if let Some(token) = cookies.get("HELIPAD_JWT").map(Cookie::value) {
-        let message = decode::<JwtClaims>(&token, &DecodingKey::from_secret(secret.as_ref()), &Validation::new(Algorithm::HS256));
+        let kid: u64 = decode_header(token).ok()?.kid?.parse().ok()?;
+
+        let key = match dbif::get_jwt_key_from_db(database_file_path, kid) {
+            Ok(key) => key?,
+            Err(e) => {
+                error!("Error getting JWT key: {}", e);
+                return None;
+            }
+        };
+
+        let message = decode::<JwtClaims>(token, &DecodingKey::from_secret(key.secret.as_ref()), &Validation::new(Algorithm::HS256));
 
         if let Ok(token) = message {
             let timestamp = Utc::now().timestamp() as usize;
 
             if token.claims.exp > timestamp {
-                return true;
+                return Some(token.claims);
             }
         }
     }
 
-    false
+    None
+}
+
+//Logins are required once a password is configured or any user accounts exist
+pub fn auth_enabled(helipad_config: &HelipadConfig) -> bool {
+    if !helipad_config.password.is_empty() {
+        return true;
+    }
+
+    dbif::get_user_count_from_db(&helipad_config.database_file_path).unwrap_or(0) > 0
+}
+
+//Work out who the request is from.  Sessions and users are looked up on every request so that
+//logging out, disabling an account or changing its role takes effect straight away
+pub fn authenticate(req: &Request<Body>, helipad_config: &HelipadConfig) -> Option<AuthUser> {
+    let database_file_path = &helipad_config.database_file_path;
+
+    if let Some(token) = bearer_token(req) {
+        return authenticate_api_token(database_file_path, token);
+    }
+
+    let claims = verify_jwt_cookie(req, database_file_path)?;
+
+    let session = match dbif::get_session_from_db(database_file_path, &claims.sid) {
+        Ok(Some(session)) => session,
+        Ok(None) => return None,
+        Err(e) => {
+            error!("Error looking up session: {}", e);
+            return None;
+        }
+    };
+
+    if session.revoked || session.user_id != claims.sub || session.last_seen < auth::session_cutoff() {
+        return None;
+    }
+
+    let now = Utc::now().timestamp();
+    if now - session.last_seen > SESSION_TOUCH_INTERVAL {
+        if let Err(e) = dbif::touch_session_in_db(database_file_path, &session.id, now) {
+            error!("Error updating session: {}", e);
+        }
+    }
+
+    let user = load_user(helipad_config, claims.sub)?;
+
+    Some(AuthUser {
+        session_id: session.id,
+        cookie_expires: Some(claims.exp as i64),
+        ..user
+    })
+}
+
+//Whether the login cookie should be signed again.  Logins without one (like the first response
+//after logging in) always get a fresh cookie
+pub fn jwt_cookie_needs_refresh(user: &AuthUser) -> bool {
+    match user.cookie_expires {
+        Some(expires) => expires - Utc::now().timestamp() < JWT_COOKIE_LIFETIME / 2,
+        None => true,
+    }
+}
+
+//Look up a user that can log in by id, where id 0 is the admin using the configured password
+fn load_user(helipad_config: &HelipadConfig, id: u64) -> Option<AuthUser> {
+    if id == auth::CONFIG_ADMIN_ID {
+        if helipad_config.password.is_empty() {
+            return None;
+        }
+
+        return Some(AuthUser::config_admin());
+    }
+
+    match dbif::get_user_from_db(&helipad_config.database_file_path, id) {
+        Ok(Some(record)) => AuthUser::from_record(&record),
+        Ok(None) => None,
+        Err(e) => {
+            error!("Error looking up user: {}", e);
+            None
+        }
+    }
+}
+
+//Api tokens are sent by scripts and overlays as "Authorization: Bearer <token>"
+fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::trim)
+}
+
+fn authenticate_api_token(database_file_path: &String, token: &str) -> Option<AuthUser> {
+    let record = match dbif::get_api_token_by_hash_from_db(database_file_path, &auth::hash_api_token(token)) {
+        Ok(Some(record)) => record,
+        Ok(None) => return None,
+        Err(e) => {
+            error!("Error looking up api token: {}", e);
+            return None;
+        }
+    };
+
+    let user = AuthUser::from_api_token(&record)?;
+
+    let now = Utc::now().timestamp();
+    if now - record.last_used.unwrap_or(0) > SESSION_TOUCH_INTERVAL {
+        if let Err(e) = dbif::touch_api_token_in_db(database_file_path, record.id, now) {
+            error!("Error updating api token: {}", e);
+        }
+    }
+
+    Some(user)
+}
+
+//Api tokens only reach the json api, and can only change things their scope allows
+fn token_allows(scope: TokenScope, method: &Method, path: &str) -> bool {
+    if !path.starts_with("/api/") && path != "/csv" && path != "/metrics" {
+        return false;
+    }
+
+    if method == Method::GET || method == Method::OPTIONS {
+        return true;
+    }
+
+    scope == TokenScope::Payments && TOKEN_PAYMENT_PATHS.contains(&path)
+}
+
+//Check a permission of the logged in user.  Without logins set up everyone can do everything
+pub fn user_can(ctx: &Context, check: fn(&AuthUser) -> bool) -> bool {
+    match &ctx.user {
+        Some(user) => check(user),
+        None => !auth_enabled(&ctx.helipad_config),
+    }
+}
+
+pub fn forbidden_response() -> Response {
+    HelipadError::Forbidden("Access forbidden".to_string()).into()
 }
 
-pub fn set_jwt_cookie(resp: &mut Response, secret: &String) {
+//The destinations a boost listing should be limited to, given the one asked for (if any) and
+//the destinations the logged in user is allowed to see
+pub fn visible_destinations(ctx: &Context, requested: Option<&String>) -> Option<Vec<String>> {
+    let allowed = ctx.user.as_ref().and_then(AuthUser::visible_destinations);
+
+    match (allowed, requested) {
+        (Some(allowed), Some(requested)) => Some(allowed.iter().filter(|dest| *dest == requested).cloned().collect()),
+        (Some(allowed), None) => Some(allowed.to_vec()),
+        (None, Some(requested)) => Some(vec![requested.clone()]),
+        (None, None) => None,
+    }
+}
+
+pub fn set_jwt_cookie(resp: &mut Response, helipad_config: &HelipadConfig, user: &AuthUser) {
+    let key = match auth::current_signing_key(&helipad_config.database_file_path) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Error getting JWT signing key: {}", e);
+            return;
+        }
+    };
+
     let iat = Utc::now().timestamp();
-    let exp = Utc::now()
-        .checked_add_signed(Duration::hours(1))
-        .expect("invalid timestamp")
-        .timestamp();
+    let exp = iat + JWT_COOKIE_LIFETIME;
 
     let my_claims = JwtClaims {
+        sub: user.id,
+        sid: user.session_id.clone(),
+        role: user.role,
         iat: iat as usize,
         exp: exp as usize,
     };
 
-    let token = encode(&Header::default(), &my_claims, &EncodingKey::from_secret(secret.as_ref())).unwrap();
+    let header = Header {
+        kid: Some(key.id.to_string()),
+        ..Header::default()
+    };
+
+    let token = encode(&header, &my_claims, &EncodingKey::from_secret(key.secret.as_ref())).unwrap();
 
     // Build a session cookie.
     let cookie = Cookie::build(("HELIPAD_JWT", token))
-        .path("/")
-        .secure(false) // Do not require HTTPS.
+        .path(cookie_path(helipad_config))
+        .secure(helipad_config.tls) // Only sent over https when we serve it
         .http_only(true)
         .same_site(cookie::SameSite::Lax)
         .build();
@@ -152,17 +516,20 @@ pub fn set_jwt_cookie(resp: &mut Response, secret: &String) {
 }
 
 pub fn login_required(ctx: &Context) -> Option<Response> {
-    if ctx.helipad_config.password.is_empty() {
-        return None;
-    }
-
     let path = ctx.req.uri().path();
 
-    if path == "/login" || path.starts_with("/script") || path.starts_with("/style") {
+    if let Some(scope) = ctx.user.as_ref().and_then(|user| user.token_scope) {
+        if token_allows(scope, ctx.req.method(), path) {
+            return None;
+        }
+        return Some(forbidden_response());
+    }
+
+    if ctx.user.is_some() || !auth_enabled(&ctx.helipad_config) {
         return None;
     }
 
-    if verify_jwt_cookie(&ctx.req, &ctx.helipad_config.secret) {
+    if PUBLIC_PAGES.contains(&path) || path.starts_with("/script") || path.starts_with("/style") || path.starts_with("/.well-known/") {
         return None;
     }
 
@@ -171,34 +538,207 @@ pub fn login_required(ctx: &Context) -> Option<Response> {
         None => "",
     };
 
-    if ctype.starts_with("application/json") {
-        return Some(text_response("Access forbidden".into(), StatusCode::FORBIDDEN));
+    if ctype.starts_with("application/json") || bearer_token(&ctx.req).is_some() {
+        return Some(forbidden_response());
+    }
+
+    Some(local_redirect(&ctx.helipad_config, "/login"))
+}
+
+//Check a username and password against the user accounts, falling back to the configured password
+//which logs in as the built-in admin
+fn check_login(helipad_config: &HelipadConfig, username: &str, password: &str) -> Option<AuthUser> {
+    if !username.is_empty() {
+        match dbif::get_user_by_username_from_db(&helipad_config.database_file_path, username) {
+            Ok(Some(record)) => {
+                if auth::verify_password(password, &record.password_hash) {
+                    return AuthUser::from_record(&record);
+                }
+                return None;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Error looking up user: {}", e);
+                return None;
+            }
+        }
+    }
+
+    //Unknown users are checked against a dummy hash so they take as long to turn away as a wrong password
+    if !(username.is_empty() || username == auth::CONFIG_ADMIN_USERNAME) || helipad_config.password.is_empty() {
+        auth::verify_dummy_password(password);
+        return None;
+    }
+
+    if auth::verify_password(password, &helipad_config.password) {
+        return Some(AuthUser::config_admin());
+    }
+
+    None
+}
+
+//Sign a short lived token saying the user got their password right and still needs to give a two-factor code
+fn make_mfa_token(database_file_path: &String, user: &AuthUser) -> Result<String, Box<dyn Error>> {
+    let key = auth::current_signing_key(database_file_path)?;
+
+    let claims = MfaClaims {
+        sub: user.id,
+        mfa_pending: true,
+        exp: (Utc::now().timestamp() + (MFA_PENDING_MINUTES * 60)) as usize,
+    };
+
+    let header = Header {
+        kid: Some(key.id.to_string()),
+        ..Header::default()
+    };
+
+    Ok(encode(&header, &claims, &EncodingKey::from_secret(key.secret.as_ref()))?)
+}
+
+//The user id from a pending two-factor token, if it is still valid
+fn verify_mfa_token(database_file_path: &String, token: &str) -> Option<u64> {
+    let kid: u64 = decode_header(token).ok()?.kid?.parse().ok()?;
+    let key = dbif::get_jwt_key_from_db(database_file_path, kid).ok()??;
+
+    let message = decode::<MfaClaims>(token, &DecodingKey::from_secret(key.secret.as_ref()), &Validation::new(Algorithm::HS256)).ok()?;
+
+    if !message.claims.mfa_pending || message.claims.exp <= Utc::now().timestamp() as usize {
+        return None;
+    }
+
+    Some(message.claims.sub)
+}
+
+//Start a session for a user who has passed every step of logging in
+fn finish_login(helipad_config: &HelipadConfig, mut user: AuthUser, remote_ip: &str, user_agent: &str) -> Response {
+    let database_file_path = &helipad_config.database_file_path;
+
+    user.session_id = match auth::start_session(database_file_path, &user, user_agent) {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            return HelipadError::database("Error starting session", e).into();
+        }
+    };
+
+    info!("User {} logged in", user.username);
+    record_login_attempt(database_file_path, remote_ip, &user.username, true, "");
+
+    let mut resp = local_redirect(helipad_config, "/");
+    set_jwt_cookie(&mut resp, helipad_config, &user);
+    resp
+}
+
+//Addresses with too many recent failed logins are locked out until those failures age out
+fn login_locked_out(database_file_path: &String, remote_ip: &str) -> bool {
+    let since = Utc::now().timestamp() - (auth::LOGIN_LOCKOUT_MINUTES * 60);
+
+    match dbif::get_failed_login_count_from_db(database_file_path, remote_ip, since) {
+        Ok(failures) => failures >= auth::MAX_LOGIN_FAILURES,
+        Err(e) => {
+            error!("Error counting failed logins: {}", e);
+            false
+        }
+    }
+}
+
+fn record_login_attempt(database_file_path: &String, remote_ip: &str, username: &str, success: bool, reason: &str) {
+    let attempt = dbif::LoginAttemptRecord {
+        index: 0,
+        time: Utc::now().timestamp(),
+        remote_ip: remote_ip.to_string(),
+        username: username.to_string(),
+        success,
+        reason: reason.to_string(),
+    };
+
+    if let Err(e) = dbif::add_login_attempt_to_db(database_file_path, &attempt) {
+        error!("Error recording login attempt: {}", e);
     }
 
-    Some(redirect("/login"))
+    //Clear out old attempts while we're here
+    let cutoff = attempt.time - (auth::LOGIN_ATTEMPT_RETENTION_DAYS * 86400);
+    if let Err(e) = dbif::delete_login_attempts_before_from_db(database_file_path, cutoff) {
+        error!("Error removing old login attempts: {}", e);
+    }
 }
 
 //Route handlers ---------------------------------------------------------------------------------------------
 
 //Login html
 pub async fn login(ctx: Context) -> Response {
-    if ctx.helipad_config.password.is_empty() {
-        return redirect("/"); // no password required
+    if !auth_enabled(&ctx.helipad_config) {
+        return local_redirect(&ctx.helipad_config, "/"); // no password required
     }
 
     let mut message = "";
+    let mut status = StatusCode::OK;
+    let mut mfa_token = String::new();
 
     if ctx.req.method() == Method::POST {
+        let helipad_config = ctx.helipad_config.clone();
+        let database_file_path = helipad_config.database_file_path.clone();
+        let remote_ip = ctx.state.remote_ip.clone();
+        let user_agent = ctx.req.headers().get(header::USER_AGENT)
+            .and_then(|val| val.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
         let post_vars = get_post_params(ctx.req).await;
+        let username = post_vars.get("username").map(|name| name.trim()).unwrap_or_default();
 
-        if let Some(password) = post_vars.get("password") {
-            if ctx.helipad_config.password == *password {
-                let mut resp = redirect("/");
-                set_jwt_cookie(&mut resp, &ctx.helipad_config.secret);
-                return resp;
+        if login_locked_out(&database_file_path, &remote_ip) {
+            warn!("Refused login for {} from locked out address {}", username, remote_ip);
+            record_login_attempt(&database_file_path, &remote_ip, username, false, "locked out");
+            message = "Too many failed logins, try again later";
+            status = StatusCode::TOO_MANY_REQUESTS;
+        }
+        else if let Some(pending_token) = post_vars.get("mfa_token") {
+            //Second step - the two-factor or recovery code
+            let code = post_vars.get("code").map(String::as_str).unwrap_or_default();
+
+            match verify_mfa_token(&database_file_path, pending_token).and_then(|id| load_user(&helipad_config, id)) {
+                Some(user) => match auth::check_second_factor(&database_file_path, user.id, code) {
+                    Ok(true) => {
+                        return finish_login(&helipad_config, user, &remote_ip, &user_agent);
+                    }
+                    Ok(false) => {
+                        warn!("Bad two-factor code for {} from {}", user.username, remote_ip);
+                        record_login_attempt(&database_file_path, &remote_ip, &user.username, false, "bad two-factor code");
+                        message = "Bad two-factor code";
+                        mfa_token = pending_token.clone();
+                    }
+                    Err(e) => {
+                        return HelipadError::database("Error checking two-factor code", e).into();
+                    }
+                },
+                None => {
+                    message = "Login expired, please try again";
+                }
+            }
+        }
+        else if let Some(password) = post_vars.get("password") {
+            if let Some(user) = check_login(&helipad_config, username, password) {
+                match auth::two_factor_enabled(&database_file_path, user.id) {
+                    Ok(false) => {
+                        return finish_login(&helipad_config, user, &remote_ip, &user_agent);
+                    }
+                    Ok(true) => {
+                        mfa_token = match make_mfa_token(&database_file_path, &user) {
+                            Ok(token) => token,
+                            Err(e) => {
+                                return HelipadError::internal("Error starting login", e).into();
+                            }
+                        };
+                    }
+                    Err(e) => {
+                        return HelipadError::database("Error starting login", e).into();
+                    }
+                }
             }
             else {
-                message = "Bad password";
+                warn!("Failed login for {} from {}", username, remote_ip);
+                record_login_attempt(&database_file_path, &remote_ip, username, false, "bad username or password");
+                message = "Bad username or password";
             }
         }
         else {
@@ -208,101 +748,91 @@ pub async fn login(ctx: Context) -> Response {
 
     let params = json!({
         "version": ctx.state.version,
+        "base_path": ctx.helipad_config.base_path,
         "message": message,
+        "mfa_token": mfa_token,
     });
 
-    let reg = Handlebars::new();
-    let doc = fs::read_to_string("webroot/html/login.html").expect("Something went wrong reading the file.");
-    let doc_rendered = reg.render_template(&doc, &params).expect("Something went wrong rendering the file");
+    match page_response(status, "login", &params) {
+        Ok(resp) => resp,
+        Err(e) => e.into(),
+    }
+}
+
+//Logout - end the session and clear the cookie.  Only a POST, so another site can't log anyone out with a link
+pub async fn logout(ctx: Context) -> Response {
+    if let Some(user) = &ctx.user {
+        if let Err(e) = dbif::revoke_session_in_db(&ctx.helipad_config.database_file_path, &user.session_id) {
+            error!("Error revoking session: {}", e);
+        }
+        info!("User {} logged out", user.username);
+    }
 
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .body(format!("{}", doc_rendered).into())
-        .unwrap();
+    let cookie = Cookie::build(("HELIPAD_JWT", ""))
+        .path(cookie_path(&ctx.helipad_config))
+        .secure(ctx.helipad_config.tls)
+        .http_only(true)
+        .same_site(cookie::SameSite::Lax)
+        .max_age(cookie::time::Duration::ZERO)
+        .build();
+
+    let mut resp = local_redirect(&ctx.helipad_config, "/login");
+    resp.headers_mut().insert(
+        header::SET_COOKIE,
+        header::HeaderValue::from_str(&cookie.to_string()).unwrap()
+    );
+
+    resp
 }
 
 //Homepage html
-pub async fn home(ctx: Context) -> Response {
+pub async fn home(ctx: Context) -> Result<Response, HelipadError> {
     //Get query parameters
     let _params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
         url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
     }).unwrap_or_else(HashMap::new);
 
-    let reg = Handlebars::new();
-    let doc = fs::read_to_string("webroot/html/home.html").expect("Something went wrong reading the file.");
-    let doc_rendered = reg.render_template(&doc, &json!({"version": ctx.state.version})).expect("Something went wrong rendering the file");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .body(format!("{}", doc_rendered).into())
-        .unwrap();
+    page_response(StatusCode::OK, "home", &json!({"version": ctx.state.version, "base_path": ctx.helipad_config.base_path, "logins": ctx.user.is_some()}))
 }
 
 //Streams html
-pub async fn streams(ctx: Context) -> Response {
+pub async fn streams(ctx: Context) -> Result<Response, HelipadError> {
 
     //Get query parameters
     let _params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
         url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
     }).unwrap_or_else(HashMap::new);
 
-    let reg = Handlebars::new();
-    let doc = fs::read_to_string("webroot/html/streams.html").expect("Something went wrong reading the file.");
-    let doc_rendered = reg.render_template(&doc, &json!({"version": ctx.state.version})).expect("Something went wrong rendering the file");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .body(format!("{}", doc_rendered).into())
-        .unwrap();
+    page_response(StatusCode::OK, "streams", &json!({"version": ctx.state.version, "base_path": ctx.helipad_config.base_path, "logins": ctx.user.is_some()}))
 }
 
 //Sent html
-pub async fn sent(ctx: Context) -> Response {
-    let reg = Handlebars::new();
-    let doc = fs::read_to_string("webroot/html/sent.html").expect("Something went wrong reading the file.");
-    let doc_rendered = reg.render_template(&doc, &json!({"version": ctx.state.version})).expect("Something went wrong rendering the file");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .body(format!("{}", doc_rendered).into())
-        .unwrap();
+pub async fn sent(ctx: Context) -> Result<Response, HelipadError> {
+    if !user_can(&ctx, AuthUser::can_view_sent) {
+        return Ok(local_redirect(&ctx.helipad_config, "/"));
+    }
+
+    page_response(StatusCode::OK, "sent", &json!({"version": ctx.state.version, "base_path": ctx.helipad_config.base_path, "logins": ctx.user.is_some()}))
 }
 
 //Pew-pew audio
-pub async fn pewmp3(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/pew.mp3").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-type", "audio/mpeg")
-        .body(hyper::Body::from(file))
-        .unwrap();
+pub async fn pewmp3(ctx: Context) -> Result<Response, HelipadError> {
+    asset_response(&ctx, "extra/pew.mp3")
 }
 
 //Favicon icon
-pub async fn favicon(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/favicon.ico").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-type", "image/x-icon")
-        .body(hyper::Body::from(file))
-        .unwrap();
+pub async fn favicon(ctx: Context) -> Result<Response, HelipadError> {
+    asset_response(&ctx, "extra/favicon.ico")
 }
 
 //Apps definitions file
-pub async fn apps_json(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/apps.json").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "application/json")
-        .body(hyper::Body::from(file))
-        .unwrap();
+pub async fn apps_json(ctx: Context) -> Result<Response, HelipadError> {
+    asset_response(&ctx, "extra/apps.json")
 }
 
 //Numerology definitions file
-pub async fn numerology_json(_ctx: Context) -> Response {
-    let file = fs::read("webroot/extra/numerology.json").expect("Something went wrong reading the file.");
-    return hyper::Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "application/json")
-        .body(hyper::Body::from(file))
-        .unwrap();
+pub async fn numerology_json(ctx: Context) -> Result<Response, HelipadError> {
+    asset_response(&ctx, "extra/numerology.json")
 }
 
 //Serve a web asset by name from webroot subfolder according to it's requested type
@@ -312,90 +842,85 @@ pub async fn asset(ctx: Context) -> Response {
         url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
     }).unwrap_or_else(HashMap::new);
 
-    println!("** Request: {:#?}", ctx.req);
-    println!("** Params: {:#?}", _params);
-
-    //Set up the response framework
-    let file_path;
-    let content_type;
-    let file_extension;
-    match ctx.path.as_str() {
-        "/html" => {
-            file_path = WEBROOT_PATH_HTML;
-            content_type = "text/html";
-            file_extension = "html";
-        }
-        "/image" => {
-            file_path = WEBROOT_PATH_IMAGE;
-            content_type = "image/png";
-            file_extension = "png";
-        }
-        "/style" => {
-            file_path = WEBROOT_PATH_STYLE;
-            content_type = "text/css";
-            file_extension = "css";
-        }
-        "/script" => {
-            file_path = WEBROOT_PATH_SCRIPT;
-            content_type = "text/javascript";
-            file_extension = "js";
-        }
+    debug!("Asset request: {} {:?}", ctx.path, _params);
+
+    //Each type of asset has its own folder and extension
+    let (folder, extension) = match ctx.path.as_str() {
+        "/html" => ("html", "html"),
+        "/image" => ("image", "png"),
+        "/style" => ("style", "css"),
+        "/script" => ("script", "js"),
         _ => {
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** Invalid asset type requested (ex. /images?name=filename.").into())
-                .unwrap();
+            return HelipadError::BadRequest("Invalid asset type requested (ex. /images?name=filename.".to_string()).into();
         }
     };
 
     //Attempt to serve the file
     if let Some(filename) = _params.get("name") {
-        let file_to_serve = format!("{}/{}.{}", file_path, filename, file_extension);
-        println!("** Serving file: [{}]", file_to_serve);
-        let file = fs::read(file_to_serve.as_str()).expect("Something went wrong reading the file.");
-        return hyper::Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-type", content_type)
-            .body(hyper::Body::from(file))
-            .unwrap();
+        let file_to_serve = format!("{}/{}.{}", folder, filename, extension);
+        debug!("Serving file: [{}]", file_to_serve);
+        match asset_response(&ctx, &file_to_serve) {
+            Ok(resp) => resp,
+            Err(e) => e.into(),
+        }
     } else {
-        return hyper::Response::builder()
-            .status(StatusCode::from_u16(500).unwrap())
-            .body(format!("** No file specified.").into())
-            .unwrap();
+        HelipadError::BadRequest("No file specified.".to_string()).into()
     }
 }
 
-//API - give back node info
-pub async fn api_v1_node_info_options(_ctx: Context) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::from_u16(204).unwrap())
-        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
-        .body(format!("").into())
-        .unwrap();
+//API - the OpenAPI document describing the api
+pub async fn openapi_json(ctx: Context) -> Response {
+    json_response(openapi::document(&ctx.state.version, &ctx.helipad_config.base_path))
 }
 
+//Prometheus metrics
+pub async fn prometheus_metrics(_ctx: Context) -> Result<Response, HelipadError> {
+    let (content_type, body) = metrics::render()
+        .map_err(|e| HelipadError::internal("Error rendering metrics", e))?;
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .body(body.into())
+        .unwrap())
+}
+
+//Healthcheck - the process is up and answering requests
+pub async fn health(ctx: Context) -> Response {
+    json_response(json!({
+        "status": "ok",
+        "version": ctx.state.version,
+    }))
+}
+
+//Healthcheck - whether everything Helipad needs is working, answering 503 if something required isn't
+pub async fn ready(ctx: Context) -> Response {
+    let readiness = health::check(&ctx.helipad_config).await;
+
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let mut resp = json_response(readiness);
+    *resp.status_mut() = status;
+    resp
+}
+
+//API - give back node info
 pub async fn api_v1_node_info(_ctx: Context) -> Response {
     match dbif::get_node_info_from_db(&_ctx.helipad_config.database_file_path) {
         Ok(info) => {
             json_response(info)
         }
         Err(e) => {
-            eprintln!("** Error getting node info: {}.\n", e);
-            server_error_response("** Error getting node info.".into())
+            HelipadError::database("Error getting node info", e).into()
         }
     }
 }
 
 //API - give back the node balance
-pub async fn api_v1_balance_options(_ctx: Context) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::from_u16(204).unwrap())
-        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
-        .body(format!("").into())
-        .unwrap();
-}
-
 pub async fn api_v1_balance(_ctx: Context) -> Response {
     //Get query parameters
     let _params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
@@ -409,30 +934,17 @@ pub async fn api_v1_balance(_ctx: Context) -> Response {
 
             return hyper::Response::builder()
                 .status(StatusCode::OK)
-                .header("Access-Control-Allow-Origin", "*")
                 .header("Content-Type", "application/json")
                 .body(format!("{}", json_doc).into())
                 .unwrap();
         }
         Err(e) => {
-            eprintln!("** Error getting balance: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting balance.").into())
-                .unwrap();
+            HelipadError::database("Error getting balance", e).into()
         }
     }
 }
 
 //API - serve boosts as JSON either in ascending or descending order
-pub async fn api_v1_boosts_options(_ctx: Context) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::from_u16(204).unwrap())
-        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
-        .body(format!("").into())
-        .unwrap();
-}
-
 pub async fn api_v1_boosts(_ctx: Context) -> Response {
     //Get query parameters
     let params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
@@ -445,24 +957,18 @@ pub async fn api_v1_boosts(_ctx: Context) -> Response {
         Some(supplied_index) => {
             index = match supplied_index.parse::<u64>() {
                 Ok(index) => {
-                    println!("** Supplied index from call: [{}]", index);
+                    debug!("Supplied index from call: [{}]", index);
                     index
                 }
                 Err(_) => {
-                    eprintln!("** Error getting boosts: 'index' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting boosts: 'index' param is not a number");
+                    return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting boosts: 'index' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting boosts: 'index' param is not present");
+            return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -472,24 +978,18 @@ pub async fn api_v1_boosts(_ctx: Context) -> Response {
         Some(bcount) => {
             boostcount = match bcount.parse::<u64>() {
                 Ok(boostcount) => {
-                    println!("** Supplied boostcount from call: [{}]", boostcount);
+                    debug!("Supplied boostcount from call: [{}]", boostcount);
                     boostcount
                 }
                 Err(_) => {
-                    eprintln!("** Error getting boosts: 'count' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting boosts: 'count' param is not a number");
+                    return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting boosts: 'count' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting boosts: 'count' param is not present");
+            return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -500,37 +1000,27 @@ pub async fn api_v1_boosts(_ctx: Context) -> Response {
         None => {}
     };
 
+    //Parameter - destination (String)
+    let destinations = visible_destinations(&_ctx, params.get("destination"));
+
     //Get the boosts from db for returning
-    match dbif::get_boosts_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true) {
+    match dbif::get_boosts_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true, destinations.as_deref()) {
         Ok(boosts) => {
             let json_doc = serde_json::to_string_pretty(&boosts).unwrap();
 
             return hyper::Response::builder()
                 .status(StatusCode::OK)
-                .header("Access-Control-Allow-Origin", "*")
                 .header("Content-Type", "application/json")
                 .body(format!("{}", json_doc).into())
                 .unwrap();
         }
         Err(e) => {
-            eprintln!("** Error getting boosts: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting boosts.").into())
-                .unwrap();
+            HelipadError::database("Error getting boosts", e).into()
         }
     }
 }
 
 //API - serve streams as JSON either in ascending or descending order
-pub async fn api_v1_streams_options(_ctx: Context) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::from_u16(204).unwrap())
-        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
-        .body(format!("").into())
-        .unwrap();
-}
-
 pub async fn api_v1_streams(_ctx: Context) -> Response {
     //Get query parameters
     let params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
@@ -543,24 +1033,18 @@ pub async fn api_v1_streams(_ctx: Context) -> Response {
         Some(supplied_index) => {
             index = match supplied_index.parse::<u64>() {
                 Ok(index) => {
-                    println!("** Supplied index from call: [{}]", index);
+                    debug!("Supplied index from call: [{}]", index);
                     index
                 }
                 Err(_) => {
-                    eprintln!("** Error getting streams: 'index' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting streams: 'index' param is not a number");
+                    return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting streams: 'index' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting streams: 'index' param is not present");
+            return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -570,24 +1054,18 @@ pub async fn api_v1_streams(_ctx: Context) -> Response {
         Some(bcount) => {
             boostcount = match bcount.parse::<u64>() {
                 Ok(boostcount) => {
-                    println!("** Supplied stream count from call: [{}]", boostcount);
+                    debug!("Supplied stream count from call: [{}]", boostcount);
                     boostcount
                 }
                 Err(_) => {
-                    eprintln!("** Error getting streams: 'count' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting streams: 'count' param is not a number");
+                    return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting streams: 'count' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting streams: 'count' param is not present");
+            return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -598,317 +1076,1087 @@ pub async fn api_v1_streams(_ctx: Context) -> Response {
         None => {}
     };
 
+    //Parameter - destination (String)
+    let destinations = visible_destinations(&_ctx, params.get("destination"));
+
     //Get the boosts from db for returning
-    match dbif::get_streams_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true) {
+    match dbif::get_streams_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true, destinations.as_deref()) {
         Ok(streams) => {
             let json_doc_raw = serde_json::to_string_pretty(&streams).unwrap();
             let json_doc: String = strip::strip_tags(&json_doc_raw);
 
             return hyper::Response::builder()
                 .status(StatusCode::OK)
-                .header("Access-Control-Allow-Origin", "*")
                 .header("Content-Type", "application/json")
                 .body(format!("{}", json_doc).into())
                 .unwrap();
         }
         Err(e) => {
-            eprintln!("** Error getting streams: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting streams.").into())
-                .unwrap();
+            HelipadError::database("Error getting streams", e).into()
+        }
+    }
+}
+
+//API - get the current invoice index number
+pub async fn api_v1_index(_ctx: Context) -> Response {
+
+    //Get the last known invoice index from the database
+    match dbif::get_last_boost_index_from_db(&_ctx.helipad_config.database_file_path) {
+        Ok(index) => {
+            debug!("get_last_boost_index_from_db() -> [{}]", index);
+            let json_doc_raw = serde_json::to_string_pretty(&index).unwrap();
+            let json_doc: String = strip::strip_tags(&json_doc_raw);
+
+            return hyper::Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(format!("{}", json_doc).into())
+                .unwrap();
+        }
+        Err(e) => {
+            HelipadError::database("Error getting current db index", e).into()
+        }
+    }
+}
+
+//API - get the current payment index number
+pub async fn api_v1_sent_index(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_view_sent) {
+        return forbidden_response();
+    }
+
+    //Get the last known payment index from the database
+    match dbif::get_last_payment_index_from_db(&_ctx.helipad_config.database_file_path) {
+        Ok(index) => {
+            debug!("get_last_payment_index_from_db() -> [{}]", index);
+            json_response(index)
+        }
+        Err(e) => {
+            HelipadError::database("Error getting current db index", e).into()
+        }
+    }
+}
+
+//API - serve sent as JSON either in ascending or descending order
+pub async fn api_v1_sent(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_view_sent) {
+        return forbidden_response();
+    }
+
+    //Get query parameters
+    let params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
+        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
+    }).unwrap_or_else(HashMap::new);
+
+    //Parameter - index (unsigned int)
+    let index = match params.get("index") {
+        Some(supplied_index) => {
+            match supplied_index.parse::<u64>() {
+                Ok(index) => {
+                    debug!("Supplied index from call: [{}]", index);
+                    index
+                }
+                Err(_) => {
+                    warn!("Error getting sent boosts: 'index' param is not a number");
+                    return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
+                }
+            }
+        }
+        None => {
+            warn!("Error getting sent boosts: 'index' param is not present");
+            return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into()
+        }
+    };
+
+    //Parameter - boostcount (unsigned int)
+    let boostcount = match params.get("count") {
+        Some(bcount) => {
+            match bcount.parse::<u64>() {
+                Ok(boostcount) => {
+                    debug!("Supplied sent boost count from call: [{}]", boostcount);
+                    boostcount
+                }
+                Err(_) => {
+                    warn!("Error getting sent boosts: 'count' param is not a number");
+                    return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into()
+                }
+            }
+        }
+        None => {
+            warn!("Error getting sent boosts: 'count' param is not present");
+            return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into()
+        }
+    };
+
+    //Parameter - old (bool)
+    let old = match params.get("old") {
+        Some(old_val) => match old_val.parse::<bool>() {
+            Ok(val) => val,
+            Err(_) => false,
+        },
+        None => false,
+    };
+
+    //Get sent boosts from db for returning
+    match dbif::get_payments_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true) {
+        Ok(sent_boosts) => {
+            json_response(sent_boosts)
+        }
+        Err(e) => {
+            HelipadError::database("Error getting sent boosts", e).into()
+        }
+    }
+}
+
+pub async fn api_v1_reply(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_send_payments) {
+        return forbidden_response();
+    }
+
+    let helipad_config = _ctx.helipad_config.clone();
+    let version = _ctx.state.version.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - index (unsigned int)
+    let index = match post_vars.get("index") {
+        Some(index) => match index.parse::<u64>() {
+            Ok(index) => index,
+            Err(_) => {
+                warn!("Error parsing reply params: 'index' param is not a number");
+                return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
+            }
+        },
+        None => {
+            return HelipadError::BadRequest("No index specified.".to_string()).into();
+        },
+    };
+
+    //Parameter - sats (unsigned int)
+    let sats = match post_vars.get("sats") {
+        Some(sats) => match sats.parse::<u64>() {
+            Ok(sats) => sats,
+            Err(_) => {
+                warn!("Error parsing reply params: 'sats' param is not a number");
+                return HelipadError::BadRequest("'sats' is a required parameter and must be an unsigned integer.".to_string()).into();
+            }
+        },
+        None => {
+            return HelipadError::BadRequest("No sats specified.".to_string()).into();
+        },
+    };
+
+    let sender = match post_vars.get("sender") {
+        Some(name) => name,
+        None => "Anonymous"
+    };
+
+    let message = match post_vars.get("message") {
+        Some(msg) => msg,
+        None => ""
+    };
+
+    match send_reply(&helipad_config, &version, index, sats, sender, message).await {
+        Ok(boost) => {
+            json_response(json!({
+                "success": true,
+                "data": boost,
+            }))
+        }
+        Err(e) => e.into(),
+    }
+}
+
+//Send a boost back to the sender of a received one and store it as a sent boost
+pub async fn send_reply(helipad_config: &HelipadConfig, version: &str, index: u64, sats: u64, sender: &str, message: &str) -> Result<BoostRecord, HelipadError> {
+    let boosts = dbif::get_boosts_from_db(&helipad_config.database_file_path, index, 1, true, true, None)
+        .map_err(|e| HelipadError::database("Error finding boost index", e))?;
+
+    let boost = match boosts.first() {
+        Some(boost) if boost.index == index => boost,
+        _ => return Err(HelipadError::NotFound("Unknown boost index.".to_string())),
+    };
+
+    let tlv = boost.parse_tlv()
+        .map_err(|e| HelipadError::internal("Error parsing boost tlv", e))?;
+
+    let pub_key = tlv["reply_address"].as_str().unwrap_or_default().to_string();
+    let custom_key = tlv["reply_custom_key"].as_u64();
+    let custom_value = match tlv["reply_custom_value"].as_str() {
+        Some(rcv) => Some(rcv.to_string()),
+        None => None
+    };
+
+    if pub_key == "" {
+        return Err(HelipadError::BadRequest("No reply_address found in boost".to_string()));
+    }
+
+    if custom_key.is_some() && custom_value.is_none() {
+        return Err(HelipadError::BadRequest("No reply_custom_value found in boost".to_string()));
+    }
+
+    let reply_tlv = json!({
+        "app_name": "Helipad",
+        "app_version": version,
+        "podcast": tlv["podcast"].as_str().unwrap_or_default(),
+        "episode": tlv["episode"].as_str().unwrap_or_default(),
+        "name": tlv["sender_name"].as_str().unwrap_or_default(),
+        "sender_name": sender,
+        "message": message,
+        "action": "boost",
+        "value_msat": sats * 1000,
+        "value_msat_total": sats * 1000,
+    });
+
+    let lightning = match lightning::shared_lnd(helipad_config).await {
+        Some(lndconn) => lndconn,
+        None => {
+            return Err(HelipadError::lightning("Error connecting to LND", &helipad_config.node_address));
+        }
+    };
+
+    let payment = lightning::send_boost(lightning, pub_key, custom_key, custom_value, sats, reply_tlv.clone()).await?;
+
+    let mut cache = podcastindex::GuidCache::new(1);
+
+    let parsed = match lightning::parse_boost_from_payment(payment.clone(), &mut cache).await {
+        Some(boost) => Some(boost),
+        None => lightning::parse_boost_from_lnurl_payment(payment, &reply_tlv, &mut cache).await,
+    };
+
+    let mut boost = match parsed {
+        Some(boost) => boost,
+        None => {
+            return Err(HelipadError::internal("Error parsing sent boost", "Payment has no boost tlv"));
+        }
+    };
+
+    if let Some(pay_info) = boost.payment_info {
+        boost.payment_info = Some(dbif::PaymentRecord {
+            reply_to_idx: Some(index),
+            ..pay_info
+        });
+    }
+
+    //Give some output
+    info!("Sent reply {} to boost {}: {} sats", boost.index, index, boost.value_msat / 1000);
+    debug!("Sent Boost: {:?}", boost);
+
+    //Store in the database
+    match dbif::add_payment_to_db(&helipad_config.database_file_path, &boost) {
+        Ok(_) => debug!("New sent boost added."),
+        Err(e) => error!("Error adding sent boost: {:?}", e)
+    }
+
+    Ok(boost)
+}
+
+pub async fn api_v1_mark_replied(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_mark_replied) {
+        return forbidden_response();
+    }
+
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let destinations = visible_destinations(&_ctx, None);
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - index (unsigned int)
+    let index = match post_vars.get("index") {
+        Some(index) => match index.parse::<u64>() {
+            Ok(index) => index,
+            Err(_) => {
+                warn!("Error parsing reply params: 'index' param is not a number");
+                return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
+            }
+        },
+        None => {
+            return HelipadError::BadRequest("No index specified.".to_string()).into();
+        },
+    };
+
+    match mark_replied(&database_file_path, index, destinations) {
+        Ok(_) => {
+            json_response(json!({
+                "success": true,
+            }))
+        }
+        Err(e) => e.into(),
+    }
+}
+
+//Mark a received boost as replied to, as long as it was sent to one of the given destinations (if limited)
+pub fn mark_replied(database_file_path: &String, index: u64, destinations: Option<Vec<String>>) -> Result<(), HelipadError> {
+    //Producers can only mark the boosts sent to their own shows
+    if let Some(destinations) = destinations {
+        let boosts = dbif::get_boosts_from_db(database_file_path, index, 1, true, false, Some(&destinations))
+            .map_err(|e| HelipadError::database("Error finding boost index", e))?;
+
+        if boosts.first().map(|boost| boost.index) != Some(index) {
+            return Err(HelipadError::Forbidden("Access forbidden".to_string()));
+        }
+    }
+
+    dbif::mark_boost_as_replied(database_file_path, index)
+        .map_err(|e| HelipadError::database("Error marking boost as replied", e))?;
+
+    Ok(())
+}
+
+//Keysend address - give out our node pubkey and the custom record identifying name@host
+pub async fn keysend_address(ctx: Context) -> Response {
+    let name = ctx.params.find("name").unwrap_or_default().to_lowercase();
+
+    if !is_known_lnurl_name(&ctx.helipad_config, &name) {
+        return lnurl_not_found_response();
+    }
+
+    let address = match dbif::get_destination_from_db(&ctx.helipad_config.database_file_path, &name) {
+        Ok(Some(address)) => address,
+        Ok(None) => return lnurl_not_found_response(),
+        Err(e) => {
+            error!("Error getting destination: {}", e);
+            return lnurl_error_response("Destination not available");
+        }
+    };
+
+    let pubkey = match dbif::get_node_info_from_db(&ctx.helipad_config.database_file_path) {
+        Ok(info) if !info.node_pubkey.is_empty() => info.node_pubkey,
+        Ok(_) => return lnurl_error_response("Node info not available yet"),
+        Err(e) => {
+            error!("Error getting node info: {}", e);
+            return lnurl_error_response("Node info not available");
+        }
+    };
+
+    let mut custom_data = Vec::new();
+
+    if let (Some(key), Some(value)) = (address.custom_key, &address.custom_value) {
+        custom_data.push(json!({
+            "customKey": key.to_string(),
+            "customValue": value,
+        }));
+    }
+
+    lnurl_response(json!({
+        "status": "OK",
+        "tag": "keysend",
+        "pubkey": pubkey,
+        "customData": custom_data,
+    }))
+}
+
+//LNURL-pay - describe how to pay us at name@host (LUD-06/LUD-16)
+pub async fn lnurlp_pay_request(ctx: Context) -> Response {
+    let name = ctx.params.find("name").unwrap_or_default().to_lowercase();
+
+    if !is_known_lnurl_name(&ctx.helipad_config, &name) {
+        return lnurl_not_found_response();
+    }
+
+    let base_url = match request_base_url(&ctx) {
+        Some(url) => url,
+        None => return lnurl_error_response("Missing or invalid host header"),
+    };
+
+    lnurl_response(json!({
+        "tag": "payRequest",
+        "callback": format!("{}/.well-known/lnurlp/{}/callback", base_url, name),
+        "minSendable": LNURL_MIN_SENDABLE,
+        "maxSendable": LNURL_MAX_SENDABLE,
+        "metadata": lnurl_metadata(&ctx, &name),
+        "commentAllowed": LNURL_COMMENT_ALLOWED,
+        "payerData": {
+            "name": { "mandatory": false },
+            "identifier": { "mandatory": false },
+            "boostagram": { "mandatory": false },
+        },
+    }))
+}
+
+//Check the amount (msats), comment (LUD-12) and payer data (LUD-18) an lnurl-pay invoice is asked for with,
+//giving back the reason to send the wallet when they won't do
+fn lnurl_callback_params(params: &HashMap<String, String>) -> Result<(u64, String, Option<String>), String> {
+    //Parameter - amount (unsigned int, msats)
+    let amount = match params.get("amount").map(|amt| amt.parse::<u64>()) {
+        Some(Ok(amount)) => amount,
+        _ => return Err("'amount' is a required parameter and must be an unsigned integer".to_string()),
+    };
+
+    if !(LNURL_MIN_SENDABLE..=LNURL_MAX_SENDABLE).contains(&amount) {
+        return Err(format!(
+            "Amount must be between {} and {} sats",
+            LNURL_MIN_SENDABLE / 1000,
+            LNURL_MAX_SENDABLE / 1000
+        ));
+    }
+
+    //Parameter - comment (LUD-12)
+    let comment = params.get("comment").cloned().unwrap_or_default();
+
+    if comment.chars().count() > LNURL_COMMENT_ALLOWED {
+        return Err(format!("Comment is limited to {} characters", LNURL_COMMENT_ALLOWED));
+    }
+
+    //Parameter - payerdata (LUD-18)
+    let payer_data = params.get("payerdata").cloned();
+
+    if let Some(data) = &payer_data {
+        match serde_json::from_str::<serde_json::Value>(data) {
+            Ok(val) if val.is_object() => {}
+            _ => return Err("'payerdata' must be a JSON object".to_string()),
+        }
+    }
+
+    Ok((amount, comment, payer_data))
+}
+
+//LNURL-pay - hand out an invoice for the requested amount and remember the comment and payer data
+pub async fn lnurlp_callback(ctx: Context) -> Response {
+    let name = ctx.params.find("name").unwrap_or_default().to_lowercase();
+
+    if !is_known_lnurl_name(&ctx.helipad_config, &name) {
+        return lnurl_not_found_response();
+    }
+
+    //Get query parameters
+    let params: HashMap<String, String> = ctx.req.uri().query().map(|v| {
+        url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
+    }).unwrap_or_default();
+
+    let (amount, comment, payer_data) = match lnurl_callback_params(&params) {
+        Ok(params) => params,
+        Err(reason) => return lnurl_error_response(&reason),
+    };
+
+    //Every invoice handed out is kept and checked by the poller until it expires, so they're limited
+    if !lnurl_rate_allowed(&ctx.state.remote_ip) {
+        warn!("Too many lnurl-pay invoices asked for from {}", ctx.state.remote_ip);
+        return lnurl_error_response("Too many requests, try again in a minute");
+    }
+
+    let expires_after = Utc::now().timestamp();
+    match dbif::count_pending_lnurl_invoices_in_db(&ctx.helipad_config.database_file_path, &name, expires_after) {
+        Ok(pending) if pending < LNURL_MAX_PENDING_INVOICES => {}
+        Ok(_) => {
+            warn!("Too many unpaid lnurl-pay invoices for {}", name);
+            return lnurl_error_response("Too many unpaid invoices, try again later");
+        }
+        Err(e) => {
+            error!("Error counting pending lnurl invoices: {}", e);
+            return lnurl_error_response("Unable to create invoice");
+        }
+    }
+
+    //The invoice commits to the metadata (and payer data if given) through its description hash
+    let description_hash = lightning::lnurl_description_hash(&lnurl_metadata(&ctx, &name), payer_data.as_deref());
+
+    let mut lightning = match lightning::shared_lnd(&ctx.helipad_config).await {
+        Some(lndconn) => lndconn,
+        None => {
+            return lnurl_error_response("Unable to connect to the lightning node");
+        }
+    };
+
+    let invoice = Invoice {
+        value_msat: amount as i64,
+        description_hash,
+        expiry: LNURL_INVOICE_EXPIRY,
+        ..Default::default()
+    };
+
+    let added = match metrics::observe_lnd("add_invoice", lnd::Lnd::add_invoice(&mut lightning, invoice)).await {
+        Ok(added) => added,
+        Err(e) => {
+            error!("Error adding lnurl invoice: {}", e);
+            return lnurl_error_response("Unable to create invoice");
+        }
+    };
+
+    let created = Utc::now().timestamp();
+    let record = dbif::LnurlInvoiceRecord {
+        payment_hash: HEXLOWER.encode(&added.r_hash),
+        add_index: added.add_index,
+        name,
+        value_msat: amount as i64,
+        comment,
+        payer_data,
+        created,
+        expires: created + LNURL_INVOICE_EXPIRY,
+    };
+
+    if let Err(e) = dbif::add_lnurl_invoice_to_db(&ctx.helipad_config.database_file_path, &record) {
+        error!("Error storing lnurl invoice: {}", e);
+        return lnurl_error_response("Unable to create invoice");
+    }
+
+    debug!("LNURL invoice created: {:?}", record);
+
+    lnurl_response(json!({
+        "pr": added.payment_request,
+        "routes": [],
+        "successAction": {
+            "tag": "message",
+            "message": "Thanks for the boost!",
+        },
+    }))
+}
+
+//API - list destinations, or add/update one when posted to
+pub async fn api_v1_destinations(_ctx: Context) -> Response {
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let visible = visible_destinations(&_ctx, None);
+
+    if _ctx.req.method() == Method::POST {
+        if !user_can(&_ctx, AuthUser::can_manage) {
+            return forbidden_response();
+        }
+
+        let post_vars = get_post_params(_ctx.req).await;
+
+        //Parameter - name (String)
+        let name = post_vars.get("name").cloned().unwrap_or_default();
+
+        //Parameter - custom_key (unsigned int)
+        let custom_key = match post_vars.get("custom_key").filter(|key| !key.is_empty()) {
+            Some(key) => match key.parse::<u64>() {
+                Ok(key) => Some(key),
+                Err(_) => {
+                    return HelipadError::BadRequest("'custom_key' must be an unsigned integer.".to_string()).into();
+                }
+            },
+            None => None,
+        };
+
+        //Parameter - custom_value (String)
+        let custom_value = post_vars.get("custom_value").filter(|val| !val.is_empty()).cloned();
+
+        //Parameter - podcast_guid (String)
+        let podcast_guid = post_vars.get("podcast_guid").filter(|guid| !guid.is_empty()).cloned();
+
+        let destination = dbif::DestinationRecord {
+            name,
+            custom_key,
+            custom_value,
+            podcast_guid,
+        };
+
+        if let Err(e) = save_destination(&database_file_path, &destination) {
+            return e.into();
+        }
+    }
+
+    match list_destinations(&database_file_path, visible) {
+        Ok(destinations) => {
+            json_response(destinations)
+        }
+        Err(e) => {
+            e.into()
+        }
+    }
+}
+
+//Get the destinations, limited to the visible ones when given
+pub fn list_destinations(database_file_path: &String, visible: Option<Vec<String>>) -> Result<Vec<dbif::DestinationRecord>, HelipadError> {
+    let mut destinations = dbif::get_destinations_from_db(database_file_path)
+        .map_err(|e| HelipadError::database("Error getting destinations", e))?;
+
+    if let Some(visible) = visible {
+        destinations.retain(|dest| visible.contains(&dest.name));
+    }
+
+    Ok(destinations)
+}
+
+//Add a user account, checking it over first
+pub fn create_user(database_file_path: &String, username: &str, password: &str, role: Role, destinations: Vec<String>) -> Result<u64, HelipadError> {
+    if username.is_empty() || username == auth::CONFIG_ADMIN_USERNAME {
+        return Err(HelipadError::BadRequest(format!("'username' is a required parameter and can't be '{}'.", auth::CONFIG_ADMIN_USERNAME)));
+    }
+
+    if password.is_empty() {
+        return Err(HelipadError::BadRequest("'password' is a required parameter.".to_string()));
+    }
+
+    if role == Role::Producer && destinations.is_empty() {
+        return Err(HelipadError::BadRequest("Producers need at least one destination in 'destinations'.".to_string()));
+    }
+
+    let password_hash = auth::hash_password(password)
+        .map_err(|e| HelipadError::internal("Error hashing password", e))?;
+
+    let user = dbif::UserRecord {
+        id: 0,
+        username: username.to_string(),
+        password_hash,
+        role: role.as_str().to_string(),
+        destinations,
+        disabled: false,
+        created: Utc::now().timestamp(),
+    };
+
+    dbif::add_user_to_db(database_file_path, &user)
+        .map_err(|e| HelipadError::database("Error adding user", e))
+}
+
+//Add or update a destination after checking it can be served as a lightning/keysend address
+pub fn save_destination(database_file_path: &String, destination: &dbif::DestinationRecord) -> Result<(), HelipadError> {
+    if !is_valid_lnurl_name(&destination.name) {
+        return Err(HelipadError::BadRequest("'name' is a required parameter and may only contain a-z, 0-9, '-', '_' and '.'.".to_string()));
+    }
+
+    if destination.custom_key.is_some() != destination.custom_value.is_some() {
+        return Err(HelipadError::BadRequest("'custom_key' and 'custom_value' must be given together.".to_string()));
+    }
+
+    dbif::add_destination_to_db(database_file_path, destination)
+        .map_err(|e| HelipadError::database("Error adding destination", e))?;
+
+    Ok(())
+}
+
+pub async fn api_v1_delete_destination(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
+
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - name (String)
+    let name = match post_vars.get("name") {
+        Some(name) => name,
+        None => {
+            return HelipadError::BadRequest("No name specified.".to_string()).into();
+        }
+    };
+
+    match dbif::delete_destination_from_db(&database_file_path, name) {
+        Ok(deleted) => {
+            json_response(json!({
+                "success": deleted,
+            }))
+        }
+        Err(e) => {
+            HelipadError::database("Error deleting destination", e).into()
+        }
+    }
+}
+
+//API - list user accounts, or add one when posted to
+pub async fn api_v1_users(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
+
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+
+    if _ctx.req.method() == Method::POST {
+        let post_vars = get_post_params(_ctx.req).await;
+
+        //Parameter - username (String)
+        let username = post_vars.get("username").map(|name| name.trim()).unwrap_or("");
+
+        //Parameter - password (String)
+        let password = post_vars.get("password").map(|password| password.as_str()).unwrap_or("");
+
+        //Parameter - role (admin, producer or viewer)
+        let role = match post_vars.get("role").map(|role| role.parse::<Role>()) {
+            Some(Ok(role)) => role,
+            _ => {
+                return HelipadError::BadRequest("'role' must be one of admin, producer or viewer.".to_string()).into();
+            }
+        };
+
+        //Parameter - destinations (comma separated list of destination names)
+        let destinations: Vec<String> = post_vars.get("destinations")
+            .map(|list| list.split(',').map(str::trim).filter(|dest| !dest.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+
+        if let Err(e) = create_user(&database_file_path, username, password, role, destinations) {
+            return e.into();
+        }
+    }
+
+    match dbif::get_users_from_db(&database_file_path) {
+        Ok(users) => {
+            json_response(users)
+        }
+        Err(e) => {
+            HelipadError::database("Error getting users", e).into()
+        }
+    }
+}
+
+//API - disable (or re-enable) a user account
+pub async fn api_v1_disable_user(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
+
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - id (unsigned int)
+    let id = match post_vars.get("id").map(|id| id.parse::<u64>()) {
+        Some(Ok(id)) => id,
+        _ => {
+            return HelipadError::BadRequest("'id' is a required parameter and must be an unsigned integer.".to_string()).into();
+        }
+    };
+
+    //Parameter - disabled (bool, defaults to true)
+    let disabled = post_vars.get("disabled").map(|val| val != "false").unwrap_or(true);
+
+    match dbif::set_user_disabled_in_db(&database_file_path, id, disabled) {
+        Ok(updated) => {
+            json_response(json!({
+                "success": updated,
+            }))
+        }
+        Err(e) => {
+            HelipadError::database("Error updating user", e).into()
+        }
+    }
+}
+
+//API - list the active login sessions
+pub async fn api_v1_sessions(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
+
+    match dbif::get_active_sessions_from_db(&_ctx.helipad_config.database_file_path, auth::session_cutoff()) {
+        Ok(sessions) => {
+            json_response(sessions)
+        }
+        Err(e) => {
+            HelipadError::database("Error getting sessions", e).into()
+        }
+    }
+}
+
+//API - revoke a login session, logging it out
+pub async fn api_v1_revoke_session(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
+
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - id (String)
+    let id = match post_vars.get("id") {
+        Some(id) => id,
+        None => {
+            return HelipadError::BadRequest("No id specified.".to_string()).into();
+        }
+    };
+
+    match dbif::revoke_session_in_db(&database_file_path, id) {
+        Ok(revoked) => {
+            json_response(json!({
+                "success": revoked,
+            }))
+        }
+        Err(e) => {
+            HelipadError::database("Error revoking session", e).into()
         }
     }
 }
 
-//API - get the current invoice index number
-pub async fn api_v1_index_options(_ctx: Context) -> Response {
-    return hyper::Response::builder()
-        .status(StatusCode::from_u16(204).unwrap())
-        .header("Access-Control-Allow-Methods", "GET, OPTIONS")
-        .body(format!("").into())
-        .unwrap();
-}
+//API - list the api tokens, or create one when posted to
+pub async fn api_v1_tokens(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
 
-pub async fn api_v1_index(_ctx: Context) -> Response {
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
 
-    //Get the last known invoice index from the database
-    match dbif::get_last_boost_index_from_db(&_ctx.helipad_config.database_file_path) {
-        Ok(index) => {
-            println!("** get_last_boost_index_from_db() -> [{}]", index);
-            let json_doc_raw = serde_json::to_string_pretty(&index).unwrap();
-            let json_doc: String = strip::strip_tags(&json_doc_raw);
+    if _ctx.req.method() == Method::POST {
+        let post_vars = get_post_params(_ctx.req).await;
 
-            return hyper::Response::builder()
-                .status(StatusCode::OK)
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Content-Type", "application/json")
-                .body(format!("{}", json_doc).into())
-                .unwrap();
+        //Parameter - name (String)
+        let name = match post_vars.get("name").map(|name| name.trim()) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => {
+                return HelipadError::BadRequest("'name' is a required parameter.".to_string()).into();
+            }
+        };
+
+        //Parameter - scope (read or payments, defaults to read)
+        let scope = match post_vars.get("scope").map(|scope| scope.parse::<TokenScope>()) {
+            Some(Ok(scope)) => scope,
+            Some(Err(_)) => {
+                return HelipadError::BadRequest("'scope' must be either read or payments.".to_string()).into();
+            }
+            None => TokenScope::Read,
+        };
+
+        let (token, token_hash) = auth::generate_api_token();
+
+        let mut record = dbif::ApiTokenRecord {
+            id: 0,
+            name,
+            token_hash,
+            scope: scope.as_str().to_string(),
+            created: Utc::now().timestamp(),
+            last_used: None,
+            revoked: false,
+        };
+
+        record.id = match dbif::add_api_token_to_db(&database_file_path, &record) {
+            Ok(id) => id,
+            Err(e) => {
+                return HelipadError::database("Error adding api token", e).into();
+            }
+        };
+
+        //The token itself is only ever shown here
+        return json_response(json!({
+            "success": true,
+            "token": token,
+            "data": record,
+        }));
+    }
+
+    match dbif::get_api_tokens_from_db(&database_file_path) {
+        Ok(tokens) => {
+            json_response(tokens)
         }
         Err(e) => {
-            eprintln!("** Error getting current db index: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting current db index.").into())
-                .unwrap();
+            HelipadError::database("Error getting api tokens", e).into()
         }
-    };
+    }
 }
 
-//API - get the current payment index number
-pub async fn api_v1_sent_index_options(_ctx: Context) -> Response {
-    options_response("GET, OPTIONS".into())
-}
+//API - revoke an api token
+pub async fn api_v1_revoke_token(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
 
-pub async fn api_v1_sent_index(_ctx: Context) -> Response {
-    //Get the last known payment index from the database
-    match dbif::get_last_payment_index_from_db(&_ctx.helipad_config.database_file_path) {
-        Ok(index) => {
-            println!("** get_last_payment_index_from_db() -> [{}]", index);
-            json_response(index)
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - id (unsigned int)
+    let id = match post_vars.get("id").map(|id| id.parse::<u64>()) {
+        Some(Ok(id)) => id,
+        _ => {
+            return HelipadError::BadRequest("'id' is a required parameter and must be an unsigned integer.".to_string()).into();
+        }
+    };
+
+    match dbif::revoke_api_token_in_db(&database_file_path, id) {
+        Ok(revoked) => {
+            json_response(json!({
+                "success": revoked,
+            }))
         }
         Err(e) => {
-            eprintln!("** Error getting current db index: {}.\n", e);
-            server_error_response("** Error getting current db index.".into())
+            HelipadError::database("Error revoking api token", e).into()
         }
     }
 }
 
-//API - serve sent as JSON either in ascending or descending order
-pub async fn api_v1_sent_options(_ctx: Context) -> Response {
-    options_response("GET, OPTIONS".into())
-}
+//API - the most recent login attempts from the audit log
+pub async fn api_v1_login_attempts(_ctx: Context) -> Response {
+    if !user_can(&_ctx, AuthUser::can_manage) {
+        return forbidden_response();
+    }
 
-pub async fn api_v1_sent(_ctx: Context) -> Response {
     //Get query parameters
     let params: HashMap<String, String> = _ctx.req.uri().query().map(|v| {
         url::form_urlencoded::parse(v.as_bytes()).into_owned().collect()
-    }).unwrap_or_else(HashMap::new);
-
-    //Parameter - index (unsigned int)
-    let index = match params.get("index") {
-        Some(supplied_index) => {
-            match supplied_index.parse::<u64>() {
-                Ok(index) => {
-                    println!("** Supplied index from call: [{}]", index);
-                    index
-                }
-                Err(_) => {
-                    eprintln!("** Error getting sent boosts: 'index' param is not a number.\n");
-                    return client_error_response("** 'index' is a required parameter and must be an unsigned integer.".into());
-                }
-            }
-        }
-        None => {
-            eprintln!("** Error getting sent boosts: 'index' param is not present.\n");
-            return client_error_response("** 'index' is a required parameter and must be an unsigned integer.".into())
-        }
-    };
+    }).unwrap_or_default();
 
-    //Parameter - boostcount (unsigned int)
-    let boostcount = match params.get("count") {
-        Some(bcount) => {
-            match bcount.parse::<u64>() {
-                Ok(boostcount) => {
-                    println!("** Supplied sent boost count from call: [{}]", boostcount);
-                    boostcount
-                }
-                Err(_) => {
-                    eprintln!("** Error getting sent boosts: 'count' param is not a number.\n");
-                    return client_error_response("** 'count' is a required parameter and must be an unsigned integer.".into())
-                }
-            }
+    //Parameter - count (unsigned int, defaults to 100)
+    let count = match params.get("count").map(|count| count.parse::<u64>()) {
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            return HelipadError::BadRequest("'count' must be an unsigned integer.".to_string()).into();
         }
-        None => {
-            eprintln!("** Error getting sent boosts: 'count' param is not present.\n");
-            return client_error_response("** 'count' is a required parameter and must be an unsigned integer.".into())
-        }
-    };
-
-    //Parameter - old (bool)
-    let old = match params.get("old") {
-        Some(old_val) => match old_val.parse::<bool>() {
-            Ok(val) => val,
-            Err(_) => false,
-        },
-        None => false,
+        None => 100,
     };
 
-    //Get sent boosts from db for returning
-    match dbif::get_payments_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, true) {
-        Ok(sent_boosts) => {
-            json_response(sent_boosts)
+    match dbif::get_login_attempts_from_db(&_ctx.helipad_config.database_file_path, count) {
+        Ok(attempts) => {
+            json_response(attempts)
         }
         Err(e) => {
-            eprintln!("** Error getting sent boosts: {}.\n", e);
-            server_error_response("** Error getting sent boosts.".into())
+            HelipadError::database("Error getting login attempts", e).into()
         }
     }
 }
 
-pub async fn api_v1_reply_options(_ctx: Context) -> Response {
-    options_response("POST, OPTIONS".to_string())
+//Two-factor settings can only be changed from a browser login, not with an api token
+fn two_factor_user(ctx: &Context) -> Option<AuthUser> {
+    ctx.user.clone().filter(|user| user.token_scope.is_none())
 }
 
-pub async fn api_v1_reply(_ctx: Context) -> Response {
-    let post_vars = get_post_params(_ctx.req).await;
-
-    //Parameter - index (unsigned int)
-    let index = match post_vars.get("index") {
-        Some(index) => match index.parse::<u64>() {
-            Ok(index) => index,
-            Err(_) => {
-                eprintln!("** Error parsing reply params: 'index' param is not a number.\n");
-                return client_error_response("** 'index' is a required parameter and must be an unsigned integer.".into());
-            }
-        },
-        None => {
-            return client_error_response("** No index specified.".to_string());
-        },
+//API - the two-factor status of the logged in user
+pub async fn api_v1_totp(_ctx: Context) -> Response {
+    let user = match two_factor_user(&_ctx) {
+        Some(user) => user,
+        None => return forbidden_response(),
     };
 
-    //Parameter - sats (unsigned int)
-    let sats = match post_vars.get("sats") {
-        Some(sats) => match sats.parse::<u64>() {
-            Ok(sats) => sats,
-            Err(_) => {
-                eprintln!("** Error parsing reply params: 'sats' param is not a number.\n");
-                return client_error_response("** 'sats' is a required parameter and must be an unsigned integer.".into());
-            }
-        },
-        None => {
-            return client_error_response("** No sats specified.".to_string());
-        },
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+
+    let totp = match dbif::get_totp_from_db(&database_file_path, user.id) {
+        Ok(totp) => totp,
+        Err(e) => {
+            return HelipadError::database("Error getting two-factor status", e).into();
+        }
     };
 
-    let sender = match post_vars.get("sender") {
-        Some(name) => name,
-        None => "Anonymous"
+    let recovery_codes_left = match dbif::get_unused_recovery_codes_from_db(&database_file_path, user.id) {
+        Ok(codes) => codes.len(),
+        Err(e) => {
+            return HelipadError::database("Error getting two-factor status", e).into();
+        }
     };
 
-    let message = match post_vars.get("message") {
-        Some(msg) => msg,
-        None => ""
+    json_response(json!({
+        "enabled": totp.as_ref().map(|totp| totp.enabled).unwrap_or(false),
+        "pending": totp.as_ref().map(|totp| !totp.enabled).unwrap_or(false),
+        "recovery_codes_left": recovery_codes_left,
+    }))
+}
+
+//API - start setting up two-factor, giving back the secret to add to an authenticator app
+pub async fn api_v1_totp_enroll(_ctx: Context) -> Response {
+    let user = match two_factor_user(&_ctx) {
+        Some(user) => user,
+        None => return forbidden_response(),
     };
 
-    let boosts = match dbif::get_boosts_from_db(&_ctx.helipad_config.database_file_path, index, 1, true, true) {
-        Ok(items) => items,
-        Err(_) => {
-            return server_error_response("** Error finding boost index.".to_string());
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+
+    match auth::two_factor_enabled(&database_file_path, user.id) {
+        Ok(false) => {},
+        Ok(true) => {
+            return HelipadError::BadRequest("Two-factor is already enabled.  Disable it first to enroll again.".to_string()).into();
         }
+        Err(e) => {
+            return HelipadError::database("Error getting two-factor status", e).into();
+        }
+    }
+
+    let totp = dbif::TotpRecord {
+        user_id: user.id,
+        secret: auth::generate_totp_secret(),
+        enabled: false,
+        last_step: 0,
+        created: Utc::now().timestamp(),
     };
 
-    if boosts.is_empty() {
-        return server_error_response("** Unknown boost index.".to_string());
+    if let Err(e) = dbif::set_totp_in_db(&database_file_path, &totp) {
+        return HelipadError::database("Error storing two-factor secret", e).into();
     }
 
-    let boost = &boosts[0];
-    let tlv = boost.parse_tlv().unwrap();
+    let uri = auth::totp_provisioning_uri(&totp.secret, &user.username);
 
-    let pub_key = tlv["reply_address"].as_str().unwrap_or_default().to_string();
-    let custom_key = tlv["reply_custom_key"].as_u64();
-    let custom_value = match tlv["reply_custom_value"].as_str() {
-        Some(rcv) => Some(rcv.to_string()),
-        None => None
+    let qr_code = match auth::qr_code_data_uri(&uri) {
+        Ok(qr_code) => qr_code,
+        Err(e) => {
+            return HelipadError::internal("Error making QR code", e).into();
+        }
     };
 
-    if pub_key == "" {
-        return client_error_response("** No reply_address found in boost".to_string());
-    }
+    json_response(json!({
+        "secret": totp.secret,
+        "uri": uri,
+        "qr_code": qr_code,
+    }))
+}
 
-    if custom_key.is_some() && custom_value.is_none() {
-        return client_error_response("** No reply_custom_value found in boost".to_string());
-    }
+//API - finish setting up two-factor with a code from the authenticator app, giving back the recovery codes
+pub async fn api_v1_totp_confirm(_ctx: Context) -> Response {
+    let user = match two_factor_user(&_ctx) {
+        Some(user) => user,
+        None => return forbidden_response(),
+    };
 
-    let reply_tlv = json!({
-        "app_name": "Helipad",
-        "app_version": _ctx.state.version,
-        "podcast": tlv["podcast"].as_str().unwrap_or_default(),
-        "episode": tlv["episode"].as_str().unwrap_or_default(),
-        "name": tlv["sender_name"].as_str().unwrap_or_default(),
-        "sender_name": sender,
-        "message": message,
-        "action": "boost",
-        "value_msat": sats * 1000,
-        "value_msat_total": sats * 1000,
-    });
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
 
-    let helipad_config = _ctx.helipad_config.clone();
-    let lightning = match lightning::connect_to_lnd(helipad_config.node_address, helipad_config.cert_path, helipad_config.macaroon_path).await {
-        Some(lndconn) => lndconn,
+    //Parameter - code (String)
+    let code = match post_vars.get("code") {
+        Some(code) => code,
         None => {
-            return server_error_response("** Error connecting to LND.".to_string())
+            return HelipadError::BadRequest("No code specified.".to_string()).into();
         }
     };
 
-    let payment = match lightning::send_boost(lightning, pub_key, custom_key, custom_value, sats, reply_tlv.clone()).await {
-        Ok(payment) => payment,
+    let totp = match dbif::get_totp_from_db(&database_file_path, user.id) {
+        Ok(Some(totp)) if !totp.enabled => totp,
+        Ok(_) => {
+            return HelipadError::BadRequest("No two-factor enrollment is waiting to be confirmed.".to_string()).into();
+        }
         Err(e) => {
-            eprintln!("** Error sending boost: {}", e);
-            return server_error_response(format!("** Error sending boost: {}", e))
+            return HelipadError::database("Error getting two-factor secret", e).into();
         }
     };
 
-    let mut cache = podcastindex::GuidCache::new(1);
-
-    let mut boost = match lightning::parse_boost_from_payment(payment, &mut cache).await {
-        Some(boost) => boost,
+    let step = match auth::verify_totp(&totp.secret, code, totp.last_step) {
+        Some(step) => step,
         None => {
-            eprintln!("** Error parsing sent boost");
-            return server_error_response("** Error parsing sent boost".into())
+            return HelipadError::BadRequest("Bad two-factor code.".to_string()).into();
         }
     };
 
-    if let Some(pay_info) = boost.payment_info {
-        boost.payment_info = Some(dbif::PaymentRecord {
-            reply_to_idx: Some(index),
-            ..pay_info
-        });
-    }
+    let (recovery_codes, recovery_code_hashes) = auth::generate_recovery_codes();
 
-    //Give some output
-    println!("Sent Boost: {:#?}", boost);
+    if let Err(e) = dbif::set_recovery_codes_in_db(&database_file_path, user.id, &recovery_code_hashes) {
+        return HelipadError::database("Error storing recovery codes", e).into();
+    }
 
-    //Store in the database
-    match dbif::add_payment_to_db(&_ctx.helipad_config.database_file_path, &boost) {
-        Ok(_) => println!("New sent boost added."),
-        Err(e) => eprintln!("Error adding sent boost: {:#?}", e)
+    if let Err(e) = dbif::enable_totp_in_db(&database_file_path, user.id, step) {
+        return HelipadError::database("Error enabling two-factor", e).into();
     }
 
+    info!("Two-factor enabled for user {}", user.username);
+
+    //The recovery codes are only ever shown here
     json_response(json!({
         "success": true,
-        "data": boost,
+        "recovery_codes": recovery_codes,
     }))
 }
 
-pub async fn api_v1_mark_replied(_ctx: Context) -> Response {
-    let post_vars = get_post_params(_ctx.req).await;
-
-    //Parameter - index (unsigned int)
-    let index = match post_vars.get("index") {
-        Some(index) => match index.parse::<u64>() {
-            Ok(index) => index,
-            Err(_) => {
-                eprintln!("** Error parsing reply params: 'index' param is not a number.\n");
-                return client_error_response("** 'index' is a required parameter and must be an unsigned integer.".into());
-            }
-        },
-        None => {
-            return client_error_response("** No index specified.".to_string());
-        },
+//API - turn off two-factor, which needs a current two-factor or recovery code
+pub async fn api_v1_totp_disable(_ctx: Context) -> Response {
+    let user = match two_factor_user(&_ctx) {
+        Some(user) => user,
+        None => return forbidden_response(),
     };
 
-    let result = dbif::mark_boost_as_replied(&_ctx.helipad_config.database_file_path, index);
+    let database_file_path = _ctx.helipad_config.database_file_path.clone();
+    let post_vars = get_post_params(_ctx.req).await;
+
+    //Parameter - code (String)
+    let code = post_vars.get("code").map(String::as_str).unwrap_or_default();
 
-    if let Err(e) = result {
-        eprintln!("** Error marking boost as replied: {}", e);
-        return server_error_response(format!("** Error marking boost as replied: {}", e))
+    match auth::check_second_factor(&database_file_path, user.id, code) {
+        Ok(true) => {},
+        Ok(false) => {
+            return HelipadError::BadRequest("Bad two-factor code.".to_string()).into();
+        }
+        Err(e) => {
+            return HelipadError::database("Error checking two-factor code", e).into();
+        }
     }
 
-    json_response(json!({
-        "success": true,
-    }))
+    match dbif::delete_totp_from_db(&database_file_path, user.id) {
+        Ok(deleted) => {
+            info!("Two-factor disabled for user {}", user.username);
+            json_response(json!({
+                "success": deleted,
+            }))
+        }
+        Err(e) => {
+            HelipadError::database("Error disabling two-factor", e).into()
+        }
+    }
 }
 
 //CSV export - max is 200 for now so the csv content can be built in memory
@@ -930,24 +2178,18 @@ pub async fn csv_export_boosts(_ctx: Context) -> Response {
         Some(supplied_index) => {
             index = match supplied_index.parse::<u64>() {
                 Ok(index) => {
-                    println!("** Supplied index from call: [{}]", index);
+                    debug!("Supplied index from call: [{}]", index);
                     index
                 }
                 Err(_) => {
-                    eprintln!("** Error getting boosts: 'index' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting boosts: 'index' param is not a number");
+                    return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting boosts: 'index' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'index' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting boosts: 'index' param is not present");
+            return HelipadError::BadRequest("'index' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -957,24 +2199,18 @@ pub async fn csv_export_boosts(_ctx: Context) -> Response {
         Some(bcount) => {
             boostcount = match bcount.parse::<u64>() {
                 Ok(boostcount) => {
-                    println!("** Supplied boostcount from call: [{}]", boostcount);
+                    debug!("Supplied boostcount from call: [{}]", boostcount);
                     boostcount
                 }
                 Err(_) => {
-                    eprintln!("** Error getting boosts: 'count' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                        .unwrap();
+                    warn!("Error getting boosts: 'count' param is not a number");
+                    return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
                 }
             };
         }
         None => {
-            eprintln!("** Error getting boosts: 'count' param is not present.\n");
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(400).unwrap())
-                .body(format!("** 'count' is a required parameter and must be an unsigned integer.").into())
-                .unwrap();
+            warn!("Error getting boosts: 'count' param is not present");
+            return HelipadError::BadRequest("'count' is a required parameter and must be an unsigned integer.".to_string()).into();
         }
     };
 
@@ -991,32 +2227,35 @@ pub async fn csv_export_boosts(_ctx: Context) -> Response {
         Some(endexnum) => {
             endex = match endexnum.parse::<u64>() {
                 Ok(endex) => {
-                    println!("** Supplied endex from call: [{}]", endex);
+                    debug!("Supplied endex from call: [{}]", endex);
                     endex
                 }
                 Err(_) => {
-                    eprintln!("** Error getting boosts: 'endex' param is not a number.\n");
-                    return hyper::Response::builder()
-                        .status(StatusCode::from_u16(400).unwrap())
-                        .body(format!("** 'endex' parameter must be an integer.").into())
-                        .unwrap();
+                    warn!("Error getting boosts: 'endex' param is not a number");
+                    return HelipadError::BadRequest("'endex' parameter must be an integer.".to_string()).into();
                 }
             };
         }
         None => {}
     };
 
+    //Parameter - destination (String)
+    let destinations = visible_destinations(&_ctx, params.get("destination"));
+
     //Get the boosts/streams/sent from db for returning
     let results;
 
     if list == "streams" {
-        results = dbif::get_streams_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, false);
+        results = dbif::get_streams_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, false, destinations.as_deref());
     }
     else if list == "sent" {
+        if !user_can(&_ctx, AuthUser::can_view_sent) {
+            return forbidden_response();
+        }
         results = dbif::get_payments_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, false);
     }
     else { // boosts
-        results = dbif::get_boosts_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, false);
+        results = dbif::get_boosts_from_db(&_ctx.helipad_config.database_file_path, index, boostcount, old, false, destinations.as_deref());
     }
 
     match results {
@@ -1024,70 +2263,203 @@ pub async fn csv_export_boosts(_ctx: Context) -> Response {
             let mut csv = String::new();
 
             //CSV column name header
-            csv.push_str(format!("count,index,time,value_msat,value_sat,value_msat_total,value_sat_total,action,sender,app,message,podcast,episode,remote_podcast,remote_episode\n").as_str());
+            csv.push_str(CSV_HEADER);
 
             //Iterate the boost set
             let mut count: u64 = 1;
             for boost in boosts {
-                //Parse out a friendly date
-                let dt = NaiveDateTime::from_timestamp(boost.time, 0);
-                let boost_time = dt.format("%e %b %Y %H:%M:%S UTC").to_string();
-
-                //Translate to sats
-                let mut value_sat = 0;
-                if boost.value_msat > 1000 {
-                    value_sat = boost.value_msat / 1000;
-                }
-                let mut value_sat_total = 0;
-                if boost.value_msat_total > 1000 {
-                    value_sat_total = boost.value_msat_total / 1000;
-                }
-
-                //The main export data formatting
-                csv.push_str(
-                    format!(
-                        "{},{},\"{}\",{},{},{},{},{},\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"\n",
-                        count,
-                        boost.index,
-                        boost_time,
-                        boost.value_msat,
-                        value_sat,
-                        boost.value_msat_total,
-                        value_sat_total,
-                        boost.action,
-                        BoostRecord::escape_for_csv(boost.sender),
-                        BoostRecord::escape_for_csv(boost.app),
-                        BoostRecord::escape_for_csv(boost.message),
-                        BoostRecord::escape_for_csv(boost.podcast),
-                        BoostRecord::escape_for_csv(boost.episode),
-                        BoostRecord::escape_for_csv(boost.remote_podcast.unwrap_or("".to_string())),
-                        BoostRecord::escape_for_csv(boost.remote_episode.unwrap_or("".to_string()))
-                    ).as_str()
-                );
+                let index = boost.index;
+                csv.push_str(&csv_row(count, boost));
 
                 //Keep count
                 count += 1;
 
                 //If an exit point was given then bail when it's reached
-                if (old && boost.index <= endex) || (!old && boost.index >= endex) {
+                if (old && index <= endex) || (!old && index >= endex) {
                     break;
                 }
             }
 
             return hyper::Response::builder()
                 .status(StatusCode::OK)
-                .header("Access-Control-Allow-Origin", "*")
                 .header("Content-type", "text/plain; charset=utf-8")
                 .header("Content-Disposition", format!("attachment; filename=\"{}.csv\"", list))
                 .body(format!("{}", csv).into())
                 .unwrap();
         }
         Err(e) => {
-            eprintln!("** Error getting boosts: {}.\n", e);
-            return hyper::Response::builder()
-                .status(StatusCode::from_u16(500).unwrap())
-                .body(format!("** Error getting boosts.").into())
-                .unwrap();
+            HelipadError::database("Error getting boosts", e).into()
+        }
+    }
+}
+
+//One line of a CSV export
+pub fn csv_row(count: u64, boost: BoostRecord) -> String {
+    //Parse out a friendly date
+    let dt = DateTime::from_timestamp(boost.time, 0).unwrap_or_default();
+    let boost_time = dt.format("%e %b %Y %H:%M:%S UTC").to_string();
+
+    //Translate to sats
+    let mut value_sat = 0;
+    if boost.value_msat > 1000 {
+        value_sat = boost.value_msat / 1000;
+    }
+    let mut value_sat_total = 0;
+    if boost.value_msat_total > 1000 {
+        value_sat_total = boost.value_msat_total / 1000;
+    }
+
+    //The main export data formatting
+    format!(
+        "{},{},\"{}\",{},{},{},{},{},\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\",\"{}\"\n",
+        count,
+        boost.index,
+        boost_time,
+        boost.value_msat,
+        value_sat,
+        boost.value_msat_total,
+        value_sat_total,
+        boost.action,
+        BoostRecord::escape_for_csv(boost.sender),
+        BoostRecord::escape_for_csv(boost.app),
+        BoostRecord::escape_for_csv(boost.message),
+        BoostRecord::escape_for_csv(boost.podcast),
+        BoostRecord::escape_for_csv(boost.episode),
+        BoostRecord::escape_for_csv(boost.remote_podcast.unwrap_or("".to_string())),
+        BoostRecord::escape_for_csv(boost.remote_episode.unwrap_or("".to_string())),
+        BoostRecord::escape_for_csv(boost.source),
+        BoostRecord::escape_for_csv(boost.destination.unwrap_or("".to_string()))
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn test_database(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("helipad-test-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let path = path.to_string_lossy().to_string();
+        dbif::create_database(&path).unwrap();
+        path
+    }
+
+    fn add_attempt(database_file_path: &String, remote_ip: &str, username: &str, time: i64, success: bool) {
+        let attempt = dbif::LoginAttemptRecord {
+            index: 0,
+            time,
+            remote_ip: remote_ip.to_string(),
+            username: username.to_string(),
+            success,
+            reason: "".to_string(),
+        };
+        dbif::add_login_attempt_to_db(database_file_path, &attempt).unwrap();
+    }
+
+    fn callback_params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn lnurl_callback_amount_must_be_in_range() {
+        assert!(lnurl_callback_params(&callback_params(&[])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "-1000")])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "10 sats")])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "999")])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "1000000001")])).is_err());
+
+        assert_eq!(lnurl_callback_params(&callback_params(&[("amount", "1000")])), Ok((1000, "".to_string(), None)));
+        assert_eq!(lnurl_callback_params(&callback_params(&[("amount", "1000000000")])).map(|params| params.0), Ok(1000000000));
+    }
+
+    #[test]
+    fn lnurl_callback_comment_is_limited_in_characters() {
+        let longest = "é".repeat(LNURL_COMMENT_ALLOWED);
+        let too_long = "é".repeat(LNURL_COMMENT_ALLOWED + 1);
+
+        assert_eq!(lnurl_callback_params(&callback_params(&[("amount", "5000"), ("comment", &longest)])), Ok((5000, longest.clone(), None)));
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "5000"), ("comment", &too_long)])).is_err());
+    }
+
+    #[test]
+    fn lnurl_callback_payer_data_must_be_an_object() {
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "5000"), ("payerdata", "not json")])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "5000"), ("payerdata", r#"["bob"]"#)])).is_err());
+        assert!(lnurl_callback_params(&callback_params(&[("amount", "5000"), ("payerdata", r#""bob""#)])).is_err());
+    }
+
+    #[test]
+    fn lnurl_callback_hashes_payer_data_as_sent() {
+        //The wallet checks the hash against the exact payer data it sent, so it mustn't be reformatted
+        let payer_data = r#"{ "name": "bob" }"#;
+        let metadata = r#"[["text/plain","Boost alice"]]"#;
+
+        let (_, _, kept) = lnurl_callback_params(&callback_params(&[("amount", "5000"), ("payerdata", payer_data)])).unwrap();
+        assert_eq!(kept.as_deref(), Some(payer_data));
+
+        let expected = Sha256::digest(format!("{}{}", metadata, payer_data).as_bytes());
+        assert_eq!(lightning::lnurl_description_hash(metadata, kept.as_deref()), expected.to_vec());
+    }
+
+    #[test]
+    fn lockout_after_too_many_failures() {
+        let database_file_path = test_database("lockout");
+        let now = Utc::now().timestamp();
+
+        for _ in 1..auth::MAX_LOGIN_FAILURES {
+            add_attempt(&database_file_path, "10.0.0.1", "admin", now, false);
+        }
+        assert!(!login_locked_out(&database_file_path, "10.0.0.1"));
+
+        add_attempt(&database_file_path, "10.0.0.1", "admin", now, false);
+        assert!(login_locked_out(&database_file_path, "10.0.0.1"));
+        assert!(!login_locked_out(&database_file_path, "10.0.0.2"));
+
+        let _ = std::fs::remove_file(&database_file_path);
+    }
+
+    #[test]
+    fn lockout_ends_when_failures_age_out() {
+        let database_file_path = test_database("lockout-window");
+        let before_window = Utc::now().timestamp() - (auth::LOGIN_LOCKOUT_MINUTES * 60) - 1;
+
+        for _ in 0..auth::MAX_LOGIN_FAILURES {
+            add_attempt(&database_file_path, "10.0.0.1", "admin", before_window, false);
+        }
+        assert!(!login_locked_out(&database_file_path, "10.0.0.1"));
+
+        let _ = std::fs::remove_file(&database_file_path);
+    }
+
+    #[test]
+    fn lockout_resets_after_the_same_user_logs_in() {
+        let database_file_path = test_database("lockout-reset");
+        let now = Utc::now().timestamp();
+
+        for _ in 0..auth::MAX_LOGIN_FAILURES {
+            add_attempt(&database_file_path, "10.0.0.1", "admin", now, false);
+        }
+        add_attempt(&database_file_path, "10.0.0.1", "admin", now, true);
+        assert!(!login_locked_out(&database_file_path, "10.0.0.1"));
+
+        let _ = std::fs::remove_file(&database_file_path);
+    }
+
+    #[test]
+    fn other_users_logging_in_doesnt_reset_lockout() {
+        let database_file_path = test_database("lockout-other-user");
+        let now = Utc::now().timestamp();
+
+        for _ in 1..auth::MAX_LOGIN_FAILURES {
+            add_attempt(&database_file_path, "10.0.0.1", "admin", now, false);
         }
+        add_attempt(&database_file_path, "10.0.0.1", "viewer", now, true);
+        add_attempt(&database_file_path, "10.0.0.1", "admin", now, false);
+        assert!(login_locked_out(&database_file_path, "10.0.0.1"));
+
+        let _ = std::fs::remove_file(&database_file_path);
     }
-}
\ No newline at end of file
+}
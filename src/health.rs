@@ -0,0 +1,215 @@
+use crate::metrics;
+use crate::HelipadConfig;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//The poller checks LND every 9 seconds when idle, so it's stuck if it hasn't finished a check in this long, in seconds
+const POLL_STALE_AFTER: i64 = 120;
+
+//The Podcast Index is only checked this often so healthchecks don't end up calling it every few seconds
+const PODCASTINDEX_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+const PODCASTINDEX_URL: &str = "https://api.podcastindex.org/";
+
+//The readiness answer is kept this long, so hitting /ready often doesn't mean checking everything each time
+const READINESS_CACHE_FOR: Duration = Duration::from_secs(5);
+
+//When the poller last finished checking LND, as a unix timestamp
+static LAST_POLL: AtomicI64 = AtomicI64::new(0);
+
+//When LND last answered the poller, as a unix timestamp, and whether it was synced to the chain the last time it said
+static LND_LAST_ANSWERED: AtomicI64 = AtomicI64::new(0);
+static LND_SYNCED: AtomicBool = AtomicBool::new(false);
+
+static PODCASTINDEX_STATUS: Mutex<Option<(Instant, Component)>> = Mutex::new(None);
+
+static READINESS: Mutex<Option<(Instant, Readiness)>> = Mutex::new(None);
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+//How one of the things Helipad depends on is doing.  Only required components being down make us not ready
+#[derive(Serialize, Clone, Debug)]
+pub struct Component {
+    pub status: &'static str,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_to_chain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_poll: Option<i64>,
+}
+
+impl Component {
+    fn up(required: bool) -> Component {
+        Component {
+            status: "up",
+            required,
+            error: None,
+            synced_to_chain: None,
+            last_poll: None,
+        }
+    }
+
+    fn down(required: bool, error: &str) -> Component {
+        Component {
+            status: "down",
+            error: Some(error.to_string()),
+            ..Component::up(required)
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.status == "up"
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub components: BTreeMap<&'static str, Component>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status == "ready"
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Called by the poller each time it's been through all of its checks of LND
+pub fn poll_completed() {
+    LAST_POLL.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+    metrics::poll_completed();
+}
+
+//Called by the poller each time LND answers it, with whether it's synced to the chain when it was asked
+pub fn lnd_answered(synced_to_chain: Option<bool>) {
+    LND_LAST_ANSWERED.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+    if let Some(synced) = synced_to_chain {
+        LND_SYNCED.store(synced, Ordering::Relaxed);
+    }
+}
+
+pub fn lnd_synced() -> bool {
+    LND_SYNCED.load(Ordering::Relaxed)
+}
+
+//Check everything Helipad needs to do its job, or give back the last answer if it's recent enough
+pub async fn check(helipad_config: &HelipadConfig) -> Readiness {
+    if let Ok(cached) = READINESS.lock() {
+        if let Some((checked, readiness)) = cached.as_ref() {
+            if checked.elapsed() < READINESS_CACHE_FOR {
+                return readiness.clone();
+            }
+        }
+    }
+
+    let mut components = BTreeMap::new();
+    components.insert("database", check_database(helipad_config));
+    components.insert("lnd", check_lnd());
+    components.insert("poller", check_poller());
+    components.insert("podcastindex", check_podcastindex(helipad_config.health_check_timeout).await);
+
+    let ready = components.values().all(|component| component.is_up() || !component.required);
+
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "not_ready" },
+        components,
+    };
+
+    if let Ok(mut cached) = READINESS.lock() {
+        *cached = Some((Instant::now(), readiness.clone()));
+    }
+
+    readiness
+}
+
+fn check_database(helipad_config: &HelipadConfig) -> Component {
+    match dbif::check_database_writable(&helipad_config.database_file_path) {
+        Ok(_) => Component::up(true),
+        Err(e) => {
+            error!("Database is not writable: {}", e);
+            Component::down(true, "The database can't be written to")
+        }
+    }
+}
+
+//Goes by how the poller last got on with LND, rather than calling it for every check
+fn check_lnd() -> Component {
+    let last_answered = LND_LAST_ANSWERED.load(Ordering::Relaxed);
+
+    if last_answered == 0 {
+        return Component::down(true, "LND hasn't answered the poller yet");
+    }
+
+    if chrono::Utc::now().timestamp() - last_answered > POLL_STALE_AFTER {
+        return Component::down(true, "LND hasn't answered the poller recently");
+    }
+
+    let synced = lnd_synced();
+    let mut component = if synced {
+        Component::up(true)
+    } else {
+        Component::down(true, "LND is not synced to the chain")
+    };
+
+    component.synced_to_chain = Some(synced);
+    component
+}
+
+fn check_poller() -> Component {
+    let last_poll = LAST_POLL.load(Ordering::Relaxed);
+
+    let mut component = if last_poll == 0 {
+        Component::down(true, "The poller hasn't finished checking LND yet")
+    } else if chrono::Utc::now().timestamp() - last_poll > POLL_STALE_AFTER {
+        Component::down(true, "The poller hasn't finished checking LND recently")
+    } else {
+        Component::up(true)
+    };
+
+    if last_poll > 0 {
+        component.last_poll = Some(last_poll);
+    }
+
+    component
+}
+
+//Only used to name remote podcasts and episodes, so boosts still come in without it
+//Waits on the Podcast Index for as long as health_check_timeout allows before calling it down
+async fn check_podcastindex(timeout: Duration) -> Component {
+    if let Ok(status) = PODCASTINDEX_STATUS.lock() {
+        if let Some((checked, component)) = status.as_ref() {
+            if checked.elapsed() < PODCASTINDEX_CHECK_INTERVAL {
+                return component.clone();
+            }
+        }
+    }
+
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Error making http client: {}", e);
+            return Component::down(false, "Unable to check the Podcast Index");
+        }
+    };
+
+    //Any answer at all means it can be reached.  The error is only logged, as the answer is public
+    let component = match client.get(PODCASTINDEX_URL).send().await {
+        Ok(_) => Component::up(false),
+        Err(e) => {
+            warn!("Unable to reach the Podcast Index: {}", e);
+            Component::down(false, "Unable to reach the Podcast Index")
+        }
+    };
+
+    if let Ok(mut status) = PODCASTINDEX_STATUS.lock() {
+        *status = Some((Instant::now(), component.clone()));
+    }
+
+    component
+}
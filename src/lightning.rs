@@ -1,11 +1,13 @@
+use crate::error::HelipadError;
+use crate::metrics;
 use crate::podcastindex;
+use crate::HelipadConfig;
 use data_encoding::HEXLOWER;
-use lnd::lnrpc::lnrpc::{SendRequest, Payment, Invoice};
+use lnd::lnrpc::lnrpc::{SendRequest, Payment, Invoice, invoice::InvoiceState};
 use serde_json::Value;
 use sha2::{Sha256, Digest};
 use std::collections::HashMap;
 use std::fs;
-use std::error::Error;
 use rand::RngCore;
 use serde::{Deserialize, Deserializer};
 
@@ -16,6 +18,9 @@ pub const TLV_WALLET_ID: u64 = 112111100;
 pub const TLV_HIVE_ACCOUNT: u64 = 818818;
 pub const TLV_KEYSEND: u64 = 5482373484;
 
+//Made by shared_lnd the first time it's needed
+static SHARED_LND: tokio::sync::Mutex<Option<lnd::Lnd>> = tokio::sync::Mutex::const_new(None);
+
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug)]
@@ -137,27 +142,32 @@ pub struct KeysendAddressCustomData {
     custom_value: String,
 }
 
-#[derive(Debug)]
-pub struct KeysendAddressError(String);
-
-impl std::fmt::Display for KeysendAddressError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "There is an error: {}", self.0)
-    }
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayResponse {
+    tag: String,
+    callback: String,
+    min_sendable: u64,
+    max_sendable: u64,
+    metadata: String,
+    #[serde(default)]
+    comment_allowed: u64,
+    #[serde(default)]
+    payer_data: Option<Value>,
 }
 
-impl std::error::Error for KeysendAddressError {}
-
-#[derive(Debug)]
-pub struct BoostError(String);
-
-impl std::fmt::Display for BoostError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Error: {}", self.0)
-    }
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LnurlInvoiceResponse {
+    pr: String,
 }
 
-impl std::error::Error for BoostError {}
+//One of our own keysend addresses, served from /.well-known/keysend/<name>
+#[derive(Clone, Debug)]
+pub struct KeysendAddress {
+    pub name: String,
+    pub custom_key: Option<u64>,
+    pub custom_value: Option<String>,
+}
 
 pub async fn connect_to_lnd(node_address: String, cert_path: String, macaroon_path: String) -> Option<lnd::Lnd> {
     let cert: Vec<u8>;
@@ -167,7 +177,7 @@ pub async fn connect_to_lnd(node_address: String, cert_path: String, macaroon_pa
             cert = cert_content;
         }
         Err(_) => {
-            eprintln!("Cannot find a valid tls.cert file");
+            error!("Cannot find a valid tls.cert file");
             return None;
         }
     }
@@ -179,7 +189,7 @@ pub async fn connect_to_lnd(node_address: String, cert_path: String, macaroon_pa
             macaroon = macaroon_content;
         }
         Err(_) => {
-            eprintln!("Cannot find a valid admin.macaroon file");
+            error!("Cannot find a valid admin.macaroon file");
             return None;
         }
     }
@@ -188,45 +198,299 @@ pub async fn connect_to_lnd(node_address: String, cert_path: String, macaroon_pa
     let lightning = lnd::Lnd::connect_with_macaroon(node_address.clone(), &cert, &macaroon).await;
 
     if lightning.is_err() {
-        println!("Could not connect to: [{}] using tls: [{}] and macaroon: [{}]", node_address, cert_path, macaroon_path);
-        eprintln!("{:#?}", lightning.err());
+        error!("Could not connect to: [{}] using tls: [{}] and macaroon: [{}]", node_address, cert_path, macaroon_path);
+        error!("{:?}", lightning.err());
         return None;
     }
 
     return lightning.ok();
 }
 
-pub async fn resolve_keysend_address(address: &str) -> Result<KeysendAddressResponse, Box<dyn Error>> {
+//Get the connection shared by requests that call LND, connecting the first time it's needed.  The clones all
+//use the one channel, which reconnects by itself if LND goes away
+pub async fn shared_lnd(helipad_config: &HelipadConfig) -> Option<lnd::Lnd> {
+    let mut shared = SHARED_LND.lock().await;
+
+    if shared.is_none() {
+        *shared = connect_to_lnd(
+            helipad_config.node_address.clone(),
+            helipad_config.cert_path.clone(),
+            helipad_config.macaroon_path.clone(),
+        ).await;
+    }
+
+    shared.clone()
+}
+
+//Parses keysend addresses given as a comma separated list of "name" or "name:custom_key:custom_value"
+pub fn parse_keysend_addresses(addresses: &str) -> Result<Vec<KeysendAddress>, HelipadError> {
+    let mut parsed: Vec<KeysendAddress> = Vec::new();
+
+    for entry in addresses.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let parts: Vec<&str> = entry.splitn(3, ':').collect();
+
+        let address = match parts.len() {
+            1 => KeysendAddress {
+                name: parts[0].to_lowercase(),
+                custom_key: None,
+                custom_value: None,
+            },
+            3 => KeysendAddress {
+                name: parts[0].to_lowercase(),
+                custom_key: Some(parts[1].parse::<u64>().map_err(|_| HelipadError::BadRequest(format!("Invalid custom key in keysend address entry: {}", entry)))?),
+                custom_value: Some(parts[2].to_string()),
+            },
+            _ => {
+                return Err(HelipadError::BadRequest(format!("Invalid keysend address entry: {}", entry)));
+            }
+        };
+
+        parsed.push(address);
+    }
+
+    Ok(parsed)
+}
+
+//Finds which destination a boost was sent to.  Destinations with a custom key/value must match the
+//payment's custom records exactly, then the podcast guid in the tlv is tried, and finally the first
+//destination with neither is used as the catch-all
+pub fn match_destination(destinations: &[dbif::DestinationRecord], custom_records: &HashMap<u64, Vec<u8>>, tlv: &Value) -> Option<String> {
+    for dest in destinations {
+        if let (Some(key), Some(value)) = (dest.custom_key, &dest.custom_value) {
+            if let Some(record) = custom_records.get(&key) {
+                if record.as_slice() == value.as_bytes() {
+                    return Some(dest.name.clone());
+                }
+            }
+        }
+    }
+
+    if let Some(guid) = tlv["guid"].as_str() {
+        for dest in destinations {
+            if dest.custom_key.is_none() && dest.podcast_guid.as_deref() == Some(guid) {
+                return Some(dest.name.clone());
+            }
+        }
+    }
+
+    destinations.iter()
+        .find(|dest| dest.custom_key.is_none() && dest.podcast_guid.is_none())
+        .map(|dest| dest.name.clone())
+}
+
+//Splits a lightning/keysend address (user@domain) into its user and domain parts
+fn split_lightning_address(address: &str) -> Result<(String, String), HelipadError> {
     if !address.contains('@') {
-        return Err(Box::new(KeysendAddressError("Invalid keysend address".to_string())));
+        return Err(HelipadError::BadRequest(format!("Invalid lightning address: {}", address)));
     }
 
     if !email_address::EmailAddress::is_valid(address) {
-        return Err(Box::new(KeysendAddressError("Invalid keysend address".to_string())));
+        return Err(HelipadError::BadRequest(format!("Invalid lightning address: {}", address)));
     }
 
     let parts: Vec<&str> = address.split('@').collect();
 
     if parts.len() != 2 {
-        return Err(Box::new(KeysendAddressError("Invalid keysend address".to_string())));
+        return Err(HelipadError::BadRequest(format!("Invalid lightning address: {}", address)));
     }
 
-    let url = format!("https://{}/.well-known/keysend/{}", parts[1], parts[0]);
-    let response = reqwest::get(url.clone()).await?.text().await?;
-    let data: KeysendAddressResponse = serde_json::from_str(&response)?;
+    Ok((parts[0].to_string(), parts[1].to_string()))
+}
 
-    print!("Keysend address {}: pub_key={}", address, data.pubkey);
+pub async fn resolve_keysend_address(address: &str) -> Result<KeysendAddressResponse, HelipadError> {
+    let (user, domain) = split_lightning_address(address)?;
 
-    for item in &data.custom_data {
-        print!(" custom_key={}, custom_value={}", item.custom_key, item.custom_value);
-    }
+    let url = format!("https://{}/.well-known/keysend/{}", domain, user);
+    let response = fetch_text(&url).await?;
+    let data: KeysendAddressResponse = serde_json::from_str(&response)
+        .map_err(|e| HelipadError::lightning(&format!("Invalid keysend address response from {}", domain), e))?;
+
+    let custom_data: Vec<String> = data.custom_data.iter()
+        .map(|item| format!(" custom_key={}, custom_value={}", item.custom_key, item.custom_value))
+        .collect();
 
-    println!("");
+    info!("Keysend address {}: pub_key={}{}", address, data.pubkey, custom_data.concat());
 
     return Ok(data);
 }
 
-pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key: Option<u64>, custom_value: Option<String>, sats: u64, tlv: Value) -> Result<Payment, Box<dyn Error>> {
+//Resolves a lightning address into its LNURL-pay parameters (LUD-06/LUD-16)
+pub async fn resolve_lnurlp_address(address: &str) -> Result<LnurlPayResponse, HelipadError> {
+    let (user, domain) = split_lightning_address(address)?;
+
+    let url = format!("https://{}/.well-known/lnurlp/{}", domain, user);
+    let response = fetch_text(&url).await?;
+    let json: Value = serde_json::from_str(&response)
+        .map_err(|e| HelipadError::lightning(&format!("Invalid LNURL-pay response from {}", domain), e))?;
+
+    if json["status"].as_str() == Some("ERROR") {
+        let reason = json["reason"].as_str().unwrap_or_default();
+        return Err(HelipadError::lightning(&format!("LNURL-pay error from {}", domain), reason));
+    }
+
+    let data: LnurlPayResponse = serde_json::from_value(json)
+        .map_err(|e| HelipadError::lightning(&format!("Invalid LNURL-pay response from {}", domain), e))?;
+
+    if data.tag != "payRequest" {
+        return Err(HelipadError::lightning(&format!("Unexpected LNURL response from {}", domain), format!("tag {}", data.tag)));
+    }
+
+    info!("LNURL-pay address {}: callback={} min={} max={} comment_allowed={}", address, data.callback, data.min_sendable, data.max_sendable, data.comment_allowed);
+
+    Ok(data)
+}
+
+//The description hash an LNURL-pay invoice has to commit to: the metadata, followed by the payer data when
+//there is some (LUD-06 and LUD-18)
+pub fn lnurl_description_hash(metadata: &str, payer_data: Option<&str>) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(metadata.as_bytes());
+
+    if let Some(data) = payer_data {
+        hasher.update(data.as_bytes());
+    }
+
+    hasher.finalize().to_vec()
+}
+
+//Callbacks have to be https so the invoice can't be swapped on the way, except on onion services (LUD-06)
+fn check_lnurl_callback(callback: &str) -> Result<(), HelipadError> {
+    let url = url::Url::parse(callback)
+        .map_err(|e| HelipadError::lightning("Invalid LNURL-pay callback url", e))?;
+
+    let onion = url.host_str().map(|host| host.ends_with(".onion")).unwrap_or(false);
+
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if onion => Ok(()),
+        scheme => Err(HelipadError::lightning("LNURL-pay callback isn't https", scheme)),
+    }
+}
+
+//Requests a BOLT11 invoice from an LNURL-pay callback, passing along the boost message as a
+//comment (LUD-12) and the sender name and boostagram as payer data (LUD-18) when supported.
+//Gives back the invoice along with the description hash it has to have
+pub async fn request_lnurl_invoice(lnurlp: &LnurlPayResponse, amount_msat: u64, tlv: &Value) -> Result<(String, Vec<u8>), HelipadError> {
+    check_lnurl_callback(&lnurlp.callback)?;
+
+    if amount_msat < lnurlp.min_sendable || amount_msat > lnurlp.max_sendable {
+        return Err(HelipadError::BadRequest(format!(
+            "Amount must be between {} and {} sats",
+            lnurlp.min_sendable / 1000,
+            lnurlp.max_sendable / 1000
+        )));
+    }
+
+    let mut query = vec![("amount".to_string(), amount_msat.to_string())];
+
+    //Comment, truncated to what the service allows
+    let message = tlv["message"].as_str().unwrap_or_default();
+
+    if lnurlp.comment_allowed > 0 && !message.is_empty() {
+        let comment: String = message.chars().take(lnurlp.comment_allowed as usize).collect();
+        query.push(("comment".to_string(), comment));
+    }
+
+    //Payer data, only including the fields the service asked for
+    let mut payer_data_sent = None;
+
+    if let Some(payer_data) = &lnurlp.payer_data {
+        let mut payer = serde_json::Map::new();
+
+        if payer_data.get("name").is_some() {
+            if let Some(name) = tlv["sender_name"].as_str() {
+                payer.insert("name".to_string(), Value::String(name.to_string()));
+            }
+        }
+
+        if payer_data.get("boostagram").is_some() {
+            payer.insert("boostagram".to_string(), tlv.clone());
+        }
+
+        if !payer.is_empty() {
+            let payer = Value::Object(payer).to_string();
+            query.push(("payerdata".to_string(), payer.clone()));
+            payer_data_sent = Some(payer);
+        }
+    }
+
+    let response = match reqwest::Client::new().get(&lnurlp.callback).query(&query).send().await {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
+    let response = response.map_err(|e| HelipadError::lightning("Error calling LNURL-pay callback", e))?;
+
+    let json: Value = serde_json::from_str(&response)
+        .map_err(|e| HelipadError::lightning("Invalid LNURL-pay callback response", e))?;
+
+    if json["status"].as_str() == Some("ERROR") {
+        let reason = json["reason"].as_str().unwrap_or_default();
+        return Err(HelipadError::lightning("LNURL-pay callback error", reason));
+    }
+
+    let invoice: LnurlInvoiceResponse = serde_json::from_value(json)
+        .map_err(|e| HelipadError::lightning("Invalid LNURL-pay callback response", e))?;
+
+    Ok((invoice.pr, lnurl_description_hash(&lnurlp.metadata, payer_data_sent.as_deref())))
+}
+
+//Looks up a payment we've just sent by its payment hash
+async fn find_payment(lightning: &mut lnd::Lnd, payment_hash: &str) -> Result<Payment, HelipadError> {
+    let payment_list = metrics::observe_lnd("list_payments", lnd::Lnd::list_payments(lightning, false, 0, 500, true)).await
+        .map_err(|e| HelipadError::lightning("Error listing payments", e))?;
+
+    for payment in payment_list.payments {
+        if payment_hash == payment.payment_hash {
+            return Ok(payment);
+        }
+    }
+
+    Err(HelipadError::lightning("Failed to find payment sent", payment_hash))
+}
+
+//Pays a lightning address that only supports LNURL-pay by fetching and paying a BOLT11 invoice
+pub async fn send_lnurl_boost(mut lightning: lnd::Lnd, address: &str, sats: u64, tlv: Value) -> Result<Payment, HelipadError> {
+    let lnurlp = resolve_lnurlp_address(address).await?;
+    let amount_msat = sats * 1000;
+    let (pay_req, description_hash) = request_lnurl_invoice(&lnurlp, amount_msat, &tlv).await?;
+
+    //Make sure the invoice we got back is for the amount we asked for, and for what we were told we're paying
+    let decoded = metrics::observe_lnd("decode_pay_req", lnd::Lnd::decode_pay_req(&mut lightning, pay_req.clone())).await
+        .map_err(|e| HelipadError::lightning("Error decoding LNURL-pay invoice", e))?;
+
+    if decoded.num_msat as u64 != amount_msat {
+        return Err(HelipadError::lightning("LNURL-pay invoice amount mismatch", format!(
+            "expected {} msat, got {} msat",
+            amount_msat,
+            decoded.num_msat
+        )));
+    }
+
+    let expected_hash = HEXLOWER.encode(&description_hash);
+    if !decoded.description_hash.eq_ignore_ascii_case(&expected_hash) {
+        return Err(HelipadError::lightning("LNURL-pay invoice description hash mismatch", format!(
+            "expected {}, got {}",
+            expected_hash,
+            decoded.description_hash
+        )));
+    }
+
+    let req = SendRequest {
+        payment_request: pay_req,
+        ..Default::default()
+    };
+
+    let response = metrics::observe_lnd("send_payment_sync", lnd::Lnd::send_payment_sync(&mut lightning, req)).await
+        .map_err(|e| HelipadError::lightning("Error sending payment", e))?;
+
+    if !response.payment_error.is_empty() {
+        return Err(HelipadError::lightning("Payment failed", response.payment_error));
+    }
+
+    find_payment(&mut lightning, &HEXLOWER.encode(&response.payment_hash)).await
+}
+
+pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key: Option<u64>, custom_value: Option<String>, sats: u64, tlv: Value) -> Result<Payment, HelipadError> {
     // thanks to BrianOfLondon and Mostro for keysend details:
     // https://peakd.com/@brianoflondon/lightning-keysend-is-strange-and-how-to-send-keysend-payment-in-lightning-with-the-lnd-rest-api-via-python
     // https://github.com/MostroP2P/mostro/blob/52a4f86c3942c26bd42dc55f1e53db5da9f7542b/src/lightning/mod.rs#L18
@@ -236,12 +500,25 @@ pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key
 
     // convert keysend address into pub_key/custom keyvalue format
     if destination.contains("@") {
-        let ln_info = resolve_keysend_address(&destination).await?;
+        let ln_info = match resolve_keysend_address(&destination).await {
+            Ok(info) => Some(info),
+            Err(e) => {
+                info!("Keysend lookup failed for {}: {}. Trying LNURL-pay.", destination, e);
+                None
+            }
+        };
+
+        //Not a keysend address, so try it as an LNURL-pay lightning address instead
+        let ln_info = match ln_info {
+            Some(info) => info,
+            None => return send_lnurl_boost(lightning, &destination, sats, tlv).await,
+        };
 
         recipient_pubkey = ln_info.pubkey;
 
         for item in ln_info.custom_data {
-            let ckey_u64 = item.custom_key.parse::<u64>()?;
+            let ckey_u64 = item.custom_key.parse::<u64>()
+                .map_err(|e| HelipadError::lightning(&format!("Invalid custom key from keysend address {}", destination), e))?;
 
             recipient_custom_data.insert(
                 ckey_u64,
@@ -252,13 +529,14 @@ pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key
     else {
         recipient_pubkey = destination;
 
-        if custom_key.is_some() && custom_value.is_some() {
-            recipient_custom_data.insert(custom_key.unwrap(), custom_value.unwrap());
+        if let (Some(key), Some(value)) = (custom_key, custom_value) {
+            recipient_custom_data.insert(key, value);
         }
     }
 
     // convert pub key hash to raw bytes
-    let raw_pubkey = HEXLOWER.decode(recipient_pubkey.as_bytes()).unwrap();
+    let raw_pubkey = HEXLOWER.decode(recipient_pubkey.as_bytes())
+        .map_err(|_| HelipadError::BadRequest(format!("Invalid node pubkey: {}", recipient_pubkey)))?;
 
     // generate 32 random bytes for pre_image
     let mut pre_image = [0u8; 32];
@@ -272,7 +550,8 @@ pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key
     // TLV custom records
     // https://github.com/satoshisstream/satoshis.stream/blob/main/TLV_registry.md
     let mut dest_custom_records = HashMap::new();
-    let tlv_json = serde_json::to_string_pretty(&tlv).unwrap();
+    let tlv_json = serde_json::to_string_pretty(&tlv)
+        .map_err(|e| HelipadError::internal("Error encoding boost tlv", e))?;
 
     dest_custom_records.insert(TLV_KEYSEND, pre_image.to_vec());
     dest_custom_records.insert(TLV_PODCASTING20, tlv_json.as_bytes().to_vec());
@@ -291,30 +570,39 @@ pub async fn send_boost(mut lightning: lnd::Lnd, destination: String, custom_key
     };
 
     // send payment and get payment hash
-    let response = lnd::Lnd::send_payment_sync(&mut lightning, req).await?;
+    let response = metrics::observe_lnd("send_payment_sync", lnd::Lnd::send_payment_sync(&mut lightning, req)).await
+        .map_err(|e| HelipadError::lightning("Error sending payment", e))?;
     let sent_payment_hash = HEXLOWER.encode(&response.payment_hash);
 
     if response.payment_error != "" {
-        return Err(Box::new(BoostError(response.payment_error.into())));
+        return Err(HelipadError::lightning("Payment failed", response.payment_error));
     }
 
     // get detailed payment info from list_payments
-    let payment_list = lnd::Lnd::list_payments(&mut lightning, false, 0, 500, true).await?;
+    find_payment(&mut lightning, &sent_payment_hash).await
+}
 
-    for payment in payment_list.payments {
-        if sent_payment_hash == payment.payment_hash {
-            return Ok(payment);
-        }
-    }
+//Fetches a url from another lightning service as text
+async fn fetch_text(url: &str) -> Result<String, HelipadError> {
+    let response = match reqwest::get(url).await {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
 
-    Err(Box::new(BoostError("Failed to find payment sent".into())))
+    response.map_err(|e| HelipadError::lightning(&format!("Error fetching {}", url), e))
 }
 
 
 
 pub async fn parse_podcast_tlv(boost: &mut dbif::BoostRecord, val: &Vec<u8>, remote_cache: &mut podcastindex::GuidCache) {
-    let tlv = std::str::from_utf8(&val).unwrap();
-    println!("TLV: {:#?}", tlv);
+    let tlv = match std::str::from_utf8(val) {
+        Ok(tlv) => tlv,
+        Err(e) => {
+            warn!("Boost tlv is not valid UTF-8: {}", e);
+            return;
+        }
+    };
+    debug!("TLV: {}", tlv);
 
     boost.tlv = tlv.to_string();
 
@@ -380,12 +668,12 @@ pub async fn parse_podcast_tlv(boost: &mut dbif::BoostRecord, val: &Vec<u8>, rem
             }
         }
         Err(e) => {
-            eprintln!("{}", e);
+            warn!("Boost tlv is not valid JSON: {}", e);
         }
     }
 }
 
-pub async fn parse_boost_from_invoice(invoice: Invoice, remote_cache: &mut podcastindex::GuidCache) -> Option<dbif::BoostRecord> {
+pub async fn parse_boost_from_invoice(invoice: Invoice, remote_cache: &mut podcastindex::GuidCache, destinations: &[dbif::DestinationRecord]) -> Option<dbif::BoostRecord> {
 
     for htlc in invoice.htlcs {
 
@@ -409,11 +697,17 @@ pub async fn parse_boost_from_invoice(invoice: Invoice, remote_cache: &mut podca
             remote_podcast: None,
             remote_episode: None,
             reply_sent: false,
+            source: "keysend".to_string(),
+            destination: None,
             payment_info: None,
         };
 
         parse_podcast_tlv(&mut boost, &htlc.custom_records[&TLV_PODCASTING20], remote_cache).await;
 
+        //Which of our shows was this sent to?
+        let tlv = boost.parse_tlv().unwrap_or_default();
+        boost.destination = match_destination(destinations, &htlc.custom_records, &tlv);
+
         return Some(boost);
     }
 
@@ -424,13 +718,10 @@ pub async fn parse_boost_from_payment(payment: Payment, remote_cache: &mut podca
 
     for htlc in payment.htlcs {
 
-        if htlc.route.is_none() {
-            continue; // no route found
-        }
-
-        let route = htlc.route.unwrap();
-        let hopidx = route.hops.len() - 1;
-        let hop = route.hops[hopidx].clone();
+        let hop = match htlc.route.as_ref().and_then(|route| route.hops.last()) {
+            Some(hop) => hop.clone(),
+            None => continue, // no route found
+        };
 
         if !hop.custom_records.contains_key(&TLV_PODCASTING20) {
             continue; // not a boost payment
@@ -452,6 +743,8 @@ pub async fn parse_boost_from_payment(payment: Payment, remote_cache: &mut podca
             remote_podcast: None,
             remote_episode: None,
             reply_sent: false,
+            source: "keysend".to_string(),
+            destination: None,
             payment_info: Some(dbif::PaymentRecord {
                 payment_hash: payment.payment_hash.clone(),
                 pubkey: hop.pub_key.clone(),
@@ -467,7 +760,7 @@ pub async fn parse_boost_from_payment(payment: Payment, remote_cache: &mut podca
                 parse_podcast_tlv(&mut boost, &val, remote_cache).await;
             }
             else if idx == TLV_WALLET_KEY || idx == TLV_WALLET_ID || idx == TLV_HIVE_ACCOUNT {
-                let custom_value = std::str::from_utf8(&val).unwrap().to_string();
+                let custom_value = String::from_utf8_lossy(&val).to_string();
 
                 boost.payment_info = Some(dbif::PaymentRecord {
                     payment_hash: payment.payment_hash.clone(),
@@ -484,4 +777,196 @@ pub async fn parse_boost_from_payment(payment: Payment, remote_cache: &mut podca
     }
 
     return None;
-}
\ No newline at end of file
+}
+
+//LNURL-pay payments don't carry the podcasting 2.0 tlv, so build the sent boost from the tlv we sent along
+pub async fn parse_boost_from_lnurl_payment(payment: Payment, tlv: &Value, remote_cache: &mut podcastindex::GuidCache) -> Option<dbif::BoostRecord> {
+    let mut pubkey = String::new();
+
+    for htlc in &payment.htlcs {
+        if let Some(route) = &htlc.route {
+            if let Some(hop) = route.hops.last() {
+                pubkey = hop.pub_key.clone();
+            }
+        }
+    }
+
+    //Initialize a boost record
+    let mut boost = dbif::BoostRecord {
+        index: payment.payment_index,
+        time: payment.creation_time_ns / 1000000000,
+        value_msat: payment.value_msat,
+        value_msat_total: payment.value_msat,
+        action: 0,
+        sender: "".to_string(),
+        app: "".to_string(),
+        message: "".to_string(),
+        podcast: "".to_string(),
+        episode: "".to_string(),
+        tlv: "".to_string(),
+        remote_podcast: None,
+        remote_episode: None,
+        reply_sent: false,
+        source: "lnurl".to_string(),
+        destination: None,
+        payment_info: Some(dbif::PaymentRecord {
+            payment_hash: payment.payment_hash.clone(),
+            pubkey,
+            custom_key: 0,
+            custom_value: "".into(),
+            fee_msat: payment.fee_msat,
+            reply_to_idx: None,
+        }),
+    };
+
+    let tlv_json = serde_json::to_string_pretty(tlv).ok()?;
+    parse_podcast_tlv(&mut boost, &tlv_json.into_bytes(), remote_cache).await;
+
+    Some(boost)
+}
+
+//Invoices paid through our lnurl-pay endpoint don't carry the podcasting 2.0 tlv either, so build the
+//boost from the comment and payer data that were given when the invoice was requested
+pub async fn parse_boost_from_lnurl_invoice(invoice: Invoice, lnurl_invoice: &dbif::LnurlInvoiceRecord, remote_cache: &mut podcastindex::GuidCache, destinations: &[dbif::DestinationRecord]) -> Option<dbif::BoostRecord> {
+    if invoice.state != InvoiceState::Settled as i32 {
+        return None;
+    }
+
+    let payer_data: Value = match &lnurl_invoice.payer_data {
+        Some(data) => serde_json::from_str(data).unwrap_or(Value::Null),
+        None => Value::Null,
+    };
+
+    //Use a boostagram sent along in the payer data if there is one, otherwise put together a
+    //boost tlv from the comment and payer info so replies work like they do for keysend boosts
+    let tlv = match payer_data.get("boostagram") {
+        Some(boostagram) if boostagram.is_object() => boostagram.clone(),
+        _ => {
+            let mut tlv = serde_json::json!({
+                "action": "boost",
+                "message": lnurl_invoice.comment,
+                "value_msat": invoice.amt_paid_msat,
+                "value_msat_total": invoice.amt_paid_msat,
+            });
+
+            if let Some(name) = payer_data.get("name").and_then(Value::as_str) {
+                tlv["sender_name"] = Value::String(name.to_string());
+            }
+
+            if let Some(identifier) = payer_data.get("identifier").and_then(Value::as_str) {
+                tlv["reply_address"] = Value::String(identifier.to_string());
+            }
+
+            tlv
+        }
+    };
+
+    //Initialize a boost record
+    let mut boost = dbif::BoostRecord {
+        index: invoice.add_index,
+        time: invoice.settle_date,
+        value_msat: invoice.amt_paid_msat,
+        value_msat_total: invoice.amt_paid_msat,
+        action: 2,
+        sender: "".to_string(),
+        app: "".to_string(),
+        message: lnurl_invoice.comment.clone(),
+        podcast: "".to_string(),
+        episode: "".to_string(),
+        tlv: "".to_string(),
+        remote_podcast: None,
+        remote_episode: None,
+        reply_sent: false,
+        source: "lnurl".to_string(),
+        destination: None,
+        payment_info: None,
+    };
+
+    let tlv_json = serde_json::to_string_pretty(&tlv).ok()?;
+    parse_podcast_tlv(&mut boost, &tlv_json.into_bytes(), remote_cache).await;
+
+    //The amount actually paid wins over whatever the payer claimed
+    boost.value_msat = invoice.amt_paid_msat;
+
+    //Paid to the lightning address of one of our destinations, or otherwise matched by the boostagram?
+    if destinations.iter().any(|dest| dest.name == lnurl_invoice.name) {
+        boost.destination = Some(lnurl_invoice.name.clone());
+    } else {
+        boost.destination = match_destination(destinations, &HashMap::new(), &tlv);
+    }
+
+    Some(boost)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destination(name: &str, custom: Option<(u64, &str)>, podcast_guid: Option<&str>) -> dbif::DestinationRecord {
+        dbif::DestinationRecord {
+            name: name.to_string(),
+            custom_key: custom.map(|(key, _)| key),
+            custom_value: custom.map(|(_, value)| value.to_string()),
+            podcast_guid: podcast_guid.map(str::to_string),
+        }
+    }
+
+    fn test_destinations() -> Vec<dbif::DestinationRecord> {
+        vec![
+            destination("everything", None, None),
+            destination("show", None, Some("show-guid")),
+            destination("alice", Some((696969, "alice")), None),
+            destination("bob", Some((696969, "bob")), Some("show-guid")),
+        ]
+    }
+
+    #[test]
+    fn destinations_match_custom_records_first() {
+        let records = HashMap::from([(696969, b"bob".to_vec())]);
+        let tlv = serde_json::json!({"guid": "show-guid"});
+
+        assert_eq!(match_destination(&test_destinations(), &records, &tlv).as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn destinations_match_podcast_guid_next() {
+        //A custom value that doesn't match, and destinations with a custom key only match on it
+        let records = HashMap::from([(696969, b"carol".to_vec())]);
+        let tlv = serde_json::json!({"guid": "show-guid"});
+
+        assert_eq!(match_destination(&test_destinations(), &records, &tlv).as_deref(), Some("show"));
+    }
+
+    #[test]
+    fn destinations_fall_back_to_the_catch_all() {
+        let tlv = serde_json::json!({"guid": "other-guid"});
+
+        assert_eq!(match_destination(&test_destinations(), &HashMap::new(), &tlv).as_deref(), Some("everything"));
+        assert_eq!(match_destination(&test_destinations()[1..], &HashMap::new(), &tlv), None);
+    }
+
+    #[test]
+    fn lnurl_callbacks_must_be_https_unless_onion() {
+        assert!(check_lnurl_callback("https://example.com/lnurlp/alice/callback").is_ok());
+        assert!(check_lnurl_callback("http://abcdefghijklmnop.onion/lnurlp/alice/callback").is_ok());
+        assert!(check_lnurl_callback("http://example.com/lnurlp/alice/callback").is_err());
+        assert!(check_lnurl_callback("http://example.onion.example.com/callback").is_err());
+        assert!(check_lnurl_callback("ftp://example.com/callback").is_err());
+        assert!(check_lnurl_callback("not a url").is_err());
+    }
+
+    #[test]
+    fn lnurl_description_hash_covers_metadata_and_payer_data() {
+        let metadata = r#"[["text/plain","Boost alice"]]"#;
+
+        assert_eq!(
+            HEXLOWER.encode(&lnurl_description_hash(metadata, None)),
+            "ce767c2406d02f83eec723e6fcd20cc58607d07d52861a8f988d18e79ff340af"
+        );
+        assert_eq!(
+            HEXLOWER.encode(&lnurl_description_hash(metadata, Some(r#"{"name":"bob"}"#))),
+            "d1f4d532c165f5a49925436302d9c2dbb038730b3bda6ad2a6e945225b5f4217"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rand::RngCore;
+use serde_json::json;
+use std::future::Future;
+use std::io::Write;
+use std::sync::RwLock;
+
+//Stands in for anything registered as a secret
+pub const REDACTED: &str = "[redacted]";
+
+//Secrets shorter than this would match all over the place
+const SECRET_MIN_LENGTH: usize = 4;
+
+//Request ids sent by a client or proxy are only kept when they're this short and made of safe characters
+const REQUEST_ID_MAX_LENGTH: usize = 64;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+tokio::task_local! {
+    //The id of the http request being answered, for tagging its log lines
+    static REQUEST_ID: String;
+}
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+//Kept apart from the logger so they can be changed while running
+static LEVELS: RwLock<Levels> = RwLock::new(Levels {
+    default_level: LevelFilter::Info,
+    module_levels: Vec::new(),
+});
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+struct Logger {
+    format: Format,
+    //Commands keep stdout for their own output
+    use_stdout: bool,
+}
+
+struct Levels {
+    default_level: LevelFilter,
+    //Most specific module first, so the first match wins
+    module_levels: Vec<(String, LevelFilter)>,
+}
+
+impl Levels {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.module_levels
+            .iter()
+            .find(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.module_levels
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, std::cmp::max)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match LEVELS.read() {
+            Ok(levels) => metadata.level() <= levels.level_for(metadata.target()),
+            Err(_) => metadata.level() <= Level::Warn,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let time = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
+        let message = redact(&record.args().to_string());
+        let request_id = request_id();
+
+        let line = match self.format {
+            Format::Text => match request_id {
+                Some(id) => format!("{} {:<5} {} [{}] {}", time, record.level(), record.target(), id, message),
+                None => format!("{} {:<5} {} {}", time, record.level(), record.target(), message),
+            },
+            Format::Json => {
+                let mut line = json!({
+                    "time": time.to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                });
+                if let Some(id) = request_id {
+                    line["request_id"] = json!(id);
+                }
+                line.to_string()
+            }
+        };
+
+        //Problems go to stderr like they always have, everything else to stdout
+        if record.level() <= Level::Warn || !self.use_stdout {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        } else {
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Start logging.  The level is a default level followed by any per-module ones, like "info,dbif=warn,helipad::handler=debug".
+//Without use_stdout everything is logged to stderr
+pub fn init(level: &str, format: Format, use_stdout: bool) -> Result<(), String> {
+    let logger = Logger {
+        format,
+        use_stdout,
+    };
+
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| format!("Unable to start logging: {}", e))?;
+
+    set_level(level)
+}
+
+//Change the levels being logged at, in the same form init takes them
+pub fn set_level(level: &str) -> Result<(), String> {
+    let (default_level, mut module_levels) = parse_levels(level)?;
+    module_levels.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+    let levels = Levels {
+        default_level,
+        module_levels,
+    };
+    let max_level = levels.max_level();
+
+    let mut current = LEVELS.write().map_err(|_| "Unable to change the log level".to_string())?;
+    *current = levels;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+//Make sure a level like the ones init takes can be used, without starting logging
+pub fn check_level(level: &str) -> Result<(), String> {
+    parse_levels(level).map(|_| ())
+}
+
+//The default level and any per-module ones
+fn parse_levels(level: &str) -> Result<(LevelFilter, Vec<(String, LevelFilter)>), String> {
+    let mut default_level = LevelFilter::Info;
+    let mut module_levels = Vec::new();
+
+    for directive in level.split(',').map(|directive| directive.trim()).filter(|directive| !directive.is_empty()) {
+        match directive.split_once('=') {
+            Some((module, module_level)) => {
+                module_levels.push((module.trim().to_string(), parse_level(module_level)?));
+            }
+            None => {
+                default_level = parse_level(directive)?;
+            }
+        }
+    }
+
+    Ok((default_level, module_levels))
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.trim().parse::<LevelFilter>()
+        .map_err(|_| format!("[{}] is not a log level.  Use off, error, warn, info, debug or trace.", level.trim()))
+}
+
+pub fn parse_format(format: &str) -> Result<Format, String> {
+    match format.trim().to_lowercase().as_str() {
+        "text" => Ok(Format::Text),
+        "json" => Ok(Format::Json),
+        _ => Err(format!("[{}] is not a log format.  Use text or json.", format.trim())),
+    }
+}
+
+//Keep a value like a password out of the logs, wherever it turns up
+pub fn add_secret(secret: &str) {
+    if secret.len() < SECRET_MIN_LENGTH {
+        return;
+    }
+
+    if let Ok(mut secrets) = SECRETS.write() {
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+fn redact(message: &str) -> String {
+    let mut message = message.to_string();
+
+    if let Ok(secrets) = SECRETS.read() {
+        for secret in secrets.iter() {
+            if message.contains(secret.as_str()) {
+                message = message.replace(secret.as_str(), REDACTED);
+            }
+        }
+    }
+
+    message
+}
+
+//The id of the http request being answered on this task, if any
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+//Use the id a proxy already gave the request so the logs line up, otherwise make a new one
+pub fn new_request_id(given: Option<&str>) -> String {
+    if let Some(id) = given {
+        let valid = !id.is_empty()
+            && id.len() <= REQUEST_ID_MAX_LENGTH
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+        if valid {
+            return id.to_string();
+        }
+    }
+
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    data_encoding::HEXLOWER.encode(&bytes)
+}
+
+//Run a request with its id attached to everything it logs
+pub async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
@@ -7,39 +7,69 @@ use hyper::{
 };
 use route_recognizer::Params;
 use router::Router;
+use std::fmt;
 use std::sync::Arc;
-use hyper::server::conn::AddrStream;
+use std::time::{Duration, Instant};
+use hyper::header::HeaderValue;
+use hyper::server::conn::{AddrStream, Http};
 use std::env;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_rustls::TlsAcceptor;
 use drop_root::set_user_group;
 
 use std::path::Path;
-use rand::{distributions::Alphanumeric, Rng}; // 0.8
+use data_encoding::HEXLOWER;
 
 #[macro_use]
 extern crate configure_me;
+#[macro_use]
+extern crate log;
 
 
 //Globals ----------------------------------------------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------
+mod api_v2;
+mod assets;
+mod auth;
+mod cli;
 mod cookies;
+mod cors;
+mod error;
 mod handler;
+mod health;
+mod metrics;
+mod middleware;
+mod openapi;
 mod router;
+mod settings;
+mod shutdown;
 mod lightning;
+mod logging;
 mod podcastindex;
+mod reindex;
+mod reload;
+mod tls;
 
 type Response = hyper::Response<hyper::Body>;
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-const HELIPAD_CONFIG_FILE: &str = "./helipad.conf";
-const HELIPAD_DATABASE_DIR: &str = "database.db";
-const HELIPAD_STANDARD_PORT: &str = "2112";
 
-const LND_STANDARD_GRPC_URL: &str = "https://127.0.0.1:10009";
-const LND_STANDARD_MACAROON_LOCATION: &str = "/lnd/data/chain/bitcoin/mainnet/admin.macaroon";
-const LND_STANDARD_TLSCERT_LOCATION: &str = "/lnd/tls.cert";
+const LOG_STANDARD_LEVEL: &str = "info";
+//Commands other than serve only log problems, so their own output stands out
+const LOG_COMMAND_LEVEL: &str = "warn";
+
+//Stands in for the client address on unix socket connections, which come from a local proxy
+const UNIX_SOCKET_REMOTE: &str = "unix";
+
 
 const REMOTE_GUID_CACHE_SIZE: usize = 20;
 
+//How long after expiring an lnurl-pay invoice is still checked for payment, in seconds
+const LNURL_INVOICE_GRACE_PERIOD: i64 = 120;
+
 //Structs ----------------------------------------------------------------------------------------------------
 //------------------------------------------------------------------------------------------------------------
 #[derive(Clone, Debug)]
@@ -47,9 +77,13 @@ pub struct AppState {
     pub state_thing: String,
     pub remote_ip: String,
     pub version: String,
+    //Whether the request came through one of the trusted proxies, so its X-Forwarded headers can be believed
+    pub via_proxy: bool,
+    //For work a request starts in the background, so shutting down waits for it
+    pub shutdown: shutdown::Watcher,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct HelipadConfig {
     pub database_file_path: String,
     pub listen_port: String,
@@ -57,7 +91,30 @@ pub struct HelipadConfig {
     pub cert_path: String,
     pub node_address: String,
     pub password: String,
-    pub secret: String,
+    pub tls: bool,
+    pub base_path: String,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub trust_unix_socket: bool,
+    pub health_check_timeout: Duration,
+}
+
+//Written out by hand to keep the password hash and macaroon location out of the logs
+impl fmt::Debug for HelipadConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HelipadConfig")
+            .field("database_file_path", &self.database_file_path)
+            .field("listen_port", &self.listen_port)
+            .field("macaroon_path", &"[redacted]")
+            .field("cert_path", &self.cert_path)
+            .field("node_address", &self.node_address)
+            .field("password", &"[redacted]")
+            .field("tls", &self.tls)
+            .field("base_path", &self.base_path)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("trust_unix_socket", &self.trust_unix_socket)
+            .field("health_check_timeout", &self.health_check_timeout)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
@@ -65,8 +122,11 @@ pub struct Context {
     pub state: AppState,
     pub req: Request<Body>,
     pub path: String,
+    pub route: String,
     pub params: Params,
-    pub helipad_config: HelipadConfig,
+    pub helipad_config: Arc<HelipadConfig>,
+    pub user: Option<auth::AuthUser>,
+    pub started: Instant,
     body_bytes: Option<hyper::body::Bytes>,
 }
 
@@ -77,165 +137,262 @@ include_config!();
 //------------------------------------------------------------------------------------------------------------
 #[tokio::main]
 async fn main() {
+    //Bring in the configuration info.  The command line is also read on its own to tell it apart from the config file
+    let (server_config, remaining_args) = Config::including_optional_config_files(&[settings::CONFIG_FILE]).unwrap_or_exit();
+    let (arg_config, _) = Config::custom_args_and_optional_files(env::args_os(), std::iter::empty::<&str>()).unwrap_or_exit();
+
+    //COMMAND -----
+    //What's left after the options says what to do.  Serving is the default
+    let command = match cli::parse(remaining_args) {
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            std::process::exit(0);
+        }
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}  Run helipad help to see the commands.", e);
+            std::process::exit(2);
+        }
+    };
+
+    //SETTINGS -----
+    //Every setting is worked out up front, so everything wrong with them can be reported at once
+    let port = match &command {
+        cli::Command::Serve { port } => *port,
+        _ => None,
+    };
+    let default_log_level = if command.is_serve() { LOG_STANDARD_LEVEL } else { LOG_COMMAND_LEVEL };
+    let settings = settings::load(&arg_config, &server_config, port, default_log_level);
+
+    if server_config.print_config {
+        //Writing is allowed to fail, for when it's piped into something like head
+        let _ = writeln!(std::io::stdout(), "{}", settings.print());
+        for e in &settings.errors {
+            eprintln!("Configuration error: {}", e);
+        }
+        std::process::exit(if settings.errors.is_empty() { 0 } else { 2 });
+    }
+
+    if !settings.errors.is_empty() {
+        for e in &settings.errors {
+            eprintln!("Configuration error: {}", e);
+        }
+        std::process::exit(2);
+    }
+
+    //LOGGING -----
+    //Started first so everything after can be logged
+    if let Err(e) = logging::init(&settings.log_level, settings.log_format, command.is_serve()) {
+        eprintln!("Logging error: {}", e);
+        std::process::exit(2);
+    }
+
     //Get what version we are
     let version = env!("CARGO_PKG_VERSION");
-    println!("Version: {}", version);
-    println!("--------------------");
+    info!("Version: {}", version);
+
+    //Keep secrets out of the logs
+    logging::add_secret(&settings.password);
+    logging::add_secret(&settings.macaroon);
+    for secret in settings.podcastindex_api_key.iter().chain(settings.podcastindex_api_secret.iter()) {
+        logging::add_secret(secret);
+    }
+
+    info!("Configuration loaded.  Run with --print-config to see every setting and where it came from.");
+    settings.log();
 
     //Configuration
     let mut helipad_config = HelipadConfig {
-        database_file_path: "".to_string(),
-        listen_port: "".to_string(),
-        macaroon_path: "".to_string(),
-        cert_path: "".to_string(),
-        node_address: "".to_string(),
-        password: "".to_string(),
-        secret: "".to_string(),
+        database_file_path: settings.database_dir.clone(),
+        listen_port: settings.listen_port.to_string(),
+        macaroon_path: settings.macaroon.clone(),
+        cert_path: settings.cert.clone(),
+        node_address: settings.lnd_url.clone(),
+        password: settings.password.clone(),
+        tls: false,
+        base_path: settings.base_path.clone(),
+        trusted_proxies: settings.trusted_proxies.clone(),
+        trust_unix_socket: settings.trust_unix_socket,
+        health_check_timeout: settings.health_check_timeout,
     };
 
-    //Bring in the configuration info
-    let (server_config, _remaining_args) = Config::including_optional_config_files(&[HELIPAD_CONFIG_FILE]).unwrap_or_exit();
-
-    //Debugging
-    println!("Config file(database_dir): {:#?}", server_config.database_dir);
-    println!("Config file(listen_port): {:#?}", server_config.listen_port);
-    println!("Config file(macaroon): {:#?}", server_config.macaroon);
-    println!("Config file(cert): {:#?}", server_config.cert);
-
-    //LISTEN PORT -----
-    println!("\nDiscovering listen port...");
-    let mut listen_port = String::from(HELIPAD_STANDARD_PORT);
-    let args: Vec<String> = env::args().collect();
-    let env_listen_port = std::env::var("HELIPAD_LISTEN_PORT");
-    //First try from the environment
-    if env_listen_port.is_ok() {
-        listen_port = env_listen_port.unwrap();
-        println!(" - Using environment var(HELIPAD_LISTEN_PORT): [{}]", listen_port);
-    } else if server_config.listen_port.is_some() {
-        //If that fails, try from the config file
-        listen_port = server_config.listen_port.unwrap().to_string();
-        println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, listen_port);
-    } else if let Some(arg_port) = args.get(1) {
-        //If that fails, try from the command line
-        listen_port = arg_port.to_owned();
-        println!(" - Using arg from command line: [{}]", listen_port);
-    } else {
-        //If everything fails, then just use the default port
-        println!(" - Nothing else found. Using default: [{}]...", listen_port);
-    }
-    helipad_config.listen_port = listen_port.clone();
+    podcastindex::configure(settings.podcastindex_api_key.clone(), settings.podcastindex_api_secret.clone(), settings.podcastindex_timeout);
 
     //DATABASE FILE -----
-    //First try to get the database file location from the environment
-    println!("\nDiscovering database location...");
-    let env_database_file_path = std::env::var("HELIPAD_DATABASE_DIR");
-    if env_database_file_path.is_ok() {
-        helipad_config.database_file_path = env_database_file_path.unwrap();
-        println!(" - Using environment var(HELIPAD_DATABASE_DIR): [{}]", helipad_config.database_file_path);
-    } else {
-        //If that fails, try to get it from the config file
-        if server_config.database_dir.is_some() {
-            helipad_config.database_file_path = server_config.database_dir.clone().unwrap().to_string();
-            println!(" - Using config file({}): [{}]", HELIPAD_CONFIG_FILE, helipad_config.database_file_path);
-        } else {
-            //If that fails just fall back to the local directory
-            helipad_config.database_file_path = HELIPAD_DATABASE_DIR.to_string();
-            println!(" - Nothing else found. Using default: [{}]", helipad_config.database_file_path);
-        }
-    }
     //Create the database file
     match dbif::create_database(&helipad_config.database_file_path) {
         Ok(_) => {
-            println!("Database file is ready...");
+            info!("Database file is ready...");
         }
         Err(e) => {
-            eprintln!("Database error: {:#?}", e);
+            error!("Database error: {:?}", e);
             std::process::exit(3);
         }
     }
 
+    //CHECKPOINTS -----
+    //Sending the poller back over invoices and payments it has already seen is done on its own, then we exit
+    if server_config.reset_checkpoints || server_config.rewind_invoice_index.is_some() || server_config.rewind_payment_index.is_some() {
+        rewind_checkpoints(&helipad_config.database_file_path, server_config.reset_checkpoints, server_config.rewind_invoice_index, server_config.rewind_payment_index);
+        std::process::exit(0);
+    }
+
+    //DATABASE COMMANDS -----
+    //These only need the database, so they're done here and then we exit
+    let database_file_path = &helipad_config.database_file_path;
+    let result = match &command {
+        cli::Command::Migrate => {
+            println!("Database {} is up to date.", database_file_path);
+            Some(Ok(()))
+        }
+        cli::Command::Export { list, format, output, destination } => Some(cli::export(database_file_path, *list, *format, output, destination.as_deref())),
+        cli::Command::Import { list, input } => Some(cli::import(database_file_path, *list, input)),
+        cli::Command::Stats { json, destination } => Some(cli::stats(database_file_path, *json, destination.as_deref())),
+        cli::Command::CreateUser { username, role, destinations } => Some(cli::create_user(database_file_path, username, *role, destinations.clone())),
+        _ => None,
+    };
+    match result {
+        Some(Ok(_)) => std::process::exit(0),
+        Some(Err(e)) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+
     //PASSWORD -----
-    //Get the configured password for Helipad
-    let env_password = std::env::var("HELIPAD_PASSWORD");
-    if env_password.is_ok() {
-        helipad_config.password = env_password.unwrap();
-        println!("Found password in environment var(HELIPAD_PASSWORD)");
-    } else if server_config.password.is_some() {
-        helipad_config.password = server_config.password.unwrap();
-        println!("Found password in config file({})", HELIPAD_CONFIG_FILE);
-    }
-
-    //Generate secret for JWT if password set
-    if !helipad_config.password.is_empty() {
-        helipad_config.secret = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(40)
-            .map(char::from)
-            .collect();
-    }
-
-    //Get the macaroon and cert files.  Look in the local directory first as an override.
-    //If the files are not found in the currect working directory, look for them at their
-    //normal LND directory locations
-    println!("\nDiscovering macaroon file path...");
-    let env_macaroon_path = std::env::var("LND_ADMINMACAROON");
-    //First try from the environment
-    if env_macaroon_path.is_ok() {
-        helipad_config.macaroon_path = env_macaroon_path.unwrap();
-        println!(" - Trying environment var(LND_ADMINMACAROON): [{}]", helipad_config.macaroon_path);
-    } else if server_config.macaroon.is_some() {
-        helipad_config.macaroon_path = server_config.macaroon.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, helipad_config.macaroon_path);
-    } else if Path::new("admin.macaroon").is_file() {
-        helipad_config.macaroon_path = "admin.macaroon".to_string();
-        println!(" - Trying current directory: [{}]", helipad_config.macaroon_path);
-    } else {
-        helipad_config.macaroon_path = String::from(LND_STANDARD_MACAROON_LOCATION);
-        println!(" - Trying LND default: [{}]", helipad_config.macaroon_path);
-    }
-
-    println!("\nDiscovering certificate file path...");
-    let env_cert_path = std::env::var("LND_TLSCERT");
-    if env_cert_path.is_ok() {
-        helipad_config.cert_path = env_cert_path.unwrap();
-        println!(" - Trying environment var(LND_TLSCERT): [{}]", helipad_config.cert_path);
-    } else if server_config.cert.is_some() {
-        helipad_config.cert_path = server_config.cert.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, helipad_config.cert_path);
-    } else if Path::new("tls.cert").is_file() {
-        helipad_config.cert_path = "tls.cert".to_string();
-        println!(" - Trying current directory: [{}]", helipad_config.cert_path);
-    } else {
-        helipad_config.cert_path = String::from(LND_STANDARD_TLSCERT_LOCATION);
-        println!(" - Trying LND default: [{}]", helipad_config.cert_path);
-    }
-
-    //Get the url connection string of the lnd node
-    println!("\nDiscovering LND node address...");
-    let env_lnd_url = std::env::var("LND_URL");
-    if env_lnd_url.is_ok() {
-        helipad_config.node_address = "https://".to_owned() + env_lnd_url.unwrap().as_str();
-        println!(" - Trying environment var(LND_URL): [{}]", helipad_config.node_address);
-    } else if server_config.lnd_url.is_some() {
-        helipad_config.node_address = server_config.lnd_url.unwrap();
-        println!(" - Trying config file({}): [{}]", HELIPAD_CONFIG_FILE, helipad_config.node_address);
-    } else {
-        helipad_config.node_address = String::from(LND_STANDARD_GRPC_URL);
-        println!(" - Trying localhost default: [{}].", helipad_config.node_address);
+    //Only keep a hash of the password around.  An argon2 hash can also be configured instead of the password itself
+    if !helipad_config.password.is_empty() && !auth::is_password_hash(&helipad_config.password) {
+        helipad_config.password = match auth::hash_password(&helipad_config.password) {
+            Ok(hash) => hash,
+            Err(e) => {
+                error!("Error hashing password: {:?}", e);
+                std::process::exit(1);
+            }
+        };
+        logging::add_secret(&helipad_config.password);
+    }
+
+    //Make sure there is a current JWT signing key, rotating it if it's too old
+    if let Err(e) = auth::current_signing_key(&helipad_config.database_file_path) {
+        error!("Error getting JWT signing key: {:?}", e);
+        std::process::exit(1);
+    }
+
+    //Clear out expired login sessions
+    if let Err(e) = dbif::delete_stale_sessions_from_db(&helipad_config.database_file_path, auth::session_cutoff()) {
+        warn!("Error removing expired sessions: {:?}", e);
+    }
+
+    //TLS -----
+    //Serve https when given a certificate and key, optionally making a self-signed pair on first run
+    let tls_acceptor = match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            info!(" - Using certificate: [{}] and key: [{}]", cert_path, key_path);
+            match tls::load_acceptor(cert_path, key_path, settings.tls_self_signed) {
+                Ok(acceptor) => Some(acceptor),
+                Err(e) => {
+                    error!("TLS error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => {
+            info!(" - No certificate configured.  Serving plain http.");
+            None
+        }
+    };
+    helipad_config.tls = tls_acceptor.is_some();
+
+    //CORS -----
+    //Which other sites can call the api from a browser
+    info!(" - Allowed origins: [{}] methods: [{}] credentials: [{}]", settings.cors_allowed_origins, settings.cors_allowed_methods, settings.cors_allow_credentials);
+
+    let cors = match cors::Cors::new(&settings.cors_allowed_origins, &settings.cors_allowed_methods, settings.cors_allow_credentials) {
+        Ok(cors) => cors,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    //KEYSEND ADDRESSES -----
+    //Each configured keysend address is also a destination boosts get routed to
+    for address in settings.keysend_addresses.clone() {
+        info!(" - Keysend address: [{}]", address.name);
+
+        let podcast_guid = match dbif::get_destination_from_db(&helipad_config.database_file_path, &address.name) {
+            Ok(Some(existing)) => existing.podcast_guid,
+            _ => None,
+        };
+
+        let destination = dbif::DestinationRecord {
+            name: address.name,
+            custom_key: address.custom_key,
+            custom_value: address.custom_value,
+            podcast_guid,
+        };
+
+        if let Err(e) = dbif::add_destination_to_db(&helipad_config.database_file_path, &destination) {
+            error!("Error adding destination: {:?}", e);
+        }
     }
 
+    //WEBROOT -----
+    //The built in pages and assets, with any from webroot_dir in their place
+    if let Err(e) = assets::load(settings.webroot_dir.as_deref()) {
+        error!("Webroot error: {}", e);
+        std::process::exit(2);
+    }
+
+    //CHECK CONFIG -----
+    //Anything above that's set wrong has already made us exit, so all that's left is the files
+    if command == cli::Command::CheckConfig {
+        check_config(&helipad_config);
+    }
+
+    //Stop cleanly when docker (or ctrl-c) asks us to
+    let shutdown = shutdown::Shutdown::new();
+    shutdown.listen_for_signals();
+
+    //REINDEX -----
+    //Reparsing LND's whole history is done on its own, then we exit
+    if command == cli::Command::Reindex {
+        if reindex::start().is_err() {
+            std::process::exit(1);
+        }
+        match reindex::run(helipad_config.clone(), shutdown.watcher()).await {
+            Ok(_) => std::process::exit(0),
+            Err(_) => std::process::exit(1),
+        }
+    }
+
+    //Answer requests with whatever the configuration is now, reloading it on SIGHUP or when the config file changes
+    let shared_config = reload::SharedConfig::new(helipad_config.clone());
+    reload::Reloader::new(shared_config.clone(), arg_config, port, default_log_level, settings.clone()).watch();
+
     //Start the LND polling thread.  This thread will poll LND every few seconds to
     //get the latest invoices and store them in the database.
-    tokio::spawn(lnd_poller(helipad_config.clone()));
+    tokio::spawn(lnd_poller(helipad_config.clone(), shutdown.watcher()));
 
     //Router
     let some_state = "state".to_string();
     let mut router: Router = Router::new();
+    router.set_base_path(&helipad_config.base_path);
+
+    //Middleware, outermost first
+    router.wrap(Box::new(middleware::RequestLog));
+    router.wrap(Box::new(middleware::HttpMetrics));
+    router.wrap(Box::new(cors));
+    router.wrap(Box::new(middleware::Compression));
+    router.wrap(Box::new(middleware::ErrorMapping));
+    router.wrap(Box::new(middleware::Authentication));
 
     //Base
     router.get("/", Box::new(handler::home));
     router.get("/login", Box::new(handler::login));
     router.post("/login", Box::new(handler::login));
+    router.post("/logout", Box::new(handler::logout));
     router.get("/streams", Box::new(handler::streams));
     router.get("/sent", Box::new(handler::sent));
     router.get("/pew.mp3", Box::new(handler::pewmp3));
@@ -249,106 +406,438 @@ async fn main() {
     router.get("/script", Box::new(handler::asset));
     router.get("/extra", Box::new(handler::asset));
     //Api
-    router.options("/api/v1/node_info", Box::new(handler::api_v1_node_info_options));
     router.get("/api/v1/node_info", Box::new(handler::api_v1_node_info));
-    router.options("/api/v1/boosts", Box::new(handler::api_v1_boosts_options));
     router.get("/api/v1/boosts", Box::new(handler::api_v1_boosts));
-    router.options("/api/v1/balance", Box::new(handler::api_v1_balance_options));
     router.get("/api/v1/balance", Box::new(handler::api_v1_balance));
-    router.options("/api/v1/streams", Box::new(handler::api_v1_streams_options));
     router.get("/api/v1/streams", Box::new(handler::api_v1_streams));
-    router.options("/api/v1/sent", Box::new(handler::api_v1_sent_options));
     router.get("/api/v1/sent", Box::new(handler::api_v1_sent));
-    router.options("/api/v1/index", Box::new(handler::api_v1_index_options));
     router.get("/api/v1/index", Box::new(handler::api_v1_index));
-    router.options("/api/v1/sent_index", Box::new(handler::api_v1_sent_index_options));
     router.get("/api/v1/sent_index", Box::new(handler::api_v1_sent_index));
-    router.options("/api/v1/reply", Box::new(handler::api_v1_reply_options));
     router.post("/api/v1/reply", Box::new(handler::api_v1_reply));
     router.post("/api/v1/mark_replied", Box::new(handler::api_v1_mark_replied));
+    router.get("/api/v1/destinations", Box::new(handler::api_v1_destinations));
+    router.post("/api/v1/destinations", Box::new(handler::api_v1_destinations));
+    router.post("/api/v1/delete_destination", Box::new(handler::api_v1_delete_destination));
+    router.get("/api/v1/users", Box::new(handler::api_v1_users));
+    router.post("/api/v1/users", Box::new(handler::api_v1_users));
+    router.post("/api/v1/disable_user", Box::new(handler::api_v1_disable_user));
+    router.get("/api/v1/sessions", Box::new(handler::api_v1_sessions));
+    router.post("/api/v1/revoke_session", Box::new(handler::api_v1_revoke_session));
+    router.get("/api/v1/tokens", Box::new(handler::api_v1_tokens));
+    router.post("/api/v1/tokens", Box::new(handler::api_v1_tokens));
+    router.post("/api/v1/revoke_token", Box::new(handler::api_v1_revoke_token));
+    router.get("/api/v1/login_attempts", Box::new(handler::api_v1_login_attempts));
+    router.get("/api/v1/totp", Box::new(handler::api_v1_totp));
+    router.post("/api/v1/totp/enroll", Box::new(handler::api_v1_totp_enroll));
+    router.post("/api/v1/totp/confirm", Box::new(handler::api_v1_totp_confirm));
+    router.post("/api/v1/totp/disable", Box::new(handler::api_v1_totp_disable));
+    router.get("/api/openapi.json", Box::new(handler::openapi_json));
+    router.get("/api/v2/node_info", Box::new(api_v2::node_info));
+    router.get("/api/v2/balance", Box::new(api_v2::balance));
+    router.get("/api/v2/index", Box::new(api_v2::index));
+    router.get("/api/v2/sent_index", Box::new(api_v2::sent_index));
+    router.get("/api/v2/boosts", Box::new(api_v2::boosts));
+    router.get("/api/v2/streams", Box::new(api_v2::streams));
+    router.get("/api/v2/sent", Box::new(api_v2::sent));
+    router.post("/api/v2/reply", Box::new(api_v2::reply));
+    router.post("/api/v2/mark_replied", Box::new(api_v2::mark_replied));
+    router.get("/api/v2/destinations", Box::new(api_v2::destinations));
+    router.post("/api/v2/destinations", Box::new(api_v2::destinations));
+    router.post("/api/v2/delete_destination", Box::new(api_v2::delete_destination));
+    router.get("/api/v2/reindex", Box::new(api_v2::reindex));
+    router.post("/api/v2/reindex", Box::new(api_v2::reindex));
     router.get("/csv", Box::new(handler::csv_export_boosts));
+    router.get("/metrics", Box::new(handler::prometheus_metrics));
+    router.get("/health", Box::new(handler::health));
+    router.get("/ready", Box::new(handler::ready));
+    //LNURL-pay
+    router.get("/.well-known/lnurlp/:name", Box::new(handler::lnurlp_pay_request));
+    router.get("/.well-known/lnurlp/:name/callback", Box::new(handler::lnurlp_callback));
+    //Keysend address
+    router.get("/.well-known/keysend/:name", Box::new(handler::keysend_address));
 
 
     let shared_router = Arc::new(router);
-    let hp_config = helipad_config.clone();
+
+    //Unix socket - for reverse proxies on the same machine
+    if let Some(socket_path) = settings.unix_socket_path() {
+        //Clear out the socket left behind by a previous run
+        if Path::new(socket_path).exists() {
+            if let Err(e) = std::fs::remove_file(socket_path) {
+                error!("Error removing old socket {}: {}", socket_path, e);
+                std::process::exit(1);
+            }
+        }
+
+        let listener = match UnixListener::bind(socket_path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Error binding to {}: {}", socket_path, e);
+                std::process::exit(1);
+            }
+        };
+        info!("Helipad is listening on unix socket {}", socket_path);
+
+        drop_privileges();
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        spawn_connection(stream, UNIX_SOCKET_REMOTE.to_string(), tls_acceptor.clone(), shared_router.clone(), shared_config.clone(), version, shutdown.watcher());
+                    }
+                    Err(e) => {
+                        error!("Error accepting connection: {}", e);
+                    }
+                },
+                _ = shutdown.requested() => break,
+            }
+        }
+
+        let _ = std::fs::remove_file(socket_path);
+        shut_down(shutdown, &helipad_config.database_file_path).await;
+        return;
+    }
+
+    let addr = match settings.socket_address() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    //Https - accept the connections ourselves so they can go through the TLS handshake first
+    if let Some(acceptor) = tls_acceptor {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Error binding to {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        info!("Helipad is listening on https://{}", addr);
+
+        if let Some(redirect_port) = settings.tls_redirect_port {
+            let redirect_addr = SocketAddr::new(addr.ip(), redirect_port);
+            match tls::redirect_to_https(redirect_addr, helipad_config.listen_port.clone()) {
+                Ok(redirect_server) => {
+                    info!("Redirecting http://{} to https", redirect_addr);
+                    tokio::spawn(redirect_server);
+                }
+                Err(e) => {
+                    warn!("Error binding https redirect to {}: {}", redirect_addr, e);
+                }
+            }
+        }
+
+        drop_privileges();
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, remote_addr)) => {
+                        spawn_connection(stream, remote_addr.ip().to_string(), Some(acceptor.clone()), shared_router.clone(), shared_config.clone(), version, shutdown.watcher());
+                    }
+                    Err(e) => {
+                        error!("Error accepting connection: {}", e);
+                    }
+                },
+                _ = shutdown.requested() => break,
+            }
+        }
+
+        shut_down(shutdown, &helipad_config.database_file_path).await;
+        return;
+    }
+
+    let hp_config = shared_config.clone();
+    let hp_watcher = shutdown.watcher();
     let new_service = make_service_fn(move |conn: &AddrStream| {
         let app_state = AppState {
             state_thing: some_state.clone(),
-            remote_ip: conn.remote_addr().to_string().clone(),
+            remote_ip: conn.remote_addr().ip().to_string(),
             version: version.to_string(),
+            via_proxy: false,
+            shutdown: hp_watcher.clone(),
         };
 
-        let helipad_config = hp_config.clone();
+        let shared_config = hp_config.clone();
         let router_capture = shared_router.clone();
         async {
             Ok::<_, Error>(service_fn(move |req| {
-                route(router_capture.clone(), req, app_state.clone(), helipad_config.clone())
+                route(router_capture.clone(), req, app_state.clone(), shared_config.current())
             }))
         }
     });
 
-    let binding = format!("0.0.0.0:{}", &listen_port);
-    let addr = binding.parse().expect("address creation works");
-    let server = Server::bind(&addr).serve(new_service);
-    println!("\nHelipad is listening on http://{}", addr);
+    //Stops taking new connections when asked to, then waits for the requests it's answering
+    let mut stopping = shutdown.watcher();
+    let server = Server::bind(&addr)
+        .serve(new_service)
+        .with_graceful_shutdown(async move { stopping.stopping().await });
+    info!("Helipad is listening on http://{}", addr);
+
+    drop_privileges();
+
+    let serving = shutdown.watcher();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Server error: {}", e);
+        }
+        drop(serving);
+    });
 
-    //If a "run as" user is set in the "HELIPAD_RUN_AS" environment variable, then switch to that user
-    //and drop root privileges after we've bound to the low range socket
+    shut_down(shutdown, &helipad_config.database_file_path).await;
+}
+
+//Wait until we're asked to stop, give the requests being answered and the poller's current batch time
+//to finish, then close the database
+async fn shut_down(shutdown: shutdown::Shutdown, database_file_path: &String) {
+    if shutdown.finish(shutdown::SHUTDOWN_TIMEOUT).await {
+        info!("Requests and the poller have finished.");
+    } else {
+        warn!("Gave up waiting on requests and the poller after {} seconds.", shutdown::SHUTDOWN_TIMEOUT.as_secs());
+    }
+
+    match dbif::close_database(database_file_path) {
+        Ok(_) => info!("Database closed.  Bye."),
+        Err(e) => error!("Error closing database: {}", e),
+    }
+}
+
+//Make sure what the configuration points at can be used, then exit
+fn check_config(helipad_config: &HelipadConfig) {
+    let mut problems = Vec::new();
+
+    if let Err(e) = dbif::check_database_writable(&helipad_config.database_file_path) {
+        problems.push(format!("Database error: {} can't be written to: {}", helipad_config.database_file_path, e));
+    }
+
+    //The macaroon location is kept out of the logs
+    if let Err(e) = std::fs::File::open(&helipad_config.macaroon_path) {
+        problems.push(format!("LND error: the macaroon file can't be read: {}", e));
+    }
+
+    if let Err(e) = std::fs::File::open(&helipad_config.cert_path) {
+        problems.push(format!("LND error: the certificate file {} can't be read: {}", helipad_config.cert_path, e));
+    }
+
+    if problems.is_empty() {
+        println!("Configuration is OK.");
+        std::process::exit(0);
+    }
+
+    for problem in problems {
+        error!("{}", problem);
+    }
+    std::process::exit(2);
+}
+
+//Move the poller's checkpoints back.  Boosts it has already stored are left as they are when it goes over them again
+fn rewind_checkpoints(database_file_path: &String, reset: bool, invoice_index: Option<u64>, payment_index: Option<u64>) {
+    let mut checkpoints = match dbif::get_checkpoints_from_db(database_file_path) {
+        Ok(checkpoints) => checkpoints,
+        Err(e) => {
+            error!("Error getting poller checkpoints: {:?}", e);
+            std::process::exit(3);
+        }
+    };
+    info!("Poller checkpoints were: {:?}", checkpoints);
+
+    if reset {
+        checkpoints = dbif::Checkpoints::default();
+    }
+
+    if let Some(index) = invoice_index {
+        if index > checkpoints.invoice_add_index {
+            error!("Checkpoint error: invoice index [{}] is past where the poller is [{}].", index, checkpoints.invoice_add_index);
+            std::process::exit(2);
+        }
+        checkpoints.invoice_add_index = index;
+    }
+
+    if let Some(index) = payment_index {
+        if index > checkpoints.payment_index {
+            error!("Checkpoint error: payment index [{}] is past where the poller is [{}].", index, checkpoints.payment_index);
+            std::process::exit(2);
+        }
+        checkpoints.payment_index = index;
+    }
+
+    match dbif::set_checkpoints_in_db(database_file_path, &checkpoints) {
+        Ok(_) => info!("Poller checkpoints are now: {:?}", checkpoints),
+        Err(e) => {
+            error!("Error setting poller checkpoints: {:?}", e);
+            std::process::exit(3);
+        }
+    }
+}
+
+//If a "run as" user is set in the "HELIPAD_RUN_AS" environment variable, then switch to that user
+//and drop root privileges after we've bound to the low range socket
+fn drop_privileges() {
     match env::var("HELIPAD_RUNAS_USER") {
         Ok(runas_user) => {
             match set_user_group(runas_user.as_str(), "nogroup") {
                 Ok(_) => {
-                    println!("RunAs: {}", runas_user.as_str());
+                    info!("RunAs: {}", runas_user.as_str());
                 }
                 Err(e) => {
-                    eprintln!("RunAs Error: {} - Check that your HELIPAD_RUNAS_USER env var is set correctly.", e);
+                    error!("RunAs Error: {} - Check that your HELIPAD_RUNAS_USER env var is set correctly.", e);
                 }
             }
         }
         Err(_) => {
-            eprintln!("ALERT: Use the HELIPAD_RUNAS_USER env var to avoid running as root.");
+            warn!("ALERT: Use the HELIPAD_RUNAS_USER env var to avoid running as root.");
         }
     }
+}
+
+//Connections we accept ourselves, with or without TLS
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+//Serve a connection we accepted ourselves on its own task, going through the TLS handshake first for https.
+//When shutting down, the request being answered is finished before the connection is closed
+fn spawn_connection<S>(stream: S, remote_ip: String, acceptor: Option<TlsAcceptor>, router: Arc<Router>, shared_config: reload::SharedConfig, version: &str, mut watcher: shutdown::Watcher)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app_state = AppState {
+        state_thing: "state".to_string(),
+        remote_ip: remote_ip.clone(),
+        version: version.to_string(),
+        via_proxy: false,
+        shutdown: watcher.clone(),
+    };
 
-    let _ = server.await;
+    let service = service_fn(move |req| {
+        route(router.clone(), req, app_state.clone(), shared_config.current())
+    });
+
+    tokio::spawn(async move {
+        let stream: Box<dyn Connection> = match acceptor {
+            Some(acceptor) => match acceptor.accept(stream).await {
+                Ok(tls_stream) => Box::new(tls_stream),
+                Err(e) => {
+                    warn!("TLS handshake with {} failed: {}", remote_ip, e);
+                    return;
+                }
+            },
+            None => Box::new(stream),
+        };
+
+        let connection = Http::new().serve_connection(stream, service);
+        tokio::pin!(connection);
+
+        let result = tokio::select! {
+            result = connection.as_mut() => result,
+            _ = watcher.stopping() => {
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        };
+
+        if let Err(e) = result {
+            warn!("Error serving connection from {}: {}", remote_ip, e);
+        }
+    });
+}
+
+//The address of the client, taken from X-Forwarded-For when the request came through a trusted proxy.
+//The header is read from the right, skipping over any proxies, since the left end is whatever the client claims
+//Unix socket peers are only trusted when "unix" is in trusted_proxies
+fn is_trusted_proxy(remote_ip: &str, helipad_config: &HelipadConfig) -> bool {
+    if remote_ip == UNIX_SOCKET_REMOTE {
+        return helipad_config.trust_unix_socket;
+    }
+
+    remote_ip.parse::<IpAddr>()
+        .map(|ip| helipad_config.trusted_proxies.contains(&ip))
+        .unwrap_or(false)
+}
+
+fn client_ip(req: &Request<Body>, remote_ip: &str, trusted_proxies: &[IpAddr]) -> String {
+
+    let forwarded: Vec<&str> = req.headers()
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(','))
+        .map(|val| val.trim())
+        .collect();
+
+    let mut client = remote_ip.to_string();
+    for hop in forwarded.iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip.to_string();
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+
+    client
 }
 
 async fn route(
     router: Arc<Router>,
     req: Request<hyper::Body>,
-    app_state: AppState,
-    helipad_config: HelipadConfig,
+    mut app_state: AppState,
+    helipad_config: Arc<HelipadConfig>,
 ) -> Result<Response, Error> {
-    let found_handler = router.route(req.uri().path(), req.method());
-    let path = req.uri().path().to_owned();
-    let ctx = Context::new(app_state, req, &path, found_handler.params, helipad_config.clone());
-
-    if let Some(resp) = handler::login_required(&ctx) {
-        return Ok(resp);
+    app_state.via_proxy = is_trusted_proxy(&app_state.remote_ip, &helipad_config);
+    if app_state.via_proxy {
+        app_state.remote_ip = client_ip(&req, &app_state.remote_ip, &helipad_config.trusted_proxies);
     }
 
-    let authed = handler::verify_jwt_cookie(&ctx.req, &helipad_config.secret);
-
-    let mut resp = found_handler
-        .handler
-        .invoke(ctx)
-        .await;
+    //Tag everything logged while answering with the request's id, and give it back to the client
+    let request_id = logging::new_request_id(req.headers().get(logging::REQUEST_ID_HEADER).and_then(|val| val.to_str().ok()));
+    let mut resp = logging::with_request_id(request_id.clone(), router.handle(req, app_state, helipad_config)).await;
 
-    if authed {
-        handler::set_jwt_cookie(&mut resp, &helipad_config.secret);
+    if let Ok(val) = HeaderValue::from_str(&request_id) {
+        resp.headers_mut().insert(logging::REQUEST_ID_HEADER, val);
     }
 
     Ok(resp)
 }
 
 impl Context {
-    pub fn new(state: AppState, reqbody: Request<Body>, path: &str, params: Params, helipad_config: HelipadConfig) -> Context {
+    pub fn new(state: AppState, reqbody: Request<Body>, path: &str, params: Params, helipad_config: Arc<HelipadConfig>) -> Context {
         Context {
             state: state,
             req: reqbody,
             path: path.to_string(),
+            route: "".to_string(),
             params: params,
             helipad_config: helipad_config,
+            user: None,
+            started: Instant::now(),
+            body_bytes: None,
+        }
+    }
+
+    //A copy of everything but the request body
+    pub fn without_body(&self) -> Context {
+        let mut req = Request::new(Body::empty());
+        *req.method_mut() = self.req.method().clone();
+        *req.uri_mut() = self.req.uri().clone();
+        *req.version_mut() = self.req.version();
+        *req.headers_mut() = self.req.headers().clone();
+
+        Context {
+            state: self.state.clone(),
+            req,
+            path: self.path.clone(),
+            route: self.route.clone(),
+            params: self.params.clone(),
+            helipad_config: self.helipad_config.clone(),
+            user: self.user.clone(),
+            started: self.started,
             body_bytes: None,
         }
     }
@@ -366,16 +855,17 @@ impl Context {
     }
 }
 
-//The LND poller runs in a thread and pulls new invoices
-async fn lnd_poller(helipad_config: HelipadConfig) {
+//The LND poller runs in a thread and pulls new invoices.  How far it got is saved along with each batch, and
+//when shutting down it finishes the batch it's on
+async fn lnd_poller(helipad_config: HelipadConfig, mut shutdown: shutdown::Watcher) {
     let db_filepath = helipad_config.database_file_path.clone();
 
     //Make the connection to LND
-    println!("\nConnecting to LND node address...");
+    info!("Connecting to LND node address...");
     let mut lightning;
     match lightning::connect_to_lnd(helipad_config.node_address, helipad_config.cert_path, helipad_config.macaroon_path).await {
         Some(lndconn) => {
-            println!(" - Success.");
+            info!(" - Success.");
             lightning = lndconn;
         }
         None => {
@@ -384,9 +874,10 @@ async fn lnd_poller(helipad_config: HelipadConfig) {
     }
 
     //Get lnd node info
-    match lnd::Lnd::get_info(&mut lightning).await {
+    match metrics::observe_lnd("get_info", lnd::Lnd::get_info(&mut lightning)).await {
         Ok(node_info) => {
-            println!("LND node info: {:#?}", node_info);
+            info!("LND node: {} ({}) version {}", node_info.alias, node_info.identity_pubkey, node_info.version);
+            health::lnd_answered(Some(node_info.synced_to_chain));
 
             let record = dbif::NodeInfoRecord {
                 lnd_alias: node_info.alias,
@@ -395,102 +886,206 @@ async fn lnd_poller(helipad_config: HelipadConfig) {
             };
 
             if dbif::add_node_info_to_db(&db_filepath, record).is_err() {
-                println!("Error updating node info in database.");
+                error!("Error updating node info in database.");
             }
         }
         Err(e) => {
-            eprintln!("Error getting LND node info: {:#?}", e);
+            error!("Error getting LND node info: {:?}", e);
         }
     }
 
     //Instantiate a cache to use when resolving remote podcasts/episode guids
     let mut remote_cache = podcastindex::GuidCache::new(REMOTE_GUID_CACHE_SIZE);
 
-    //The main loop
-    let mut current_index = dbif::get_last_boost_index_from_db(&db_filepath).unwrap();
-    let mut current_payment = dbif::get_last_payment_index_from_db(&db_filepath).unwrap();
+    //The main loop picks up from where it last saved its place
+    let mut checkpoints = match dbif::get_checkpoints_from_db(&db_filepath) {
+        Ok(checkpoints) => checkpoints,
+        Err(e) => {
+            error!("Error getting poller checkpoints: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Poller starting from invoice index {} and payment index {}.", checkpoints.invoice_add_index, checkpoints.payment_index);
 
-    loop {
+    while !shutdown.is_stopping() {
         let mut updated = false;
 
+        //Keep asking whether LND has caught up with the chain until it has, for the readiness check
+        if !health::lnd_synced() {
+            match metrics::observe_lnd("get_info", lnd::Lnd::get_info(&mut lightning)).await {
+                Ok(node_info) => health::lnd_answered(Some(node_info.synced_to_chain)),
+                Err(e) => error!("Error getting LND node info: {:?}", e),
+            }
+        }
+
         //Get lnd node channel balance
-        match lnd::Lnd::channel_balance(&mut lightning).await {
+        match metrics::observe_lnd("channel_balance", lnd::Lnd::channel_balance(&mut lightning)).await {
             Ok(balance) => {
                 let mut current_balance: i64 = 0;
                 if let Some(bal) = balance.local_balance {
-                    println!("LND node local balance: {:#?}", bal.sat);
+                    debug!("LND node local balance: {}", bal.sat);
                     current_balance = bal.sat as i64;
                 }
 
+                metrics::set_channel_balance(current_balance);
+
                 if dbif::add_wallet_balance_to_db(&db_filepath, current_balance).is_err() {
-                    println!("Error adding wallet balance to the database.");
+                    error!("Error adding wallet balance to the database.");
                 }
             }
             Err(e) => {
-                eprintln!("Error getting LND wallet balance: {:#?}", e);
+                error!("Error getting LND wallet balance: {:?}", e);
             }
         }
 
+        //Get the destinations incoming boosts get routed to
+        let destinations = match dbif::get_destinations_from_db(&db_filepath) {
+            Ok(destinations) => destinations,
+            Err(e) => {
+                error!("Error getting destinations: {:?}", e);
+                Vec::new()
+            }
+        };
+
         //Get a list of invoices
-        match lnd::Lnd::list_invoices(&mut lightning, false, current_index.clone(), 500, false).await {
-            Ok(response) => {
+        match metrics::observe_lnd("list_invoices", lnd::Lnd::list_invoices(&mut lightning, false, checkpoints.invoice_add_index, 500, false)).await {
+            Ok(response) if !response.invoices.is_empty() => {
+                health::lnd_answered(None);
+
+                let mut boosts = Vec::new();
+                let mut add_index = checkpoints.invoice_add_index;
+                let mut settle_index = checkpoints.invoice_settle_index;
+
                 for invoice in response.invoices {
-                    let parsed = lightning::parse_boost_from_invoice(invoice.clone(), &mut remote_cache).await;
+                    let parsed = lightning::parse_boost_from_invoice(invoice.clone(), &mut remote_cache, &destinations).await;
 
                     if let Some(boost) = parsed {
-                        //Give some output
-                        println!("Boost: {:#?}", boost);
-
-                        //Store in the database
-                        match dbif::add_invoice_to_db(&db_filepath, boost) {
-                            Ok(_) => println!("New invoice added."),
-                            Err(e) => eprintln!("Error adding invoice: {:#?}", e)
-                        }
+                        //Give some output.  The whole boost has the sender's message in it, so only when debugging
+                        info!("Received {} {}: {} sats via {}", boost.action_name(), boost.index, boost.value_msat / 1000, boost.app);
+                        debug!("Boost: {:?}", boost);
+                        boosts.push(boost);
                     }
 
-                    current_index = invoice.add_index;
-                    updated = true;
+                    add_index = invoice.add_index;
+                    settle_index = settle_index.max(invoice.settle_index);
+                }
+
+                //Store in the database along with how far we got, so a batch is never half stored
+                match dbif::add_invoices_to_db(&db_filepath, &boosts, add_index, settle_index) {
+                    Ok(_) => {
+                        debug!("{} new invoices added.", boosts.len());
+                        boosts.iter().for_each(metrics::record_received);
+                        checkpoints.invoice_add_index = add_index;
+                        checkpoints.invoice_settle_index = settle_index;
+                        updated = true;
+                    }
+                    Err(e) => error!("Error adding invoices: {:?}", e)
                 }
             }
+            Ok(_) => health::lnd_answered(None),
             Err(e) => {
-                eprintln!("lnd::Lnd::list_invoices failed: {}", e);
+                error!("lnd::Lnd::list_invoices failed: {}", e);
             }
         }
 
         //Make sure we are tracking our position properly
-        println!("Current index: {}", current_index);
+        debug!("Current index: {}", checkpoints.invoice_add_index);
+
+        //Check whether any invoices handed out by the lnurl-pay endpoint have been paid.  These
+        //don't carry a tlv, and may be settled long after the poller has moved past their index
+        let expires_after = chrono::Utc::now().timestamp() - LNURL_INVOICE_GRACE_PERIOD;
+
+        let pending = match dbif::get_pending_lnurl_invoices_from_db(&db_filepath, expires_after) {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!("Error getting pending lnurl invoices: {:?}", e);
+                Vec::new()
+            }
+        };
+
+        for lnurl_invoice in pending {
+            let r_hash = match HEXLOWER.decode(lnurl_invoice.payment_hash.as_bytes()) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    error!("Invalid lnurl invoice payment hash: {:?}", e);
+                    continue;
+                }
+            };
+
+            let invoice = match metrics::observe_lnd("lookup_invoice", lnd::Lnd::lookup_invoice(&mut lightning, r_hash)).await {
+                Ok(invoice) => invoice,
+                Err(e) => {
+                    error!("lnd::Lnd::lookup_invoice failed: {}", e);
+                    continue;
+                }
+            };
+
+            let parsed = lightning::parse_boost_from_lnurl_invoice(invoice, &lnurl_invoice, &mut remote_cache, &destinations).await;
+
+            if let Some(boost) = parsed {
+                //Give some output
+                info!("Received lnurl {} {}: {} sats via {}", boost.action_name(), boost.index, boost.value_msat / 1000, boost.app);
+                debug!("LNURL Boost: {:?}", boost);
+
+                //Store in the database.  If that fails the invoice stays pending and is tried again next time
+                match dbif::add_lnurl_boost_to_db(&db_filepath, &boost, &lnurl_invoice.payment_hash) {
+                    Ok(_) => {
+                        debug!("New lnurl invoice added.");
+                        metrics::record_received(&boost);
+                    }
+                    Err(e) => error!("Error adding lnurl invoice: {:?}", e)
+                }
+            }
+        }
+
+        match metrics::observe_lnd("list_payments", lnd::Lnd::list_payments(&mut lightning, false, checkpoints.payment_index, 500, false)).await {
+            Ok(response) if !response.payments.is_empty() => {
+                let mut boosts = Vec::new();
+                let mut payment_index = checkpoints.payment_index;
 
-        match lnd::Lnd::list_payments(&mut lightning, false, current_payment, 500, false).await {
-            Ok(response) => {
                 for payment in response.payments {
                     let parsed = lightning::parse_boost_from_payment(payment.clone(), &mut remote_cache).await;
 
                     if let Some(boost) = parsed {
                         //Give some output
-                        println!("Sent Boost: {:#?}", boost);
-
-                        //Store in the database
-                        match dbif::add_payment_to_db(&db_filepath, &boost) {
-                            Ok(_) => println!("New payment added."),
-                            Err(e) => eprintln!("Error adding payment: {:#?}", e)
-                        }
+                        info!("Sent {} {}: {} sats via {}", boost.action_name(), boost.index, boost.value_msat / 1000, boost.app);
+                        debug!("Sent Boost: {:?}", boost);
+                        boosts.push(boost);
                     }
 
-                    current_payment = payment.payment_index;
-                    updated = true;
+                    payment_index = payment.payment_index;
+                }
+
+                //Store in the database along with how far we got
+                match dbif::add_payments_to_db(&db_filepath, &boosts, payment_index) {
+                    Ok(_) => {
+                        debug!("{} new payments added.", boosts.len());
+                        boosts.iter().for_each(metrics::record_sent);
+                        checkpoints.payment_index = payment_index;
+                        updated = true;
+                    }
+                    Err(e) => error!("Error adding payments: {:?}", e)
                 }
             }
+            Ok(_) => {}
             Err(e) => {
-                eprintln!("lnd::Lnd::list_payments failed: {}", e);
+                error!("lnd::Lnd::list_payments failed: {}", e);
             }
         };
 
         //Make sure we are tracking our position properly
-        println!("Current payment: {}", current_payment);
+        debug!("Current payment: {}", checkpoints.payment_index);
+
+        health::poll_completed();
 
         //Sleep only if nothing was updated
         if !updated {
-            tokio::time::sleep(tokio::time::Duration::from_millis(9000)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_millis(9000)) => {},
+                _ = shutdown.stopping() => {},
+            }
         }
     }
+
+    info!("Poller stopped at invoice index {} and payment index {}.", checkpoints.invoice_add_index, checkpoints.payment_index);
 }
\ No newline at end of file
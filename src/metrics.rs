@@ -0,0 +1,199 @@
+use hyper::{Method, StatusCode};
+use prometheus::{Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+//App names come from the sender's tlv, so only the apps the UI knows get their own label and the rest share this one
+const OTHER_APP_LABEL: &str = "other";
+
+//The apps the UI has icons for, keyed by lowercased name
+const KNOWN_APPS_JSON: &str = include_str!("../webroot/extra/apps.json");
+
+static KNOWN_APPS: OnceLock<Vec<String>> = OnceLock::new();
+
+//Stands in for the route of requests that didn't match one, so unknown paths don't each get their own series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+struct Metrics {
+    registry: Registry,
+    boosts_received: IntCounterVec,
+    boosts_sent: IntCounterVec,
+    sats_received: IntCounter,
+    sats_sent: IntCounter,
+    channel_balance: IntGauge,
+    lnd_errors: IntCounterVec,
+    lnd_duration: HistogramVec,
+    poll_last_completed: IntGauge,
+    poll_lag: Gauge,
+    podcastindex_lookups: IntCounterVec,
+    podcastindex_cache: IntCounterVec,
+    podcastindex_cache_hit_ratio: Gauge,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Metrics, prometheus::Error> {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            boosts_received: IntCounterVec::new(
+                Opts::new("helipad_boosts_received_total", "Boosts and streams received"),
+                &["action", "app"],
+            )?,
+            boosts_sent: IntCounterVec::new(
+                Opts::new("helipad_boosts_sent_total", "Boosts and streams sent"),
+                &["action", "app"],
+            )?,
+            sats_received: IntCounter::new("helipad_sats_received_total", "Sats received in boosts and streams")?,
+            sats_sent: IntCounter::new("helipad_sats_sent_total", "Sats sent in boosts and streams, without fees")?,
+            channel_balance: IntGauge::new("helipad_channel_balance_sats", "Local channel balance last seen by the poller")?,
+            lnd_errors: IntCounterVec::new(
+                Opts::new("helipad_lnd_rpc_errors_total", "LND calls that failed"),
+                &["method"],
+            )?,
+            lnd_duration: HistogramVec::new(
+                HistogramOpts::new("helipad_lnd_rpc_duration_seconds", "How long LND calls took"),
+                &["method"],
+            )?,
+            poll_last_completed: IntGauge::new("helipad_poll_last_completed_timestamp_seconds", "When the poller last finished checking LND")?,
+            poll_lag: Gauge::new("helipad_poll_lag_seconds", "Seconds since the poller last finished checking LND")?,
+            podcastindex_lookups: IntCounterVec::new(
+                Opts::new("helipad_podcastindex_lookups_total", "Podcast Index api lookups of remote podcasts/episodes"),
+                &["result"],
+            )?,
+            podcastindex_cache: IntCounterVec::new(
+                Opts::new("helipad_podcastindex_cache_requests_total", "Remote podcast/episode cache hits and misses"),
+                &["result"],
+            )?,
+            podcastindex_cache_hit_ratio: Gauge::new("helipad_podcastindex_cache_hit_ratio", "Share of remote podcast/episode lookups answered from the cache")?,
+            http_requests: IntCounterVec::new(
+                Opts::new("helipad_http_requests_total", "HTTP requests answered"),
+                &["method", "route", "status"],
+            )?,
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("helipad_http_request_duration_seconds", "How long HTTP requests took to answer"),
+                &["method", "route"],
+            )?,
+        };
+
+        metrics.registry.register(Box::new(metrics.boosts_received.clone()))?;
+        metrics.registry.register(Box::new(metrics.boosts_sent.clone()))?;
+        metrics.registry.register(Box::new(metrics.sats_received.clone()))?;
+        metrics.registry.register(Box::new(metrics.sats_sent.clone()))?;
+        metrics.registry.register(Box::new(metrics.channel_balance.clone()))?;
+        metrics.registry.register(Box::new(metrics.lnd_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.lnd_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.poll_last_completed.clone()))?;
+        metrics.registry.register(Box::new(metrics.poll_lag.clone()))?;
+        metrics.registry.register(Box::new(metrics.podcastindex_lookups.clone()))?;
+        metrics.registry.register(Box::new(metrics.podcastindex_cache.clone()))?;
+        metrics.registry.register(Box::new(metrics.podcastindex_cache_hit_ratio.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_duration.clone()))?;
+
+        Ok(metrics)
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions are valid"))
+}
+
+fn app_label(app: &str) -> &'static str {
+    let known_apps = KNOWN_APPS.get_or_init(|| {
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(KNOWN_APPS_JSON)
+            .map(|apps| apps.keys().cloned().collect())
+            .unwrap_or_default()
+    });
+
+    let app = app.trim().to_lowercase();
+
+    match known_apps.iter().find(|known| **known == app) {
+        Some(known) => known,
+        None => OTHER_APP_LABEL,
+    }
+}
+
+//Count a boost or stream the poller stored
+pub fn record_received(boost: &dbif::BoostRecord) {
+    let m = metrics();
+    m.boosts_received.with_label_values(&[boost.action_name(), app_label(&boost.app)]).inc();
+    m.sats_received.inc_by(boost.value_msat.max(0) as u64 / 1000);
+}
+
+pub fn record_sent(boost: &dbif::BoostRecord) {
+    let m = metrics();
+    m.boosts_sent.with_label_values(&[boost.action_name(), app_label(&boost.app)]).inc();
+    m.sats_sent.inc_by(boost.value_msat.max(0) as u64 / 1000);
+}
+
+pub fn set_channel_balance(sats: i64) {
+    metrics().channel_balance.set(sats);
+}
+
+//Time a call to LND, counting it as an error if it fails
+pub async fn observe_lnd<T, E, F>(method: &str, call: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let result = call.await;
+
+    let m = metrics();
+    m.lnd_duration.with_label_values(&[method]).observe(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        m.lnd_errors.with_label_values(&[method]).inc();
+    }
+
+    result
+}
+
+pub fn poll_completed() {
+    metrics().poll_last_completed.set(chrono::Utc::now().timestamp());
+}
+
+pub fn record_podcastindex_cache(hit: bool) {
+    metrics().podcastindex_cache.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+}
+
+pub fn record_podcastindex_lookup(success: bool) {
+    metrics().podcastindex_lookups.with_label_values(&[if success { "success" } else { "error" }]).inc();
+}
+
+pub fn record_http(method: &Method, route: &str, status: StatusCode, elapsed: Duration) {
+    let route = if route.is_empty() { UNMATCHED_ROUTE } else { route };
+
+    let m = metrics();
+    m.http_requests.with_label_values(&[method.as_str(), route, status.as_str()]).inc();
+    m.http_duration.with_label_values(&[method.as_str(), route]).observe(elapsed.as_secs_f64());
+}
+
+//Everything in the Prometheus text format, along with its content type
+pub fn render() -> Result<(String, Vec<u8>), prometheus::Error> {
+    let m = metrics();
+
+    //Work out the values that depend on when they're looked at
+    let last_poll = m.poll_last_completed.get();
+    if last_poll > 0 {
+        m.poll_lag.set((chrono::Utc::now().timestamp() - last_poll) as f64);
+    }
+
+    let hits = m.podcastindex_cache.with_label_values(&["hit"]).get();
+    let misses = m.podcastindex_cache.with_label_values(&["miss"]).get();
+    if hits + misses > 0 {
+        m.podcastindex_cache_hit_ratio.set(hits as f64 / (hits + misses) as f64);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&m.registry.gather(), &mut buffer)?;
+
+    Ok((encoder.format_type().to_string(), buffer))
+}
@@ -0,0 +1,255 @@
+use crate::error;
+use crate::handler;
+use crate::metrics;
+use crate::router::Middleware;
+use crate::{Context, Response};
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method};
+use serde_json::json;
+use std::io::Write;
+
+//Responses smaller than this aren't worth compressing, in bytes
+pub const COMPRESSION_MIN_SIZE: usize = 1024;
+
+//Content types that compress well, matched by prefix
+const COMPRESSIBLE_TYPES: [&str; 4] = ["text/", "application/json", "application/javascript", "image/svg+xml"];
+
+//Brotli's middle quality compresses about as fast as gzip, but smaller
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+//Checks the login on every request and keeps it fresh afterwards
+pub struct Authentication;
+
+//Logs a line for every request once it's been answered
+pub struct RequestLog;
+
+//Counts requests and how long they took per route for the metrics
+pub struct HttpMetrics;
+
+//Compresses text responses with brotli or gzip, whichever the client accepts
+pub struct Compression;
+
+//Gives api clients the errors that didn't come from a HelipadError as JSON too, in the same shape
+pub struct ErrorMapping;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+
+//Middleware -------------------------------------------------------------------------------------------------
+#[async_trait]
+impl Middleware for Authentication {
+    async fn before(&self, ctx: &mut Context) -> Option<Response> {
+        //Preflight requests don't carry credentials
+        if ctx.req.method() == Method::OPTIONS {
+            return None;
+        }
+
+        ctx.user = handler::authenticate(&ctx.req, &ctx.helipad_config);
+        handler::login_required(ctx)
+    }
+
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        //Refresh the login once it's getting old, unless the handler set its own cookie.  Api tokens
+        //don't use cookies
+        if let Some(user) = ctx.user.as_ref().filter(|user| user.token_scope.is_none()) {
+            if !resp.headers().contains_key(header::SET_COOKIE) && handler::jwt_cookie_needs_refresh(user) {
+                handler::set_jwt_cookie(resp, &ctx.helipad_config, user);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RequestLog {
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        info!(
+            "{} {} {} -> {} ({}ms)",
+            ctx.state.remote_ip,
+            ctx.req.method(),
+            ctx.path,
+            resp.status().as_u16(),
+            ctx.started.elapsed().as_millis()
+        );
+    }
+}
+
+#[async_trait]
+impl Middleware for HttpMetrics {
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        metrics::record_http(ctx.req.method(), &ctx.route, resp.status(), ctx.started.elapsed());
+    }
+}
+
+#[async_trait]
+impl Middleware for Compression {
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        let compressible = resp.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .map(compressible)
+            .unwrap_or(false);
+
+        let encoding = match accepted_encoding(ctx.req.headers()) {
+            Some(encoding) if compressible && !resp.headers().contains_key(header::CONTENT_ENCODING) => encoding,
+            _ => return,
+        };
+
+        let body = match take_body(resp).await {
+            Some(body) => body,
+            None => return,
+        };
+
+        if body.len() < COMPRESSION_MIN_SIZE {
+            *resp.body_mut() = Body::from(body);
+            return;
+        }
+
+        match compress(&body, encoding) {
+            Ok(compressed) => {
+                let headers = resp.headers_mut();
+                headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+                headers.remove(header::CONTENT_LENGTH);
+                headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+                *resp.body_mut() = Body::from(compressed);
+            }
+            Err(e) => {
+                error!("Error compressing response: {}", e);
+                *resp.body_mut() = Body::from(body);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ErrorMapping {
+    async fn after(&self, ctx: &Context, resp: &mut Response) {
+        let status = resp.status();
+        if !ctx.path.starts_with("/api/") || !(status.is_client_error() || status.is_server_error()) {
+            return;
+        }
+
+        let is_json = resp.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .map(|content_type| content_type.starts_with("application/json"))
+            .unwrap_or(false);
+
+        if is_json {
+            return;
+        }
+
+        let body = match take_body(resp).await {
+            Some(body) => body,
+            None => return,
+        };
+
+        let message = String::from_utf8_lossy(&body).trim().to_string();
+        let message = if message.is_empty() {
+            status.canonical_reason().unwrap_or("Error").to_string()
+        } else {
+            message
+        };
+
+        let headers = resp.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.remove(header::CONTENT_LENGTH);
+        *resp.body_mut() = Body::from(json!({"error": message, "code": error::status_code(status), "details": null}).to_string());
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//The encoding to compress a response with, brotli first.  Ones the client gives a quality of 0 aren't accepted
+pub fn accepted_encoding(headers: &HeaderMap) -> Option<Encoding> {
+    let accepted: Vec<&str> = headers.get(header::ACCEPT_ENCODING)
+        .and_then(|val| val.to_str().ok())
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("");
+            let refused = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .any(|quality| quality.trim().parse::<f32>().map(|quality| quality <= 0.0).unwrap_or(false));
+            if refused { None } else { Some(name) }
+        })
+        .collect();
+
+    [Encoding::Brotli, Encoding::Gzip].iter().copied().find(|encoding| accepted.contains(&encoding.as_str()))
+}
+
+pub fn compressible(content_type: &str) -> bool {
+    COMPRESSIBLE_TYPES.iter().any(|prefix| content_type.starts_with(prefix))
+}
+
+pub fn compress(body: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(body)?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+//Read the whole body out of a response so it can be rewritten
+async fn take_body(resp: &mut Response) -> Option<hyper::body::Bytes> {
+    match hyper::body::to_bytes(std::mem::take(resp.body_mut())).await {
+        Ok(body) => Some(body),
+        Err(e) => {
+            error!("Error reading response body: {}", e);
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn brotli_is_preferred_over_gzip() {
+        assert_eq!(accepted_encoding(&accept_encoding("gzip, deflate, br")), Some(Encoding::Brotli));
+        assert_eq!(accepted_encoding(&accept_encoding("gzip;q=0.5, deflate")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(&accept_encoding("deflate, identity")), None);
+        assert_eq!(accepted_encoding(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn encodings_with_no_quality_are_refused() {
+        assert_eq!(accepted_encoding(&accept_encoding("br;q=0, gzip")), Some(Encoding::Gzip));
+        assert_eq!(accepted_encoding(&accept_encoding("br; q=0.0, gzip;q=0")), None);
+        assert_eq!(accepted_encoding(&accept_encoding("br;q=0.001")), Some(Encoding::Brotli));
+    }
+}
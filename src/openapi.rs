@@ -0,0 +1,367 @@
+use crate::api_v2::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use serde_json::{json, Map, Value};
+
+//The v1 endpoints, kept working for existing clients but superseded by v2
+const V1_ENDPOINTS: [(&str, &str, &str); 25] = [
+    ("get", "/api/v1/node_info", "Node alias, pubkey and LND version"),
+    ("get", "/api/v1/balance", "Channel balance"),
+    ("get", "/api/v1/index", "Newest received boost index"),
+    ("get", "/api/v1/sent_index", "Newest sent boost index"),
+    ("get", "/api/v1/boosts", "Received boosts, paged by index/count/old"),
+    ("get", "/api/v1/streams", "Received streaming payments, paged by index/count/old"),
+    ("get", "/api/v1/sent", "Sent boosts, paged by index/count/old"),
+    ("post", "/api/v1/reply", "Reply to a boost (form encoded index, sats, sender, message)"),
+    ("post", "/api/v1/mark_replied", "Mark a boost as replied to (form encoded index)"),
+    ("get", "/api/v1/destinations", "List destinations"),
+    ("post", "/api/v1/destinations", "Add or update a destination (form encoded)"),
+    ("post", "/api/v1/delete_destination", "Delete a destination (form encoded name)"),
+    ("get", "/api/v1/users", "List user accounts"),
+    ("post", "/api/v1/users", "Add a user account (form encoded)"),
+    ("post", "/api/v1/disable_user", "Disable or enable a user account"),
+    ("get", "/api/v1/sessions", "List active login sessions"),
+    ("post", "/api/v1/revoke_session", "End a login session"),
+    ("get", "/api/v1/tokens", "List api tokens"),
+    ("post", "/api/v1/tokens", "Create an api token"),
+    ("post", "/api/v1/revoke_token", "Revoke an api token"),
+    ("get", "/api/v1/login_attempts", "Login audit log"),
+    ("get", "/api/v1/totp", "Two-factor status"),
+    ("post", "/api/v1/totp/enroll", "Start two-factor enrollment"),
+    ("post", "/api/v1/totp/confirm", "Turn two-factor on with a code"),
+    ("post", "/api/v1/totp/disable", "Turn two-factor off with a code"),
+];
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//The OpenAPI 3 description of the api, with paths relative to the base path Helipad is served under
+pub fn document(version: &str, base_path: &str) -> Value {
+    let mut paths = v2_paths();
+
+    for (method, path, summary) in V1_ENDPOINTS.iter() {
+        let entry = paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}));
+
+        entry[*method] = json!({
+            "tags": ["v1"],
+            "summary": summary,
+            "deprecated": true,
+            "responses": {
+                "200": {"description": "Success"},
+                "default": {"$ref": "#/components/responses/Error"},
+            },
+        });
+    }
+
+    let server = if base_path.is_empty() { "/" } else { base_path };
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Helipad",
+            "description": "Boosts and streaming payments received by a podcaster's lightning node.  The /api/v1 endpoints are kept for existing clients, new ones should use /api/v2.",
+            "version": version,
+        },
+        "servers": [{"url": server}],
+        "security": [{"cookieAuth": []}, {"bearerAuth": []}],
+        "tags": [
+            {"name": "v2", "description": "JSON requests, typed responses and paged lists"},
+            {"name": "v1", "description": "The original api, with form encoded requests"},
+        ],
+        "paths": paths,
+        "components": components(),
+    })
+}
+
+fn v2_paths() -> Map<String, Value> {
+    let page_params = json!([
+        {"$ref": "#/components/parameters/index"},
+        {"$ref": "#/components/parameters/count"},
+        {"$ref": "#/components/parameters/old"},
+    ]);
+
+    let mut boost_page_params = page_params.clone();
+    if let Some(params) = boost_page_params.as_array_mut() {
+        params.push(json!({"$ref": "#/components/parameters/destination"}));
+    }
+
+    let paths = json!({
+        "/api/openapi.json": {
+            "get": {
+                "tags": ["v2"],
+                "summary": "This document",
+                "security": [],
+                "responses": {"200": {"description": "The OpenAPI document"}},
+            },
+        },
+        "/api/v2/node_info": {
+            "get": get_operation("Node alias, pubkey and LND version", "NodeInfo", json!([])),
+        },
+        "/api/v2/balance": {
+            "get": get_operation("Channel balance in sats", "Balance", json!([])),
+        },
+        "/api/v2/index": {
+            "get": get_operation("Newest received boost index", "Index", json!([])),
+        },
+        "/api/v2/sent_index": {
+            "get": get_operation("Newest sent boost index", "Index", json!([])),
+        },
+        "/api/v2/boosts": {
+            "get": get_operation("Received boosts", "BoostPage", boost_page_params.clone()),
+        },
+        "/api/v2/streams": {
+            "get": get_operation("Received streaming payments", "BoostPage", boost_page_params),
+        },
+        "/api/v2/sent": {
+            "get": get_operation("Sent boosts", "BoostPage", page_params),
+        },
+        "/api/v2/reply": {
+            "post": post_operation("Send a boost back to the sender of a received one", "ReplyRequest", "Boost"),
+        },
+        "/api/v2/mark_replied": {
+            "post": post_operation("Mark a received boost as replied to", "MarkRepliedRequest", "MarkedReplied"),
+        },
+        "/api/v2/destinations": {
+            "get": get_operation("Destinations (shows) incoming boosts are routed to", "DestinationList", json!([])),
+            "post": post_operation("Add or update a destination", "Destination", "Destination"),
+        },
+        "/api/v2/delete_destination": {
+            "post": post_operation("Delete a destination", "DeleteDestinationRequest", "DeletedDestination"),
+        },
+        "/api/v2/reindex": {
+            "get": get_operation("Progress of the last history reindex", "ReindexProgress", json!([])),
+            "post": {
+                "tags": ["v2"],
+                "summary": "Start reparsing every invoice and payment in LND's history",
+                "responses": {
+                    "200": json_content("Started", "ReindexProgress"),
+                    "409": {"$ref": "#/components/responses/Error"},
+                    "default": {"$ref": "#/components/responses/Error"},
+                },
+            },
+        },
+    });
+
+    match paths {
+        Value::Object(paths) => paths,
+        _ => Map::new(),
+    }
+}
+
+fn get_operation(summary: &str, schema: &str, parameters: Value) -> Value {
+    json!({
+        "tags": ["v2"],
+        "summary": summary,
+        "parameters": parameters,
+        "responses": {
+            "200": json_content("Success", schema),
+            "default": {"$ref": "#/components/responses/Error"},
+        },
+    })
+}
+
+fn post_operation(summary: &str, request: &str, response: &str) -> Value {
+    json!({
+        "tags": ["v2"],
+        "summary": summary,
+        "requestBody": {
+            "required": true,
+            "content": {"application/json": {"schema": schema_ref(request)}},
+        },
+        "responses": {
+            "200": json_content("Success", response),
+            "default": {"$ref": "#/components/responses/Error"},
+        },
+    })
+}
+
+fn json_content(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema_ref(schema)}},
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({"$ref": format!("#/components/schemas/{}", name)})
+}
+
+fn components() -> Value {
+    let integer = json!({"type": "integer", "format": "int64"});
+    let string = json!({"type": "string"});
+    let nullable_string = json!({"type": "string", "nullable": true});
+    let nullable_integer = json!({"type": "integer", "format": "int64", "nullable": true});
+
+    json!({
+        "securitySchemes": {
+            "cookieAuth": {"type": "apiKey", "in": "cookie", "name": "HELIPAD_JWT"},
+            "bearerAuth": {"type": "http", "scheme": "bearer", "description": "An api token made at /api/v1/tokens"},
+        },
+        "parameters": {
+            "index": {
+                "name": "index",
+                "in": "query",
+                "description": "Index to start the page from.  Defaults to the newest boost for older pages, otherwise the oldest",
+                "schema": {"type": "integer", "format": "int64", "minimum": 0},
+            },
+            "count": {
+                "name": "count",
+                "in": "query",
+                "description": "How many items to return",
+                "schema": {"type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE, "default": DEFAULT_PAGE_SIZE},
+            },
+            "old": {
+                "name": "old",
+                "in": "query",
+                "description": "Page back in time from index instead of forward",
+                "schema": {"type": "boolean", "default": false},
+            },
+            "destination": {
+                "name": "destination",
+                "in": "query",
+                "description": "Only include boosts routed to this destination",
+                "schema": string,
+            },
+        },
+        "responses": {
+            "Error": json_content("Something went wrong, see the code", "Error"),
+        },
+        "schemas": {
+            "Error": {
+                "type": "object",
+                "required": ["error", "code"],
+                "properties": {
+                    "error": string,
+                    "code": {
+                        "type": "string",
+                        "example": "bad_request",
+                        "description": "bad_request, forbidden, not_found, database_error, lightning_error, podcastindex_error, file_error, template_error or internal_error",
+                    },
+                    "details": nullable_string,
+                },
+            },
+            "NodeInfo": {
+                "type": "object",
+                "properties": {"alias": string, "pubkey": string, "version": string},
+            },
+            "Balance": {
+                "type": "object",
+                "properties": {"balance": integer},
+            },
+            "Index": {
+                "type": "object",
+                "properties": {"index": integer},
+            },
+            "Payment": {
+                "type": "object",
+                "properties": {
+                    "payment_hash": string,
+                    "pubkey": string,
+                    "custom_key": nullable_integer,
+                    "custom_value": nullable_string,
+                    "fee_msat": integer,
+                    "reply_to_index": nullable_integer,
+                },
+            },
+            "Boost": {
+                "type": "object",
+                "properties": {
+                    "index": integer,
+                    "time": integer,
+                    "value_msat": integer,
+                    "value_msat_total": integer,
+                    "action": {"type": "string", "enum": ["stream", "boost", "auto", "unknown"]},
+                    "sender": string,
+                    "app": string,
+                    "message": string,
+                    "podcast": string,
+                    "episode": string,
+                    "remote_podcast": nullable_string,
+                    "remote_episode": nullable_string,
+                    "reply_sent": {"type": "boolean"},
+                    "source": {"type": "string", "enum": ["keysend", "lnurl"]},
+                    "destination": nullable_string,
+                    "tlv": {"type": "object", "nullable": true, "description": "The podcasting 2.0 tlv record as sent"},
+                    "payment": {"$ref": "#/components/schemas/Payment"},
+                },
+            },
+            "Pagination": {
+                "type": "object",
+                "properties": {
+                    "index": integer,
+                    "count": integer,
+                    "old": {"type": "boolean"},
+                    "next_index": {
+                        "type": "integer",
+                        "format": "int64",
+                        "nullable": true,
+                        "description": "Index for the next page with the same old flag.  Null once there are no older boosts",
+                    },
+                },
+            },
+            "BoostPage": {
+                "type": "object",
+                "properties": {
+                    "items": {"type": "array", "items": schema_ref("Boost")},
+                    "pagination": schema_ref("Pagination"),
+                },
+            },
+            "Destination": {
+                "type": "object",
+                "required": ["name"],
+                "properties": {
+                    "name": string,
+                    "custom_key": nullable_integer,
+                    "custom_value": nullable_string,
+                    "podcast_guid": nullable_string,
+                },
+            },
+            "DestinationList": {
+                "type": "object",
+                "properties": {
+                    "items": {"type": "array", "items": schema_ref("Destination")},
+                },
+            },
+            "DeleteDestinationRequest": {
+                "type": "object",
+                "required": ["name"],
+                "properties": {"name": string},
+            },
+            "DeletedDestination": {
+                "type": "object",
+                "properties": {"name": string, "deleted": {"type": "boolean"}},
+            },
+            "ReplyRequest": {
+                "type": "object",
+                "required": ["index", "sats"],
+                "properties": {
+                    "index": integer,
+                    "sats": integer,
+                    "sender": {"type": "string", "default": "Anonymous"},
+                    "message": string,
+                },
+            },
+            "MarkRepliedRequest": {
+                "type": "object",
+                "required": ["index"],
+                "properties": {"index": integer},
+            },
+            "MarkedReplied": {
+                "type": "object",
+                "properties": {"index": integer, "reply_sent": {"type": "boolean"}},
+            },
+            "ReindexProgress": {
+                "type": "object",
+                "properties": {
+                    "running": {"type": "boolean"},
+                    "started": integer,
+                    "finished": nullable_integer,
+                    "invoices_scanned": integer,
+                    "boosts_found": integer,
+                    "payments_scanned": integer,
+                    "sent_boosts_found": integer,
+                    "error": nullable_string,
+                },
+            },
+        },
+    })
+}
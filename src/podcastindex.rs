@@ -1,11 +1,27 @@
+use crate::error::HelipadError;
+use crate::metrics;
+use data_encoding::HEXLOWER;
 use reqwest;
-use reqwest::header::USER_AGENT;
+use reqwest::header::{AUTHORIZATION, USER_AGENT};
 use serde_json::Value;
-use std::error::Error;
+use sha1::{Digest, Sha1};
 
 use std::num::NonZeroUsize;
+use std::sync::RwLock;
+use std::time::Duration;
 use lru::LruCache;
 
+//How the Podcast Index is called, set from the configuration at startup
+static API: RwLock<Api> = RwLock::new(Api {
+    key: None,
+    timeout: Duration::from_secs(10),
+});
+
+struct Api {
+    key: Option<(String, String)>,
+    timeout: Duration,
+}
+
 #[derive(Clone, Debug)]
 pub struct PodcastEpisodeGuid {
     pub podcast_guid: String,
@@ -21,30 +37,43 @@ pub struct GuidCache {
 impl GuidCache {
     pub fn new(size: usize) -> GuidCache {
         GuidCache {
-            cache: LruCache::new(NonZeroUsize::new(size).unwrap()),
+            cache: LruCache::new(NonZeroUsize::new(size).unwrap_or(NonZeroUsize::MIN)),
         }
     }
 
     // Fetches remote podcast/episode names by guids using the Podcastindex API and caches results into an LRU cache
-    pub async fn get(&mut self, podcast_guid: String, episode_guid: String) -> Result<PodcastEpisodeGuid, Box<dyn Error>> {
+    pub async fn get(&mut self, podcast_guid: String, episode_guid: String) -> Result<PodcastEpisodeGuid, HelipadError> {
         let key = format!("{}_{}", podcast_guid, episode_guid);
 
         if let Some(cached_guid) = self.cache.get(&key) {
-            println!("Remote podcast/episode from cache: {:#?}", cached_guid);
+            debug!("Remote podcast/episode from cache: {:?}", cached_guid);
+            metrics::record_podcastindex_cache(true);
             return Ok(cached_guid.clone()); // already exists in cache
         }
 
-        let guid = fetch_api_podcast_episode_by_guid(&podcast_guid, &episode_guid).await?;
+        metrics::record_podcastindex_cache(false);
+
+        let guid = fetch_api_podcast_episode_by_guid(&podcast_guid, &episode_guid).await;
+        metrics::record_podcastindex_lookup(guid.is_ok());
+        let guid = guid?;
 
-        println!("Remote podcast/episode from API: {:#?}", guid);
+        debug!("Remote podcast/episode from API: {:?}", guid);
         self.cache.put(key, guid.clone()); // cache to avoid spamming api
 
         Ok(guid)
     }
 }
 
+// Sets the api key and secret to sign requests with (if there are any), and how long to wait on the API
+pub fn configure(api_key: Option<String>, api_secret: Option<String>, timeout: Duration) {
+    if let Ok(mut api) = API.write() {
+        api.key = api_key.zip(api_secret);
+        api.timeout = timeout;
+    }
+}
+
 // Fetches remote podcast/episode names by guids using the Podcastindex API
-pub async fn fetch_api_podcast_episode_by_guid(podcast_guid: &str, episode_guid: &str) -> Result<PodcastEpisodeGuid, Box<dyn Error>> {
+pub async fn fetch_api_podcast_episode_by_guid(podcast_guid: &str, episode_guid: &str) -> Result<PodcastEpisodeGuid, HelipadError> {
     let query = vec![
         ("podcastguid", podcast_guid),
         ("episodeguid", episode_guid)
@@ -59,16 +88,42 @@ pub async fn fetch_api_podcast_episode_by_guid(podcast_guid: &str, episode_guid:
 
     let app_version = env!("CARGO_PKG_VERSION");
 
-    // call API, get text response, and parse into json
-    let response = reqwest::Client::new()
+    let (key, timeout) = match API.read() {
+        Ok(api) => (api.key.clone(), api.timeout),
+        Err(_) => (None, Duration::from_secs(10)),
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| HelipadError::podcastindex("Error making the Podcastindex API client", e))?;
+
+    let mut request = client
         .get("https://api.podcastindex.org/api/1.0/value/byepisodeguid")
         .header(USER_AGENT, format!("Helipad/{}", app_version))
-        .query(&query)
+        .query(&query);
+
+    // sign the request when there's an api key: sha1 of the key, secret and time
+    if let Some((api_key, api_secret)) = key {
+        let date = chrono::Utc::now().timestamp().to_string();
+        let hash = Sha1::digest(format!("{}{}{}", api_key, api_secret, date).as_bytes());
+
+        request = request
+            .header("X-Auth-Key", api_key)
+            .header("X-Auth-Date", date)
+            .header(AUTHORIZATION, HEXLOWER.encode(&hash));
+    }
+
+    // call API, get text response, and parse into json
+    let response = request
         .send()
-        .await?;
+        .await
+        .map_err(|e| HelipadError::podcastindex("Error calling the Podcastindex API", e))?;
 
-    let result = response.text().await?;
-    let json: Value = serde_json::from_str(&result)?;
+    let result = response.text().await
+        .map_err(|e| HelipadError::podcastindex("Error reading the Podcastindex API response", e))?;
+    let json: Value = serde_json::from_str(&result)
+        .map_err(|e| HelipadError::podcastindex("Invalid Podcastindex API response", e))?;
 
     let status = json["status"].as_str().unwrap_or_default();
 
@@ -0,0 +1,179 @@
+use crate::error::HelipadError;
+use crate::{lightning, metrics, podcastindex, shutdown, HelipadConfig};
+use serde::Serialize;
+use std::sync::Mutex;
+
+//How many invoices or payments are asked of LND at a time
+const BATCH_SIZE: u64 = 500;
+
+//Bigger than the poller's, since going over the whole history finds the same podcasts over and over
+const REMOTE_GUID_CACHE_SIZE: usize = 200;
+
+//How the last (or current) reindex went, for the api to report
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Progress {
+    pub running: bool,
+    pub started: i64,
+    pub finished: Option<i64>,
+    pub invoices_scanned: u64,
+    pub boosts_found: u64,
+    pub payments_scanned: u64,
+    pub sent_boosts_found: u64,
+    pub error: Option<String>,
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+pub fn progress() -> Option<Progress> {
+    PROGRESS.lock().ok().and_then(|progress| progress.clone())
+}
+
+fn update(change: impl FnOnce(&mut Progress)) -> Progress {
+    match PROGRESS.lock() {
+        Ok(mut progress) => {
+            let progress = progress.get_or_insert_with(Progress::default);
+            change(progress);
+            progress.clone()
+        }
+        Err(_) => Progress::default(),
+    }
+}
+
+//Claim the reindex before starting it, so only one runs at a time
+pub fn start() -> Result<Progress, HelipadError> {
+    let mut progress = PROGRESS.lock()
+        .map_err(|e| HelipadError::internal("Error getting reindex progress", e))?;
+
+    if progress.as_ref().map(|progress| progress.running).unwrap_or(false) {
+        return Err(HelipadError::Conflict("A reindex is already running.".to_string()));
+    }
+
+    let started = Progress {
+        running: true,
+        started: chrono::Utc::now().timestamp(),
+        ..Progress::default()
+    };
+    *progress = Some(started.clone());
+
+    Ok(started)
+}
+
+//Go over every invoice and payment LND has, parsing them again and storing what's found.  Boosts that are
+//already stored get refreshed, but keep whether they've been replied to.  Call start() first.  Shutting down
+//stops it between batches, keeping the batches already stored
+pub async fn run(helipad_config: HelipadConfig, shutdown: shutdown::Watcher) -> Result<Progress, HelipadError> {
+    let result = reindex(&helipad_config, &shutdown).await;
+
+    let progress = update(|progress| {
+        progress.running = false;
+        progress.finished = Some(chrono::Utc::now().timestamp());
+        progress.error = result.as_ref().err().map(|e| e.to_string());
+    });
+
+    match result {
+        Ok(_) => {
+            info!("Reindex finished: {} boosts in {} invoices, {} sent boosts in {} payments.",
+                progress.boosts_found, progress.invoices_scanned, progress.sent_boosts_found, progress.payments_scanned);
+            Ok(progress)
+        }
+        Err(e) => {
+            error!("Reindex failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+fn check_stopping(shutdown: &shutdown::Watcher) -> Result<(), HelipadError> {
+    if shutdown.is_stopping() {
+        return Err(HelipadError::internal("Reindex stopped before it finished", "shutting down"));
+    }
+
+    Ok(())
+}
+
+async fn reindex(helipad_config: &HelipadConfig, shutdown: &shutdown::Watcher) -> Result<(), HelipadError> {
+    let db_filepath = &helipad_config.database_file_path;
+
+    let mut lightning = lightning::connect_to_lnd(
+        helipad_config.node_address.clone(),
+        helipad_config.cert_path.clone(),
+        helipad_config.macaroon_path.clone(),
+    ).await.ok_or_else(|| HelipadError::lightning("Unable to connect to LND", &helipad_config.node_address))?;
+
+    let destinations = dbif::get_destinations_from_db(db_filepath)
+        .map_err(|e| HelipadError::database("Error getting destinations", e))?;
+
+    let mut remote_cache = podcastindex::GuidCache::new(REMOTE_GUID_CACHE_SIZE);
+
+    //Received boosts
+    let mut index = 0;
+    loop {
+        check_stopping(shutdown)?;
+
+        let response = metrics::observe_lnd("list_invoices", lnd::Lnd::list_invoices(&mut lightning, false, index, BATCH_SIZE, false)).await
+            .map_err(|e| HelipadError::lightning("Error listing invoices", e.message()))?;
+
+        if response.invoices.is_empty() {
+            break;
+        }
+
+        let scanned = response.invoices.len() as u64;
+        let mut boosts = Vec::new();
+
+        for invoice in response.invoices {
+            index = invoice.add_index;
+
+            if let Some(boost) = lightning::parse_boost_from_invoice(invoice, &mut remote_cache, &destinations).await {
+                boosts.push(boost);
+            }
+        }
+
+        dbif::reindex_invoices_in_db(db_filepath, &boosts)
+            .map_err(|e| HelipadError::database("Error storing reindexed boosts", e))?;
+
+        let progress = update(|progress| {
+            progress.invoices_scanned += scanned;
+            progress.boosts_found += boosts.len() as u64;
+        });
+        info!("Reindexed invoices up to index {}: {} boosts in {} invoices so far.", index, progress.boosts_found, progress.invoices_scanned);
+    }
+
+    //Sent boosts
+    let mut index = 0;
+    loop {
+        check_stopping(shutdown)?;
+
+        let response = metrics::observe_lnd("list_payments", lnd::Lnd::list_payments(&mut lightning, false, index, BATCH_SIZE, false)).await
+            .map_err(|e| HelipadError::lightning("Error listing payments", e.message()))?;
+
+        if response.payments.is_empty() {
+            break;
+        }
+
+        let scanned = response.payments.len() as u64;
+        let mut boosts = Vec::new();
+
+        for payment in response.payments {
+            index = payment.payment_index;
+
+            if let Some(boost) = lightning::parse_boost_from_payment(payment, &mut remote_cache).await {
+                boosts.push(boost);
+            }
+        }
+
+        dbif::reindex_payments_in_db(db_filepath, &boosts)
+            .map_err(|e| HelipadError::database("Error storing reindexed sent boosts", e))?;
+
+        let progress = update(|progress| {
+            progress.payments_scanned += scanned;
+            progress.sent_boosts_found += boosts.len() as u64;
+        });
+        info!("Reindexed payments up to index {}: {} sent boosts in {} payments so far.", index, progress.sent_boosts_found, progress.payments_scanned);
+    }
+
+    Ok(())
+}
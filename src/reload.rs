@@ -0,0 +1,218 @@
+use crate::config::Config;
+use crate::settings::{self, Settings};
+use crate::{assets, auth, logging, podcastindex, HelipadConfig};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+
+//How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+//Settings that take effect without a restart.  Changes to any others are only noted in the log
+const RELOADABLE_SETTINGS: [&str; 8] = [
+    "password",
+    "webroot_dir",
+    "trusted_proxies",
+    "log_level",
+    "podcastindex_api_key",
+    "podcastindex_api_secret",
+    "podcastindex_timeout",
+    "health_check_timeout",
+];
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+//The configuration requests are answered with.  Reloading swaps in a new one, which every request after sees
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<HelipadConfig>>>,
+}
+
+//What's needed to work the settings out again: the command line doesn't change, but the environment and
+//config file are read again
+pub struct Reloader {
+    shared: SharedConfig,
+    arg_config: Config,
+    port: Option<u16>,
+    default_log_level: &'static str,
+    settings: Settings,
+}
+
+impl SharedConfig {
+    pub fn new(helipad_config: HelipadConfig) -> SharedConfig {
+        SharedConfig {
+            current: Arc::new(RwLock::new(Arc::new(helipad_config))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<HelipadConfig> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn swap(&self, helipad_config: HelipadConfig) {
+        match self.current.write() {
+            Ok(mut current) => *current = Arc::new(helipad_config),
+            Err(poisoned) => *poisoned.into_inner() = Arc::new(helipad_config),
+        }
+    }
+}
+
+impl Reloader {
+    pub fn new(shared: SharedConfig, arg_config: Config, port: Option<u16>, default_log_level: &'static str, settings: Settings) -> Reloader {
+        Reloader {
+            shared,
+            arg_config,
+            port,
+            default_log_level,
+            settings,
+        }
+    }
+
+    //Reload on SIGHUP, or when the config file is changed
+    pub fn watch(mut self) {
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!("Unable to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            let mut modified = config_file_modified();
+
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => {
+                        info!("Got SIGHUP, reloading the configuration...");
+                        modified = config_file_modified();
+                    }
+                    _ = interval.tick() => {
+                        let now_modified = config_file_modified();
+                        if now_modified == modified {
+                            continue;
+                        }
+                        modified = now_modified;
+                        info!("{} changed, reloading the configuration...", settings::CONFIG_FILE);
+                    }
+                }
+
+                self.reload();
+            }
+        });
+    }
+
+    //Anything wrong with the new settings leaves the ones in use as they are
+    fn reload(&mut self) {
+        let merged = match Config::including_optional_config_files(&[settings::CONFIG_FILE]) {
+            Ok((merged, _)) => merged,
+            Err(e) => {
+                error!("Configuration not reloaded: {}", e);
+                return;
+            }
+        };
+
+        let settings = settings::load(&self.arg_config, &merged, self.port, self.default_log_level);
+        if !settings.errors.is_empty() {
+            for e in &settings.errors {
+                error!("Configuration error: {}", e);
+            }
+            error!("Configuration not reloaded.  Still using the settings from before.");
+            return;
+        }
+
+        //Theme files can change without the config file changing, so they're always loaded again
+        let mut webroot_loaded = true;
+        if settings.webroot_dir.is_some() || self.settings.webroot_dir.is_some() {
+            match assets::load(settings.webroot_dir.as_deref()) {
+                Ok(_) => info!("Reloaded the webroot files."),
+                Err(e) => {
+                    error!("Webroot files not reloaded: {}", e);
+                    webroot_loaded = false;
+                }
+            }
+        }
+
+        let changed = self.settings.changed(&settings);
+        if changed.is_empty() {
+            info!("Configuration is unchanged.");
+            return;
+        }
+
+        let (reloadable, needs_restart): (Vec<&str>, Vec<&str>) = changed.into_iter()
+            .partition(|name| RELOADABLE_SETTINGS.contains(name));
+
+        if !needs_restart.is_empty() {
+            warn!("Restart Helipad to use the new {}.", needs_restart.join(", "));
+        }
+
+        //Only what's actually put to use is remembered, so anything left out is tried again on the next reload
+        let mut applied = reloadable;
+        if !webroot_loaded {
+            applied.retain(|name| *name != "webroot_dir");
+        }
+
+        if applied.is_empty() {
+            return;
+        }
+
+        let mut helipad_config = self.shared.current().as_ref().clone();
+
+        if applied.contains(&"password") {
+            helipad_config.password = match password_hash(&settings.password) {
+                Ok(password) => password,
+                Err(e) => {
+                    error!("Configuration not reloaded.  Error hashing password: {}", e);
+                    return;
+                }
+            };
+        }
+
+        if applied.contains(&"log_level") {
+            if let Err(e) = logging::set_level(&settings.log_level) {
+                error!("Error changing the log level: {}", e);
+                applied.retain(|name| *name != "log_level");
+            }
+        }
+
+        for secret in settings.podcastindex_api_key.iter().chain(settings.podcastindex_api_secret.iter()) {
+            logging::add_secret(secret);
+        }
+        podcastindex::configure(settings.podcastindex_api_key.clone(), settings.podcastindex_api_secret.clone(), settings.podcastindex_timeout);
+
+        helipad_config.trusted_proxies = settings.trusted_proxies.clone();
+        helipad_config.trust_unix_socket = settings.trust_unix_socket;
+        helipad_config.health_check_timeout = settings.health_check_timeout;
+
+        self.shared.swap(helipad_config);
+        self.settings.adopt(&applied, &settings);
+
+        if !applied.is_empty() {
+            info!("Reloaded the configuration.  Now using the new {}.", applied.join(", "));
+        }
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Only a hash of the password is kept, like at startup
+fn password_hash(password: &str) -> Result<String, String> {
+    logging::add_secret(password);
+
+    if password.is_empty() || auth::is_password_hash(password) {
+        return Ok(password.to_string());
+    }
+
+    let hash = auth::hash_password(password).map_err(|e| e.to_string())?;
+    logging::add_secret(&hash);
+
+    Ok(hash)
+}
+
+fn config_file_modified() -> Option<SystemTime> {
+    std::fs::metadata(settings::CONFIG_FILE).and_then(|metadata| metadata.modified()).ok()
+}
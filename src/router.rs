@@ -1,9 +1,11 @@
-use crate::{Context, Response};
+use crate::{AppState, Context, HelipadConfig, Response};
 use async_trait::async_trait;
 use futures::future::Future;
-use hyper::{Method, StatusCode};
+use hyper::header;
+use hyper::{Body, Method, Request, StatusCode, Uri};
 use route_recognizer::{Match, Params, Router as InternalRouter};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[async_trait]
 pub trait Handler: Send + Sync + 'static {
@@ -22,13 +24,36 @@ where
     }
 }
 
-pub struct RouterMatch<'a> {
-    pub handler: &'a dyn Handler,
-    pub params: Params,
+//Cross-cutting request handling wrapped around every handler.  The before hooks run in the order the
+//middleware was added and can answer the request themselves, skipping the handler and any middleware
+//after them.  The after hooks then run in reverse order for each middleware whose before hook ran
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    async fn before(&self, _ctx: &mut Context) -> Option<Response> {
+        None
+    }
+
+    async fn after(&self, _ctx: &Context, _resp: &mut Response) {}
+}
+
+//A handler along with the path pattern it was added for, like "/.well-known/lnurlp/:name"
+struct Route {
+    pattern: String,
+    handler: Box<dyn Handler>,
+}
+
+//What a request resolved to
+enum Endpoint<'a> {
+    Handler(&'a dyn Handler),
+    Options(Vec<Method>),
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
 }
 
 pub struct Router {
-    method_map: HashMap<Method, InternalRouter<Box<dyn Handler>>>,
+    method_map: HashMap<Method, InternalRouter<Route>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    base_path: String,
 }
 
 //This web router is based on the example code here:
@@ -37,56 +62,176 @@ impl Router {
     pub fn new() -> Router {
         Router {
             method_map: HashMap::default(),
+            middleware: Vec::new(),
+            base_path: "".to_string(),
         }
     }
 
-    pub fn get(&mut self, path: &str, handler: Box<dyn Handler>) {
-        self.method_map
-            .entry(Method::GET)
-            .or_insert_with(InternalRouter::new)
-            .add(path, handler)
+    //Add a middleware to the end of the pipeline
+    pub fn wrap(&mut self, middleware: Box<dyn Middleware>) {
+        self.middleware.push(middleware);
     }
 
-    pub fn options(&mut self, path: &str, handler: Box<dyn Handler>) {
-        self.method_map
-            .entry(Method::OPTIONS)
-            .or_insert_with(InternalRouter::new)
-            .add(path, handler)
+    //Serve every route under this url path, for running behind a reverse proxy at e.g. /helipad
+    pub fn set_base_path(&mut self, base_path: &str) {
+        self.base_path = normalize_base_path(base_path);
+    }
+
+    //The request path relative to the base path, or None if it's outside of it
+    fn strip_base_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        if self.base_path.is_empty() {
+            return Some(path);
+        }
+
+        match path.strip_prefix(self.base_path.as_str()) {
+            Some("") => Some("/"),
+            Some(rest) if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+
+    pub fn get(&mut self, path: &str, handler: Box<dyn Handler>) {
+        self.add(Method::GET, path, handler)
     }
 
     pub fn post(&mut self, path: &str, handler: Box<dyn Handler>) {
+        self.add(Method::POST, path, handler)
+    }
+
+    fn add(&mut self, method: Method, path: &str, handler: Box<dyn Handler>) {
         self.method_map
-            .entry(Method::POST)
+            .entry(method)
             .or_insert_with(InternalRouter::new)
-            .add(path, handler)
+            .add(path, Route {
+                pattern: path.to_string(),
+                handler,
+            })
     }
 
-    pub fn route(&self, path: &str, method: &Method) -> RouterMatch<'_> {
-        if let Some(Match { handler, params }) = self
+    //Find the handler for a path, or what to answer with when there isn't one for this method.  Also gives
+    //the pattern of the route the path matched, or an empty one if it didn't match any
+    fn lookup(&self, path: &str, method: &Method) -> (Endpoint<'_>, Params, String) {
+        let path = match self.strip_base_path(path) {
+            Some(path) => path,
+            None => return (Endpoint::NotFound, Params::new(), "".to_string()),
+        };
+
+        if let Some(Match { handler: route, params }) = self
             .method_map
             .get(method)
             .and_then(|r| r.recognize(path).ok())
         {
-            RouterMatch {
-                handler: &**handler,
-                params,
+            return (Endpoint::Handler(&*route.handler), params, route.pattern.clone());
+        }
+
+        let mut pattern = "".to_string();
+        let mut methods: Vec<Method> = Vec::new();
+        for (method, r) in &self.method_map {
+            if let Ok(found) = r.recognize(path) {
+                pattern = found.handler.pattern.clone();
+                methods.push(method.clone());
             }
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        if methods.is_empty() {
+            (Endpoint::NotFound, Params::new(), pattern)
+        } else if method == Method::OPTIONS {
+            (Endpoint::Options(methods), Params::new(), pattern)
         } else {
-            RouterMatch {
-                handler: &not_found_handler,
-                params: Params::new(),
+            (Endpoint::MethodNotAllowed(methods), Params::new(), pattern)
+        }
+    }
+
+    //Run a request through the middleware and on to its handler
+    pub async fn handle(&self, mut req: Request<Body>, app_state: AppState, helipad_config: Arc<HelipadConfig>) -> Response {
+        let (endpoint, params, route) = self.lookup(req.uri().path(), req.method());
+
+        //Handlers only see the part of the path after the base path
+        let path = self.strip_base_path(req.uri().path()).unwrap_or("/").to_owned();
+        if path != req.uri().path() {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path.clone(),
+            };
+            if let Ok(uri) = path_and_query.parse::<Uri>() {
+                *req.uri_mut() = uri;
+            }
+        }
+
+        let mut ctx = Context::new(app_state, req, &path, params, helipad_config);
+        ctx.route = route;
+
+        let mut ran = 0;
+        let mut early_resp = None;
+        for middleware in &self.middleware {
+            ran += 1;
+            if let Some(resp) = middleware.before(&mut ctx).await {
+                early_resp = Some(resp);
+                break;
             }
         }
+
+        //The handler takes the context, so the after hooks get a copy without the body
+        let after_ctx = ctx.without_body();
+
+        let mut resp = match (early_resp, endpoint) {
+            (Some(resp), _) => resp,
+            (None, Endpoint::Handler(handler)) => handler.invoke(ctx).await,
+            (None, Endpoint::Options(methods)) => options_response(&methods),
+            (None, Endpoint::MethodNotAllowed(methods)) => method_not_allowed_response(&methods),
+            (None, Endpoint::NotFound) => not_found_response(),
+        };
+
+        for middleware in self.middleware[..ran].iter().rev() {
+            middleware.after(&after_ctx, &mut resp).await;
+        }
+
+        resp
     }
 }
 
-async fn not_found_handler(_cx: Context) -> Response {
+//Base paths start with a slash and don't end with one, with an empty one meaning the root
+pub fn normalize_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim().trim_matches('/');
+
+    if trimmed.is_empty() {
+        return "".to_string();
+    }
+
+    format!("/{}", trimmed)
+}
+
+fn not_found_response() -> Response {
     hyper::Response::builder()
         .status(StatusCode::NOT_FOUND)
         .body("NOT FOUND".into())
         .unwrap()
 }
 
+//The methods a path can be requested with, always including OPTIONS since those are answered automatically
+fn allow_header(methods: &[Method]) -> String {
+    let mut allow: Vec<&str> = methods.iter().map(|method| method.as_str()).collect();
+    allow.push(Method::OPTIONS.as_str());
+    allow.join(", ")
+}
+
+fn options_response(methods: &[Method]) -> Response {
+    hyper::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::ALLOW, allow_header(methods))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn method_not_allowed_response(methods: &[Method]) -> Response {
+    hyper::Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(header::ALLOW, allow_header(methods))
+        .body("METHOD NOT ALLOWED".into())
+        .unwrap()
+}
+
 pub trait IntoResponse: Send + Sized {
     fn into_response(self) -> Response;
 }
@@ -107,4 +252,83 @@ impl IntoResponse for String {
     fn into_response(self) -> Response {
         Response::new(self.into())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ok(_ctx: Context) -> &'static str {
+        "ok"
+    }
+
+    fn test_router(base_path: &str) -> Router {
+        let mut router = Router::new();
+        router.set_base_path(base_path);
+        router.get("/", Box::new(ok));
+        router.get("/boosts", Box::new(ok));
+        router.post("/boosts", Box::new(ok));
+        router.get("/lnurlp/:name", Box::new(ok));
+        router
+    }
+
+    #[test]
+    fn base_paths_are_normalized() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("helipad"), "/helipad");
+        assert_eq!(normalize_base_path(" /helipad/ "), "/helipad");
+        assert_eq!(normalize_base_path("/tools/helipad/"), "/tools/helipad");
+    }
+
+    #[test]
+    fn base_path_is_stripped_from_requests() {
+        let router = test_router("/helipad");
+
+        assert_eq!(router.strip_base_path("/helipad"), Some("/"));
+        assert_eq!(router.strip_base_path("/helipad/boosts"), Some("/boosts"));
+        assert_eq!(router.strip_base_path("/helipadboosts"), None);
+        assert_eq!(router.strip_base_path("/boosts"), None);
+
+        let (endpoint, params, route) = router.lookup("/helipad/lnurlp/alice", &Method::GET);
+        assert!(matches!(endpoint, Endpoint::Handler(_)));
+        assert_eq!(params.find("name"), Some("alice"));
+        assert_eq!(route, "/lnurlp/:name");
+
+        assert!(matches!(router.lookup("/boosts", &Method::GET).0, Endpoint::NotFound));
+        assert!(matches!(test_router("").lookup("/boosts", &Method::GET).0, Endpoint::Handler(_)));
+    }
+
+    #[test]
+    fn options_are_answered_with_the_allowed_methods() {
+        let router = test_router("");
+
+        let (endpoint, _, route) = router.lookup("/boosts", &Method::OPTIONS);
+        match endpoint {
+            Endpoint::Options(methods) => assert_eq!(methods, vec![Method::GET, Method::POST]),
+            _ => panic!("OPTIONS wasn't answered by the router"),
+        }
+        assert_eq!(route, "/boosts");
+
+        let resp = options_response(&[Method::GET, Method::POST]);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers().get(header::ALLOW).unwrap(), "GET, POST, OPTIONS");
+
+        assert!(matches!(router.lookup("/missing", &Method::OPTIONS).0, Endpoint::NotFound));
+    }
+
+    #[test]
+    fn unsupported_methods_get_405_with_allow() {
+        let router = test_router("");
+
+        match router.lookup("/lnurlp/alice", &Method::DELETE).0 {
+            Endpoint::MethodNotAllowed(methods) => assert_eq!(methods, vec![Method::GET]),
+            _ => panic!("DELETE wasn't refused"),
+        }
+        assert!(matches!(router.lookup("/boosts", &Method::POST).0, Endpoint::Handler(_)));
+
+        let resp = method_not_allowed_response(&[Method::GET]);
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(resp.headers().get(header::ALLOW).unwrap(), "GET, OPTIONS");
+    }
+}
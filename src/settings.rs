@@ -0,0 +1,527 @@
+use crate::cors;
+use crate::lightning::{self, KeysendAddress};
+use crate::logging;
+use crate::router;
+use crate::config::Config;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+pub const CONFIG_FILE: &str = "./helipad.conf";
+
+const STANDARD_DATABASE_DIR: &str = "database.db";
+const STANDARD_LISTEN_PORT: u16 = 2112;
+const STANDARD_LISTEN_ADDRESS: &str = "0.0.0.0";
+
+const STANDARD_LOG_FORMAT: &str = "text";
+
+const STANDARD_CORS_ALLOWED_ORIGINS: &str = "";
+const STANDARD_CORS_ALLOWED_METHODS: &str = "GET, POST";
+
+const STANDARD_LND_URL: &str = "https://127.0.0.1:10009";
+const STANDARD_LND_MACAROON: &str = "/lnd/data/chain/bitcoin/mainnet/admin.macaroon";
+const STANDARD_LND_TLSCERT: &str = "/lnd/tls.cert";
+
+//Looked for in the working directory before falling back to where LND keeps them
+const LOCAL_LND_MACAROON: &str = "admin.macaroon";
+const LOCAL_LND_TLSCERT: &str = "tls.cert";
+
+//In seconds
+const STANDARD_PODCASTINDEX_TIMEOUT: u64 = 10;
+const STANDARD_HEALTH_CHECK_TIMEOUT: u64 = 5;
+
+//Listen addresses starting with this are unix domain socket paths
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+//Listed in trusted_proxies to trust the X-Forwarded headers on requests over the unix socket
+const UNIX_SOCKET_PROXY: &str = "unix";
+
+//Shown in place of these when printing the configuration.  The macaroon location is kept out of the logs too
+const SECRET_SETTINGS: [&str; 4] = ["password", "macaroon", "podcastindex_api_key", "podcastindex_api_secret"];
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+//Where a setting's value came from, from most to least important
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    CommandLine,
+    Environment(&'static str),
+    ConfigFile,
+    WorkingDirectory,
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CommandLine => write!(f, "command line"),
+            Source::Environment(var) => write!(f, "env {}", var),
+            Source::ConfigFile => write!(f, "config file {}", CONFIG_FILE),
+            Source::WorkingDirectory => write!(f, "working directory"),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+//One setting as it was found, for printing the configuration
+#[derive(Clone, Debug)]
+struct Entry {
+    name: &'static str,
+    value: Option<String>,
+    source: Source,
+}
+
+//Every setting, worked out from the command line, environment, config file and defaults in that order
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub database_dir: String,
+    pub listen_port: u16,
+    pub listen_address: String,
+    pub base_path: String,
+    pub webroot_dir: Option<String>,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub trust_unix_socket: bool,
+    pub password: String,
+    pub macaroon: String,
+    pub cert: String,
+    pub lnd_url: String,
+    pub keysend_addresses: Vec<KeysendAddress>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_self_signed: bool,
+    pub tls_redirect_port: Option<u16>,
+    pub cors_allowed_origins: String,
+    pub cors_allowed_methods: String,
+    pub cors_allow_credentials: bool,
+    pub log_level: String,
+    pub log_format: logging::Format,
+    pub podcastindex_api_key: Option<String>,
+    pub podcastindex_api_secret: Option<String>,
+    pub podcastindex_timeout: Duration,
+    pub health_check_timeout: Duration,
+    //Everything that's wrong with the configuration, worded so it can be fixed
+    pub errors: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+//Goes through the layers for each setting, noting where it was found
+struct Loader<'a> {
+    //Only what was given on the command line, to tell it apart from the config file
+    args: &'a Config,
+    //The command line merged over the config file
+    merged: &'a Config,
+    entries: Vec<Entry>,
+    errors: Vec<String>,
+}
+
+//A setting's type, as read from an environment variable and shown when printing
+trait Value: Sized + Clone {
+    fn parse(value: &str) -> Result<Self, String>;
+    fn show(&self) -> String;
+}
+
+impl Value for String {
+    fn parse(value: &str) -> Result<Self, String> {
+        Ok(value.to_string())
+    }
+
+    fn show(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl Value for u16 {
+    fn parse(value: &str) -> Result<Self, String> {
+        value.trim().parse().map_err(|_| format!("[{}] is not a number from 0 to 65535", value))
+    }
+
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Value for u64 {
+    fn parse(value: &str) -> Result<Self, String> {
+        value.trim().parse().map_err(|_| format!("[{}] is not a whole number", value))
+    }
+
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Value for bool {
+    fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(true),
+            "false" | "0" | "no" => Ok(false),
+            _ => Err(format!("[{}] is not true or false", value)),
+        }
+    }
+
+    fn show(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<'a> Loader<'a> {
+    //The first layer that has the setting wins
+    fn value<T: Value>(&mut self, name: &'static str, env_var: &'static str, arg: Option<T>, file: Option<T>) -> Option<T> {
+        if let Some(value) = arg {
+            return Some(self.found(name, value, Source::CommandLine));
+        }
+
+        if let Ok(raw) = std::env::var(env_var) {
+            match T::parse(&raw) {
+                Ok(value) => return Some(self.found(name, value, Source::Environment(env_var))),
+                Err(e) => self.errors.push(format!("{} from environment var {}: {}.", name, env_var, e)),
+            }
+        }
+
+        if let Some(value) = file {
+            return Some(self.found(name, value, Source::ConfigFile));
+        }
+
+        self.entries.push(Entry { name, value: None, source: Source::Default });
+        None
+    }
+
+    fn value_or<T: Value>(&mut self, name: &'static str, env_var: &'static str, arg: Option<T>, file: Option<T>, default: T) -> T {
+        match self.value(name, env_var, arg, file) {
+            Some(value) => value,
+            None => self.found(name, default, Source::Default),
+        }
+    }
+
+    //Files LND makes can also be picked up from the working directory
+    fn lnd_file(&mut self, name: &'static str, env_var: &'static str, arg: Option<String>, file: Option<String>, local: &str, default: &str) -> String {
+        match self.value(name, env_var, arg, file) {
+            Some(value) => value,
+            None if Path::new(local).is_file() => self.found(name, local.to_string(), Source::WorkingDirectory),
+            None => self.found(name, default.to_string(), Source::Default),
+        }
+    }
+
+    fn found<T: Value>(&mut self, name: &'static str, value: T, source: Source) -> T {
+        self.entries.retain(|entry| entry.name != name);
+        self.entries.push(Entry { name, value: Some(value.show()), source });
+        value
+    }
+
+    //Show the value that's used rather than the one given, keeping where it came from
+    fn normalized<T: Value>(&mut self, name: &'static str, value: T) -> T {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.name == name) {
+            entry.value = Some(value.show());
+        }
+        value
+    }
+
+    fn check<T>(&mut self, name: &str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{}: {}", name, e));
+                None
+            }
+        }
+    }
+}
+
+impl Settings {
+    //Where to listen when listen_address is a unix domain socket
+    pub fn unix_socket_path(&self) -> Option<&str> {
+        self.listen_address.strip_prefix(UNIX_SOCKET_PREFIX)
+    }
+
+    pub fn socket_address(&self) -> Result<SocketAddr, String> {
+        socket_address(&self.listen_address, self.listen_port)
+    }
+
+    //Every setting with where it came from, secrets masked, in a form that could go in the config file
+    pub fn print(&self) -> String {
+        let width = self.entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+
+        self.entries.iter().map(|entry| {
+            let value = match &entry.value {
+                //A secret that's left empty is shown as empty, so it's clear it isn't set
+                Some(value) if SECRET_SETTINGS.contains(&entry.name) && value != "\"\"" => format!("{:?}", logging::REDACTED),
+                Some(value) => value.clone(),
+                None => "(not set)".to_string(),
+            };
+            format!("{:<width$} = {}  # {}", entry.name, value, entry.source, width = width)
+        }).collect::<Vec<String>>().join("\n")
+    }
+
+    //The names of the settings that have a different value in other
+    pub fn changed(&self, other: &Settings) -> Vec<&'static str> {
+        self.entries.iter()
+            .filter(|entry| !other.entries.iter().any(|theirs| theirs.name == entry.name && theirs.value == entry.value))
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    //Take the named settings from other, once they've been put to use without a restart.  The rest stay as they
+    //were, so they're still found to be changed next time
+    pub fn adopt(&mut self, names: &[&str], other: &Settings) {
+        for name in names {
+            match *name {
+                "password" => self.password = other.password.clone(),
+                "webroot_dir" => self.webroot_dir = other.webroot_dir.clone(),
+                "trusted_proxies" => {
+                    self.trusted_proxies = other.trusted_proxies.clone();
+                    self.trust_unix_socket = other.trust_unix_socket;
+                }
+                "log_level" => self.log_level = other.log_level.clone(),
+                "podcastindex_api_key" => self.podcastindex_api_key = other.podcastindex_api_key.clone(),
+                "podcastindex_api_secret" => self.podcastindex_api_secret = other.podcastindex_api_secret.clone(),
+                "podcastindex_timeout" => self.podcastindex_timeout = other.podcastindex_timeout,
+                "health_check_timeout" => self.health_check_timeout = other.health_check_timeout,
+                _ => continue,
+            }
+
+            let theirs = other.entries.iter().find(|entry| entry.name == *name);
+            if let (Some(entry), Some(theirs)) = (self.entries.iter_mut().find(|entry| entry.name == *name), theirs) {
+                *entry = theirs.clone();
+            }
+        }
+    }
+
+    //Log where each setting came from, for when it's not what was expected
+    pub fn log(&self) {
+        for entry in &self.entries {
+            match &entry.value {
+                Some(value) if !SECRET_SETTINGS.contains(&entry.name) => debug!(" - {}: {} ({})", entry.name, value, entry.source),
+                Some(_) => debug!(" - {}: set ({})", entry.name, entry.source),
+                None => debug!(" - {}: not set", entry.name),
+            }
+        }
+    }
+}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Work out every setting.  A port given as a command is taken as if it came from --listen-port.  Anything wrong with them is
+//collected in errors rather than stopping at the first
+pub fn load(args: &Config, merged: &Config, port: Option<u16>, default_log_level: &str) -> Settings {
+    let mut loader = Loader {
+        args,
+        merged,
+        entries: Vec::new(),
+        errors: Vec::new(),
+    };
+    let (args, merged) = (loader.args, loader.merged);
+
+    let database_dir = loader.value_or("database_dir", "HELIPAD_DATABASE_DIR", args.database_dir.clone(), merged.database_dir.clone(), STANDARD_DATABASE_DIR.to_string());
+    let listen_port = loader.value_or("listen_port", "HELIPAD_LISTEN_PORT", args.listen_port.or(port), merged.listen_port, STANDARD_LISTEN_PORT);
+    let listen_address = loader.value_or("listen_address", "HELIPAD_LISTEN_ADDRESS", args.listen_address.clone(), merged.listen_address.clone(), STANDARD_LISTEN_ADDRESS.to_string());
+    let base_path = loader.value_or("base_path", "HELIPAD_BASE_PATH", args.base_path.clone(), merged.base_path.clone(), "".to_string());
+    let webroot_dir = loader.value("webroot_dir", "HELIPAD_WEBROOT_DIR", args.webroot_dir.clone(), merged.webroot_dir.clone());
+    let trusted_proxies = loader.value_or("trusted_proxies", "HELIPAD_TRUSTED_PROXIES", args.trusted_proxies.clone(), merged.trusted_proxies.clone(), "".to_string());
+    let password = loader.value_or("password", "HELIPAD_PASSWORD", args.password.clone(), merged.password.clone(), "".to_string());
+    let macaroon = loader.lnd_file("macaroon", "LND_ADMINMACAROON", args.macaroon.clone(), merged.macaroon.clone(), LOCAL_LND_MACAROON, STANDARD_LND_MACAROON);
+    let cert = loader.lnd_file("cert", "LND_TLSCERT", args.cert.clone(), merged.cert.clone(), LOCAL_LND_TLSCERT, STANDARD_LND_TLSCERT);
+    let lnd_url = loader.value_or("lnd_url", "LND_URL", args.lnd_url.clone(), merged.lnd_url.clone(), STANDARD_LND_URL.to_string());
+    let keysend_addresses = loader.value_or("keysend_addresses", "HELIPAD_KEYSEND_ADDRESSES", args.keysend_addresses.clone(), merged.keysend_addresses.clone(), "".to_string());
+    let tls_cert = loader.value("tls_cert", "HELIPAD_TLS_CERT", args.tls_cert.clone(), merged.tls_cert.clone());
+    let tls_key = loader.value("tls_key", "HELIPAD_TLS_KEY", args.tls_key.clone(), merged.tls_key.clone());
+    let tls_self_signed = loader.value_or("tls_self_signed", "HELIPAD_TLS_SELF_SIGNED", args.tls_self_signed, merged.tls_self_signed, false);
+    let tls_redirect_port = loader.value("tls_redirect_port", "HELIPAD_TLS_REDIRECT_PORT", args.tls_redirect_port, merged.tls_redirect_port);
+    let cors_allowed_origins = loader.value_or("cors_allowed_origins", "HELIPAD_CORS_ALLOWED_ORIGINS", args.cors_allowed_origins.clone(), merged.cors_allowed_origins.clone(), STANDARD_CORS_ALLOWED_ORIGINS.to_string());
+    let cors_allowed_methods = loader.value_or("cors_allowed_methods", "HELIPAD_CORS_ALLOWED_METHODS", args.cors_allowed_methods.clone(), merged.cors_allowed_methods.clone(), STANDARD_CORS_ALLOWED_METHODS.to_string());
+    let cors_allow_credentials = loader.value_or("cors_allow_credentials", "HELIPAD_CORS_ALLOW_CREDENTIALS", args.cors_allow_credentials, merged.cors_allow_credentials, false);
+    let log_level = loader.value_or("log_level", "HELIPAD_LOG_LEVEL", args.log_level.clone(), merged.log_level.clone(), default_log_level.to_string());
+    let log_format = loader.value_or("log_format", "HELIPAD_LOG_FORMAT", args.log_format.clone(), merged.log_format.clone(), STANDARD_LOG_FORMAT.to_string());
+    let podcastindex_api_key = loader.value("podcastindex_api_key", "HELIPAD_PODCASTINDEX_API_KEY", args.podcastindex_api_key.clone(), merged.podcastindex_api_key.clone());
+    let podcastindex_api_secret = loader.value("podcastindex_api_secret", "HELIPAD_PODCASTINDEX_API_SECRET", args.podcastindex_api_secret.clone(), merged.podcastindex_api_secret.clone());
+    let podcastindex_timeout = loader.value_or("podcastindex_timeout", "HELIPAD_PODCASTINDEX_TIMEOUT", args.podcastindex_timeout, merged.podcastindex_timeout, STANDARD_PODCASTINDEX_TIMEOUT);
+    let health_check_timeout = loader.value_or("health_check_timeout", "HELIPAD_HEALTH_CHECK_TIMEOUT", args.health_check_timeout, merged.health_check_timeout, STANDARD_HEALTH_CHECK_TIMEOUT);
+
+    //Check them over
+    if !listen_address.starts_with(UNIX_SOCKET_PREFIX) {
+        loader.check("listen_address", socket_address(&listen_address, listen_port));
+    }
+
+    if let Some(dir) = webroot_dir.as_ref().filter(|dir| !Path::new(dir).is_dir()) {
+        loader.errors.push(format!("webroot_dir: [{}] is not a directory.", dir));
+    }
+
+    //Requests over the unix socket only count as coming from a proxy when "unix" is listed
+    let (unix, trusted_proxies): (Vec<&str>, Vec<&str>) = trusted_proxies.split(',')
+        .map(|proxy| proxy.trim())
+        .filter(|proxy| !proxy.is_empty())
+        .partition(|proxy| *proxy == UNIX_SOCKET_PROXY);
+    let trust_unix_socket = !unix.is_empty();
+
+    let trusted_proxies = trusted_proxies.into_iter()
+        .filter_map(|proxy| {
+            let ip = proxy.parse::<IpAddr>().map_err(|_| format!("[{}] is not an IP address.", proxy));
+            loader.check("trusted_proxies", ip)
+        })
+        .collect();
+
+    let lnd_url = match loader.check("lnd_url", normalize_lnd_url(&lnd_url)) {
+        Some(normalized) => loader.normalized("lnd_url", normalized),
+        None => lnd_url,
+    };
+
+    let keysend_addresses = lightning::parse_keysend_addresses(&keysend_addresses).map_err(|e| e.to_string());
+    let keysend_addresses = loader.check("keysend_addresses", keysend_addresses).unwrap_or_default();
+
+    match (&tls_cert, &tls_key) {
+        (Some(_), None) | (None, Some(_)) => {
+            loader.errors.push("tls_cert and tls_key: both a certificate and a key are needed to serve https.".to_string());
+        }
+        (None, None) if tls_self_signed => {
+            loader.errors.push("tls_self_signed: set tls_cert and tls_key to where the certificate and key should be made.".to_string());
+        }
+        _ => {}
+    }
+
+    let cors = cors::Cors::new(&cors_allowed_origins, &cors_allowed_methods, cors_allow_credentials).map_err(|e| e.to_string());
+    loader.check("cors", cors);
+
+    loader.check("log_level", logging::check_level(&log_level));
+    let log_format = loader.check("log_format", logging::parse_format(&log_format)).unwrap_or(logging::Format::Text);
+
+    if podcastindex_api_key.is_some() != podcastindex_api_secret.is_some() {
+        loader.errors.push("podcastindex_api_key and podcastindex_api_secret: both are needed to use a Podcast Index api key.".to_string());
+    }
+
+    for (name, timeout) in [("podcastindex_timeout", podcastindex_timeout), ("health_check_timeout", health_check_timeout)] {
+        if timeout == 0 {
+            loader.errors.push(format!("{}: must be at least 1 second.", name));
+        }
+    }
+
+    Settings {
+        database_dir,
+        listen_port,
+        listen_address,
+        base_path: router::normalize_base_path(&base_path),
+        webroot_dir,
+        trusted_proxies,
+        trust_unix_socket,
+        password,
+        macaroon,
+        cert,
+        lnd_url,
+        keysend_addresses,
+        tls_cert,
+        tls_key,
+        tls_self_signed,
+        tls_redirect_port,
+        cors_allowed_origins,
+        cors_allowed_methods,
+        cors_allow_credentials,
+        log_level,
+        log_format,
+        podcastindex_api_key,
+        podcastindex_api_secret,
+        podcastindex_timeout: Duration::from_secs(podcastindex_timeout),
+        health_check_timeout: Duration::from_secs(health_check_timeout),
+        errors: loader.errors,
+        entries: loader.entries,
+    }
+}
+
+//IPv6 addresses can be given with or without brackets
+fn socket_address(listen_address: &str, listen_port: u16) -> Result<SocketAddr, String> {
+    let ip = listen_address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+        .map_err(|_| format!("[{}] is not an IP address or unix:/path/to/socket.", listen_address))?;
+
+    Ok(SocketAddr::new(ip, listen_port))
+}
+
+//LND's grpc api only takes TLS connections.  The scheme can be left off, wherever the url comes from
+fn normalize_lnd_url(lnd_url: &str) -> Result<String, String> {
+    let lnd_url = lnd_url.trim().trim_end_matches('/');
+
+    match lnd_url.split_once("://") {
+        None => Ok(format!("https://{}", lnd_url)),
+        Some(("https", host)) if !host.is_empty() => Ok(lnd_url.to_string()),
+        Some(("https", _)) => Err(format!("[{}] is missing the host and port of LND's grpc api.", lnd_url)),
+        Some(_) => Err(format!("[{}] has to be an https:// url, since LND's grpc api only takes TLS connections.", lnd_url)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //The command line on its own, and merged over a config file with these contents
+    fn configs(name: &str, args: &[&str], file: &str) -> (Config, Config) {
+        let path = std::env::temp_dir().join(format!("helipad-test-{}-{}.conf", name, std::process::id()));
+        std::fs::write(&path, file).unwrap();
+
+        let args: Vec<String> = std::iter::once("helipad").chain(args.iter().copied()).map(str::to_string).collect();
+        let (arg_config, _) = Config::custom_args_and_optional_files(args.clone(), std::iter::empty::<&str>()).unwrap();
+        let (merged, _) = Config::custom_args_and_optional_files(args, std::iter::once(&path)).unwrap();
+
+        let _ = std::fs::remove_file(&path);
+        (arg_config, merged)
+    }
+
+    fn printed<'a>(printed: &'a str, name: &str) -> &'a str {
+        printed.lines()
+            .find(|line| line.split_whitespace().next() == Some(name))
+            .unwrap_or_else(|| panic!("{} wasn't printed", name))
+    }
+
+    //The only test that sets environment variables, since they're shared with every other test running
+    #[test]
+    fn command_line_beats_environment_beats_file_beats_default() {
+        std::env::set_var("HELIPAD_DATABASE_DIR", "env.db");
+        std::env::set_var("HELIPAD_LISTEN_ADDRESS", "127.0.0.2");
+        std::env::set_var("HELIPAD_LISTEN_PORT", "not a port");
+
+        let (args, merged) = configs(
+            "precedence",
+            &["--database-dir", "cli.db"],
+            "database_dir = \"file.db\"\nlisten_address = \"127.0.0.3\"\nlisten_port = 2113\nbase_path = \"/file/\"\n",
+        );
+        let settings = load(&args, &merged, None, "warn");
+
+        std::env::remove_var("HELIPAD_DATABASE_DIR");
+        std::env::remove_var("HELIPAD_LISTEN_ADDRESS");
+        std::env::remove_var("HELIPAD_LISTEN_PORT");
+
+        assert_eq!(settings.database_dir, "cli.db");
+        assert_eq!(settings.listen_address, "127.0.0.2");
+        assert_eq!(settings.listen_port, 2113);
+        assert_eq!(settings.base_path, "/file");
+        assert_eq!(settings.cors_allowed_methods, STANDARD_CORS_ALLOWED_METHODS);
+
+        //A bad environment variable is reported rather than quietly skipped
+        assert!(settings.errors.iter().any(|e| e.contains("HELIPAD_LISTEN_PORT")), "{:?}", settings.errors);
+
+        let printed_settings = settings.print();
+        assert!(printed(&printed_settings, "database_dir").ends_with("# command line"));
+        assert!(printed(&printed_settings, "listen_address").ends_with("# env HELIPAD_LISTEN_ADDRESS"));
+        assert!(printed(&printed_settings, "listen_port").ends_with(&format!("# config file {}", CONFIG_FILE)));
+        assert!(printed(&printed_settings, "cors_allowed_methods").ends_with("# default"));
+    }
+
+    #[test]
+    fn printing_masks_secrets() {
+        let (args, merged) = configs(
+            "print",
+            &["--podcastindex-api-key", "pi-key", "--lnd-url", "lnd.example:10009"],
+            "podcastindex_api_secret = \"pi-secret\"\n",
+        );
+        let settings = load(&args, &merged, None, "warn");
+        let printed_settings = settings.print();
+
+        for secret in ["pi-key", "pi-secret"].iter() {
+            assert!(!printed_settings.contains(secret), "{} was printed", secret);
+        }
+        assert!(printed(&printed_settings, "podcastindex_api_key").contains(logging::REDACTED));
+        assert!(printed(&printed_settings, "podcastindex_api_secret").contains(logging::REDACTED));
+        assert!(printed(&printed_settings, "macaroon").contains(logging::REDACTED));
+
+        //The url shown is the one that's used, and unset secrets show as empty rather than masked
+        assert!(printed(&printed_settings, "lnd_url").contains("\"https://lnd.example:10009\""));
+        assert!(printed(&printed_settings, "password").contains("= \"\""));
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+
+//How long requests and the poller get to finish up once asked to stop.  Docker waits 10 seconds by default
+//before killing us, so the compose file gives it longer
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
+
+
+//Structs ----------------------------------------------------------------------------------------------------
+//Tells everything that's running to stop, then waits for it to finish
+pub struct Shutdown {
+    notify: Arc<watch::Sender<bool>>,
+    //Each Watcher holds a sender, so the receiver closes once they've all been dropped
+    done_tx: mpsc::Sender<()>,
+    done_rx: mpsc::Receiver<()>,
+}
+
+//Handed to each task shutdown should wait for.  It's done once this is dropped
+#[derive(Clone, Debug)]
+pub struct Watcher {
+    receiver: watch::Receiver<bool>,
+    _done: mpsc::Sender<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (notify, _) = watch::channel(false);
+        let (done_tx, done_rx) = mpsc::channel(1);
+
+        Shutdown {
+            notify: Arc::new(notify),
+            done_tx,
+            done_rx,
+        }
+    }
+
+    //Start shutting down on SIGTERM (docker stop) or SIGINT (ctrl-c)
+    pub fn listen_for_signals(&self) {
+        let notify = self.notify.clone();
+
+        tokio::spawn(async move {
+            let (mut terminate, mut interrupt) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+                (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Unable to listen for shutdown signals: {}", e);
+                    return;
+                }
+            };
+
+            tokio::select! {
+                _ = terminate.recv() => info!("Got SIGTERM, shutting down..."),
+                _ = interrupt.recv() => info!("Got SIGINT, shutting down..."),
+            }
+
+            let _ = notify.send(true);
+        });
+    }
+
+    pub fn watcher(&self) -> Watcher {
+        Watcher {
+            receiver: self.notify.subscribe(),
+            _done: self.done_tx.clone(),
+        }
+    }
+
+    //Resolves once we've been asked to stop
+    pub async fn requested(&self) {
+        let mut receiver = self.notify.subscribe();
+        let _ = receiver.wait_for(|stopping| *stopping).await;
+    }
+
+    //Wait to be asked to stop, then for every Watcher to be dropped.  False if they didn't all finish in time
+    pub async fn finish(self, timeout: Duration) -> bool {
+        self.requested().await;
+
+        let Shutdown { done_tx, mut done_rx, .. } = self;
+        drop(done_tx);
+
+        tokio::time::timeout(timeout, done_rx.recv()).await.is_ok()
+    }
+}
+
+impl Watcher {
+    pub fn is_stopping(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    //Resolves once we've been asked to stop
+    pub async fn stopping(&mut self) {
+        let _ = self.receiver.wait_for(|stopping| *stopping).await;
+    }
+}
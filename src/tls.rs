@@ -0,0 +1,150 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{BufReader, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+//Names the self-signed certificate is made for
+const SELF_SIGNED_NAMES: [&str; 2] = ["localhost", "helipad.local"];
+
+
+//Structs and Enums ------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct TlsError(String);
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TLS error: {}", self.0)
+    }
+}
+
+impl Error for TlsError {}
+
+
+//Functions --------------------------------------------------------------------------------------------------
+//Build the TLS acceptor from the certificate and key files, first making a self-signed pair
+//if asked to and they don't exist yet
+pub fn load_acceptor(cert_path: &str, key_path: &str, self_signed: bool) -> Result<TlsAcceptor, Box<dyn Error>> {
+    if self_signed && !Path::new(cert_path).exists() && !Path::new(key_path).exists() {
+        generate_self_signed(cert_path, key_path)?;
+    }
+
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_certs(cert_path: &str) -> Result<Vec<Certificate>, Box<dyn Error>> {
+    let mut reader = BufReader::new(fs::File::open(cert_path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    if certs.is_empty() {
+        return Err(Box::new(TlsError(format!("No certificates found in: [{}]", cert_path))));
+    }
+
+    Ok(certs)
+}
+
+//Accepts PKCS#8, RSA and EC private keys
+fn load_private_key(key_path: &str) -> Result<PrivateKey, Box<dyn Error>> {
+    let mut reader = BufReader::new(fs::File::open(key_path)?);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key)) => return Ok(PrivateKey(key)),
+            Some(rustls_pemfile::Item::RSAKey(key)) => return Ok(PrivateKey(key)),
+            Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => break,
+        }
+    }
+
+    Err(Box::new(TlsError(format!("No private key found in: [{}]", key_path))))
+}
+
+fn generate_self_signed(cert_path: &str, key_path: &str) -> Result<(), Box<dyn Error>> {
+    info!("Generating self-signed TLS certificate: [{}]", cert_path);
+
+    let names: Vec<String> = SELF_SIGNED_NAMES.iter().map(|name| name.to_string()).collect();
+    let cert = rcgen::generate_simple_self_signed(names)?;
+
+    fs::write(cert_path, cert.serialize_pem()?)?;
+
+    //The private key is only ever readable by us, from the moment the file is made
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(key_path)?.write_all(cert.serialize_private_key_pem().as_bytes())?;
+
+    Ok(())
+}
+
+//Answer plain http requests with a redirect to the same place over https
+pub fn redirect_to_https(addr: SocketAddr, https_port: String) -> Result<impl std::future::Future<Output = ()>, Box<dyn Error>> {
+    let make_service = make_service_fn(move |_conn| {
+        let https_port = https_port.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let location = https_location(&req, &https_port);
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .status(StatusCode::MOVED_PERMANENTLY)
+                            .header("Location", location)
+                            .body(Body::empty())
+                            .unwrap()
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+
+    Ok(async move {
+        if let Err(e) = server.await {
+            error!("HTTPS redirect server error: {}", e);
+        }
+    })
+}
+
+fn https_location(req: &Request<Body>, https_port: &str) -> String {
+    let host = req.headers()
+        .get(hyper::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+
+    //Swap whatever port the request came in on for the https one
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !hostname.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => hostname,
+        _ => host,
+    };
+
+    let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+
+    if https_port == "443" {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, https_port, path)
+    }
+}
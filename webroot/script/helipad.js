@@ -1,8 +1,10 @@
 $(document).ready(function () {
     let messages = $('div.mesgs');
     let inbox = messages.find('div.msg_history');
-    let appIconUrlBase = '/image?name=';
-    let pewAudioFile = '/pew.mp3';
+    //Everything is served under this path when running behind a reverse proxy
+    const basePath = $('meta[name="base-path"]').attr('content') || '';
+    let appIconUrlBase = basePath + '/image?name=';
+    let pewAudioFile = basePath + '/pew.mp3';
     let pewAudio = new Audio(pewAudioFile);
     let appList = {};
     let numerologyList = [];
@@ -14,11 +16,12 @@ $(document).ready(function () {
     let nodeInfo = null;
 
     let config = {
-        'listUrl': '/api/v1/boosts',
-        'indexUrl': '/api/v1/index',
+        'listUrl': basePath + '/api/v1/boosts',
+        'indexUrl': basePath + '/api/v1/index',
         'singularName': 'boost',
         'pluralName': 'boosts',
         'effects': true,
+        'destination': new URLSearchParams(window.location.search).get('destination') || '',
     }
 
     //Get a boost list starting at a particular invoice index
@@ -79,6 +82,10 @@ $(document).ready(function () {
             params.old = true;
         }
 
+        if (config.destination) {
+            params.destination = config.destination;
+        }
+
         let url = config.listUrl + '?' + $.param(params);
 
         $.ajax({
@@ -88,7 +95,7 @@ $(document).ready(function () {
             dataType: "json",
             error: function (xhr) {
                 if (xhr.status === 403) {
-                    window.location.href = "/login";
+                    window.location.href = basePath + "/login";
                 }
             },
             success: function (data) {
@@ -313,7 +320,12 @@ $(document).ready(function () {
                     endex = 1;
                 }
 
-                $('span.csv a').attr('href', '/csv?list=' + list + '&index=' + csvindex + '&count=' + bcount + '&old=true' + '&end=' + endex);
+                let csvdest = '';
+                if (config.destination) {
+                    csvdest = '&destination=' + encodeURIComponent(config.destination);
+                }
+
+                $('span.csv a').attr('href', basePath + '/csv?list=' + list + '&index=' + csvindex + '&count=' + bcount + '&old=true' + '&end=' + endex + csvdest);
 
                 //Load more link
                 if ($('div.outgoing_msg').length > 0 && $('div.loadmore').length == 0 && (boostIndex > 1 || noIndex)) {
@@ -363,13 +375,13 @@ $(document).ready(function () {
     function getBalance(init) {
         //Get the current boost index number
         $.ajax({
-            url: "/api/v1/balance",
+            url: basePath + "/api/v1/balance",
             type: "GET",
             contentType: "application/json; charset=utf-8",
             dataType: "json",
             error: function (xhr) {
                 if (xhr.status === 403) {
-                    window.location.href = "/login";
+                    window.location.href = basePath + "/login";
                 }
             },
             success: function (data) {
@@ -399,7 +411,7 @@ $(document).ready(function () {
 
     //Get the current node alias and pubkey
     async function getNodeInfo() {
-        nodeInfo = await $.get(`/api/v1/node_info`);
+        nodeInfo = await $.get(`${basePath}/api/v1/node_info`);
     }
 
     //Refresh the timestatmps of all the boosts on the list
@@ -421,7 +433,7 @@ $(document).ready(function () {
             dataType: "json",
             error: function (xhr) {
                 if (xhr.status === 403) {
-                    window.location.href = "/login";
+                    window.location.href = basePath + "/login";
                 }
             },
             success: function (data) {
@@ -436,10 +448,47 @@ $(document).ready(function () {
         });
     }
 
+    //Get the destinations (shows) boosts can be routed to and offer them as a filter
+    async function getDestinations() {
+        if (config.pluralName != 'boosts' && config.pluralName != 'streams') {
+            return;
+        }
+
+        const destinations = await $.get(basePath + '/api/v1/destinations');
+
+        if (!destinations.length) {
+            return;
+        }
+
+        const $select = $('<select class="destinationFilter" title="Filter by show"></select>');
+        $select.append($('<option value="">All shows</option>'));
+
+        for (let dest of destinations) {
+            $select.append($('<option></option>').val(dest.name).text(dest.name));
+        }
+
+        $select.val(config.destination);
+
+        $select.on('change', function () {
+            const params = new URLSearchParams(window.location.search);
+
+            if (this.value) {
+                params.set('destination', this.value);
+            }
+            else {
+                params.delete('destination');
+            }
+
+            window.location.search = params.toString();
+        });
+
+        $('div.rightHeader').prepend($select);
+    }
+
     //Get the defined list of apps
     async function getAppList() {
         appList = await $.ajax({
-            url: "/apps.json",
+            url: basePath + "/apps.json",
             type: "GET",
             contentType: "application/json; charset=utf-8",
             dataType: "json"
@@ -451,7 +500,7 @@ $(document).ready(function () {
     //Get the defined numerology
     async function getNumerologyList() {
         numerologyList = await $.ajax({
-            url: "/numerology.json",
+            url: basePath + "/numerology.json",
             type: "GET",
             contentType: "application/json; charset=utf-8",
             dataType: "json"
@@ -672,7 +721,7 @@ $(document).ready(function () {
 
                 try {
                     await sendBrowserReplyBoost(boost, reply);
-                    await $.post(`/api/v1/mark_replied`, { 'index': index });
+                    await $.post(`${basePath}/api/v1/mark_replied`, { 'index': index });
                     renderReplyButton(index, true);
                     setTimeout(() => $dialog.modal('hide'), 1000);
                 }
@@ -682,7 +731,7 @@ $(document).ready(function () {
                 }
             }
             else { //node boost
-                $.post(`/api/v1/reply`, $form.serialize()).then(result => {
+                $.post(`${basePath}/api/v1/reply`, $form.serialize()).then(result => {
                     if (!result.success) {
                         $('#send-reply').removeClass('loading').prop('disabled', false);
                         return alert(result.message);
@@ -775,6 +824,7 @@ $(document).ready(function () {
         //Get starting balance and index number
         getBalance(true);
         await getNodeInfo();
+        await getDestinations();
         await getAppList();
         await getNumerologyList();
         renderBoostInfo();
@@ -782,21 +832,21 @@ $(document).ready(function () {
     }
 
     function setConfig() {
-        const pathname = window.location.pathname;
+        const pathname = window.location.pathname.substring(basePath.length) || "/";
 
         if (pathname == "/") {
-            config.listUrl = '/api/v1/boosts';
+            config.listUrl = basePath + '/api/v1/boosts';
             config.singularName = 'boost';
             config.pluralName = 'boosts';
         }
         else if (pathname == "/streams") {
-            config.listUrl = '/api/v1/streams';
+            config.listUrl = basePath + '/api/v1/streams';
             config.singularName = 'stream';
             config.pluralName = 'streams';
         }
         else if (pathname == "/sent") {
-            config.listUrl = '/api/v1/sent';
-            config.indexUrl = '/api/v1/sent_index';
+            config.listUrl = basePath + '/api/v1/sent';
+            config.indexUrl = basePath + '/api/v1/sent_index';
             config.singularName = 'sent boost';
             config.pluralName = 'sent boosts';
             config.effects = false;